@@ -16,11 +16,15 @@
 
 #pragma once
 
+#include <stddef.h>
+#include <stdint.h>
 #include <sys/socket.h>
 
 extern "C" {
 
 struct AIBinder;
+struct ARpcServer;
+struct ARpcSession;
 
 // Starts an RPC server on a given port and a given root IBinder object.
 // This function sets up the server and joins before returning.
@@ -51,4 +55,65 @@ AIBinder* RpcClient(unsigned int cid, unsigned int port);
 // the requestFd function.
 AIBinder* RpcPreconnectedClient(int (*requestFd)(void* param), void* param);
 
+// Creates an RPC server listening on a Unix domain socket bound to the given
+// path, with the given root IBinder object. On success, returns OK and sets
+// outServer to the new server, which must be deleted with ARpcServer_delete.
+// Otherwise returns an error status_t.
+int32_t ARpcServer_newUnixDomain(AIBinder* service, const char* path, ARpcServer** outServer);
+
+// Creates an RPC server listening on the given vsock port, with the given root
+// IBinder object. See ARpcServer_newUnixDomain.
+int32_t ARpcServer_newVsock(AIBinder* service, unsigned int port, ARpcServer** outServer);
+
+// Sets the maximum number of threads that the server uses to handle incoming
+// transactions. This must be called before the server is started.
+void ARpcServer_setMaxThreads(ARpcServer* server, size_t threads);
+
+// Starts accepting connections on a background thread.
+void ARpcServer_start(ARpcServer* server);
+
+// Accepts connections on the calling thread until the server is shut down.
+void ARpcServer_join(ARpcServer* server);
+
+// Shuts down the server and any open sessions, waiting for the thread that
+// accepts connections to stop. Returns false if the server could not be shut
+// down.
+bool ARpcServer_shutdown(ARpcServer* server);
+
+// Shuts down the server if it is still running, and deletes it.
+void ARpcServer_delete(ARpcServer* server);
+
+// Creates a new RPC session, which must be deleted with ARpcSession_delete.
+ARpcSession* ARpcSession_new();
+
+// Sets the maximum number of threads that the session uses to handle
+// transactions sent by the server. This must be called before the session is
+// connected.
+void ARpcSession_setMaxIncomingThreads(ARpcSession* session, size_t threads);
+
+// Sets the maximum number of connections that the session uses to send
+// transactions to the server. This must be called before the session is
+// connected.
+void ARpcSession_setMaxOutgoingThreads(ARpcSession* session, size_t threads);
+
+// Connects the session to an RPC server listening on a Unix domain socket
+// bound to the given path. On success, returns OK and sets outRoot to a new
+// strong reference to the root object of the server. Otherwise returns an
+// error status_t.
+int32_t ARpcSession_setupUnixDomainClient(ARpcSession* session, const char* path,
+                                          AIBinder** outRoot);
+
+// Connects the session to an RPC server listening on the given vsock CID and
+// port. See ARpcSession_setupUnixDomainClient.
+int32_t ARpcSession_setupVsockClient(ARpcSession* session, unsigned int cid, unsigned int port,
+                                     AIBinder** outRoot);
+
+// Shuts down the session, waiting for its incoming threads to stop. Returns
+// false if the session could not be shut down.
+bool ARpcSession_shutdown(ARpcSession* session);
+
+// Deletes the session. Binders received from the session keep it alive until
+// they are released.
+void ARpcSession_delete(ARpcSession* session);
+
 }
@@ -24,10 +24,19 @@
 using android::OK;
 using android::RpcServer;
 using android::RpcSession;
+using android::sp;
 using android::status_t;
 using android::statusToString;
 using android::base::unique_fd;
 
+struct ARpcServer {
+    sp<RpcServer> server;
+};
+
+struct ARpcSession {
+    sp<RpcSession> session;
+};
+
 extern "C" {
 
 bool RunRpcServerWithFactory(AIBinder* (*factory)(unsigned int cid, void* context),
@@ -93,4 +102,91 @@ AIBinder* RpcPreconnectedClient(int (*requestFd)(void* param), void* param) {
     }
     return AIBinder_fromPlatformBinder(session->getRootObject());
 }
+
+status_t ARpcServer_newUnixDomain(AIBinder* service, const char* path, ARpcServer** outServer) {
+    auto server = RpcServer::make();
+    if (status_t status = server->setupUnixDomainServer(path); status != OK) {
+        LOG(ERROR) << "Failed to set up Unix Domain RPC server with path " << path
+                   << " error: " << statusToString(status).c_str();
+        return status;
+    }
+    server->setRootObject(AIBinder_toPlatformBinder(service));
+    *outServer = new ARpcServer{server};
+    return OK;
+}
+
+status_t ARpcServer_newVsock(AIBinder* service, unsigned int port, ARpcServer** outServer) {
+    auto server = RpcServer::make();
+    if (status_t status = server->setupVsockServer(port); status != OK) {
+        LOG(ERROR) << "Failed to set up vsock server with port " << port
+                   << " error: " << statusToString(status).c_str();
+        return status;
+    }
+    server->setRootObject(AIBinder_toPlatformBinder(service));
+    *outServer = new ARpcServer{server};
+    return OK;
+}
+
+void ARpcServer_setMaxThreads(ARpcServer* server, size_t threads) {
+    server->server->setMaxThreads(threads);
+}
+
+void ARpcServer_start(ARpcServer* server) {
+    server->server->start();
+}
+
+void ARpcServer_join(ARpcServer* server) {
+    server->server->join();
+}
+
+bool ARpcServer_shutdown(ARpcServer* server) {
+    return server->server->shutdown();
+}
+
+void ARpcServer_delete(ARpcServer* server) {
+    (void)server->server->shutdown();
+    delete server;
+}
+
+ARpcSession* ARpcSession_new() {
+    return new ARpcSession{RpcSession::make()};
+}
+
+void ARpcSession_setMaxIncomingThreads(ARpcSession* session, size_t threads) {
+    session->session->setMaxIncomingThreads(threads);
+}
+
+void ARpcSession_setMaxOutgoingThreads(ARpcSession* session, size_t threads) {
+    session->session->setMaxOutgoingThreads(threads);
+}
+
+status_t ARpcSession_setupUnixDomainClient(ARpcSession* session, const char* path,
+                                           AIBinder** outRoot) {
+    if (status_t status = session->session->setupUnixDomainClient(path); status != OK) {
+        LOG(ERROR) << "Failed to set up Unix Domain RPC client with path " << path
+                   << " error: " << statusToString(status).c_str();
+        return status;
+    }
+    *outRoot = AIBinder_fromPlatformBinder(session->session->getRootObject());
+    return OK;
+}
+
+status_t ARpcSession_setupVsockClient(ARpcSession* session, unsigned int cid, unsigned int port,
+                                      AIBinder** outRoot) {
+    if (status_t status = session->session->setupVsockClient(cid, port); status != OK) {
+        LOG(ERROR) << "Failed to set up vsock client with CID " << cid << " and port " << port
+                   << " error: " << statusToString(status).c_str();
+        return status;
+    }
+    *outRoot = AIBinder_fromPlatformBinder(session->session->getRootObject());
+    return OK;
+}
+
+bool ARpcSession_shutdown(ARpcSession* session) {
+    return session->session->shutdownAndWait(true);
+}
+
+void ARpcSession_delete(ARpcSession* session) {
+    delete session;
+}
 }
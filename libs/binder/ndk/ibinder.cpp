@@ -190,9 +190,14 @@ status_t ABBinder::onTransact(transaction_code_t code, const Parcel& data, Parce
         const AParcel in = AParcel::readOnly(this, &data);
         AParcel out = AParcel(this, reply, false /*owns*/);
 
-        binder_status_t status = getClass()->onTransact(this, code, &in, &out);
+        binder_status_t status = getClass()->onTransactWithFlags != nullptr
+                                         ? getClass()->onTransactWithFlags(this, code, &in, &out,
+                                                                           flags)
+                                         : getClass()->onTransact(this, code, &in, &out);
         return PruneStatusT(status);
-    } else if (code == SHELL_COMMAND_TRANSACTION && getClass()->handleShellCommand != nullptr) {
+    } else if (code == SHELL_COMMAND_TRANSACTION &&
+               (getClass()->handleShellCommand != nullptr ||
+                getClass()->handleShellCommandWithCallback != nullptr)) {
         int in = data.readFileDescriptor();
         int out = data.readFileDescriptor();
         int err = data.readFileDescriptor();
@@ -205,7 +210,7 @@ status_t ABBinder::onTransact(transaction_code_t code, const Parcel& data, Parce
             utf8Pointers.push_back(utf8Args[i].c_str());
         }
 
-        data.readStrongBinder();  // skip over the IShellCallback
+        sp<IBinder> shellCallback = data.readStrongBinder();
         sp<IResultReceiver> resultReceiver = IResultReceiver::asInterface(data.readStrongBinder());
 
         // Shell commands should only be callable by ADB.
@@ -225,8 +230,17 @@ status_t ABBinder::onTransact(transaction_code_t code, const Parcel& data, Parce
             return STATUS_BAD_VALUE;
         }
 
-        binder_status_t status = getClass()->handleShellCommand(
-                this, in, out, err, utf8Pointers.data(), utf8Pointers.size());
+        binder_status_t status;
+        if (getClass()->handleShellCommandWithCallback != nullptr) {
+            sp<AIBinder> ndkShellCallback = ABpBinder::lookupOrCreateFromBinder(shellCallback);
+            status = getClass()->handleShellCommandWithCallback(this, in, out, err,
+                                                                utf8Pointers.data(),
+                                                                utf8Pointers.size(),
+                                                                ndkShellCallback.get());
+        } else {
+            status = getClass()->handleShellCommand(this, in, out, err, utf8Pointers.data(),
+                                                    utf8Pointers.size());
+        }
         if (resultReceiver != nullptr) {
             resultReceiver->send(status);
         }
@@ -785,6 +799,26 @@ const char* AIBinder_getCallingSid() {
     return ::android::IPCThreadState::self()->getCallingSid();
 }
 
+bool AIBinder_isRequestingSid(AIBinder* binder) {
+    ABBinder* localBinder = binder->asABBinder();
+    return localBinder != nullptr && localBinder->isRequestingSid();
+}
+
+void AIBinder_Class_setOnTransactWithFlags(AIBinder_Class* clazz,
+                                           AIBinder_Class_onTransactWithFlags onTransact) {
+    CHECK(clazz != nullptr) << "setOnTransactWithFlags requires non-null clazz";
+
+    // this is required to be called before instances are instantiated
+    clazz->onTransactWithFlags = onTransact;
+}
+
+void AIBinder_Class_setHandleShellCommandWithCallback(
+        AIBinder_Class* clazz, AIBinder_handleShellCommandWithCallback handleShellCommand) {
+    CHECK(clazz != nullptr) << "setHandleShellCommandWithCallback requires non-null clazz";
+
+    clazz->handleShellCommandWithCallback = handleShellCommand;
+}
+
 android::sp<android::IBinder> AIBinder_toPlatformBinder(AIBinder* binder) {
     if (binder == nullptr) return nullptr;
     return binder->getBinder();
@@ -808,3 +842,46 @@ void AIBinder_setInheritRt(AIBinder* binder, bool inheritRt) {
 
     localBinder->setInheritRt(inheritRt);
 }
+
+binder_status_t AIBinder_getRemoteDescriptor(AIBinder* binder, void* stringData,
+                                             AParcel_stringAllocator allocator) {
+    if (binder == nullptr || allocator == nullptr) {
+        return STATUS_UNEXPECTED_NULL;
+    }
+
+    sp<IBinder> platformBinder = binder->getBinder();
+    const String16& descriptor = platformBinder->getInterfaceDescriptor();
+    // BpBinder returns an empty descriptor if the transaction fails, so check
+    // whether the remote binder is still there.
+    if (descriptor.size() == 0) {
+        status_t status = platformBinder->pingBinder();
+        if (status != android::OK) return PruneStatusT(status);
+    }
+
+    String8 utf8(descriptor);
+    const size_t length = utf8.size() + 1;  // includes null-terminator
+    char* buffer = nullptr;
+    if (!allocator(stringData, static_cast<int32_t>(length), &buffer) || buffer == nullptr) {
+        return STATUS_NO_MEMORY;
+    }
+    memcpy(buffer, utf8.c_str(), length);
+    return STATUS_OK;
+}
+
+binder_status_t AIBinder_getDebugPid(AIBinder* binder, pid_t* outPid) {
+    if (binder == nullptr || outPid == nullptr) {
+        return STATUS_UNEXPECTED_NULL;
+    }
+
+    return PruneStatusT(binder->getBinder()->getDebugPid(outPid));
+}
+
+binder_status_t AIBinder_notifySyspropsChanged(AIBinder* binder) {
+    if (binder == nullptr) {
+        return STATUS_UNEXPECTED_NULL;
+    }
+
+    Parcel data, reply;
+    return PruneStatusT(
+            binder->getBinder()->transact(IBinder::SYSPROPS_TRANSACTION, data, &reply, 0));
+}
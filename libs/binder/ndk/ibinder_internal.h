@@ -17,6 +17,7 @@
 #pragma once
 
 #include <android/binder_ibinder.h>
+#include <android/binder_ibinder_platform.h>
 #include <android/binder_shell.h>
 #include "ibinder_internal.h"
 
@@ -127,6 +128,8 @@ struct AIBinder_Class {
     // optional methods for a class
     AIBinder_onDump onDump = nullptr;
     AIBinder_handleShellCommand handleShellCommand = nullptr;
+    AIBinder_handleShellCommandWithCallback handleShellCommandWithCallback = nullptr;
+    AIBinder_Class_onTransactWithFlags onTransactWithFlags = nullptr;
 
    private:
     // Copy of the raw char string for when we don't have to return UTF-16
@@ -55,6 +55,48 @@ __attribute__((weak)) void AIBinder_setRequestingSid(AIBinder* binder, bool requ
  */
 __attribute__((weak, warn_unused_result)) const char* AIBinder_getCallingSid() __INTRODUCED_IN(31);
 
+/**
+ * Whether calls to AIBinder_getCallingSid work for transactions on this binder,
+ * see AIBinder_setRequestingSid.
+ *
+ * \param binder local server binder
+ *
+ * \return whether the binder requests security contexts. This is always false
+ * for remote binders.
+ */
+bool AIBinder_isRequestingSid(AIBinder* binder) __INTRODUCED_IN(__ANDROID_API_FUTURE__);
+
+/**
+ * Like AIBinder_Class_onTransact, but also given the flags of the transaction,
+ * such as FLAG_ONEWAY and FLAG_CLEAR_BUF.
+ *
+ * \param binder the object being transacted on.
+ * \param code implementation-specific code representing which transaction should be taken.
+ * \param in the implementation-specific input data to this transaction.
+ * \param out the implementation-specific output data to this transaction.
+ * \param flags the flags the transaction was sent with.
+ *
+ * \return see AIBinder_Class_onTransact.
+ */
+typedef binder_status_t (*AIBinder_Class_onTransactWithFlags)(AIBinder* binder,
+                                                              transaction_code_t code,
+                                                              const AParcel* in, AParcel* out,
+                                                              binder_flags_t flags);
+
+/**
+ * Set a transaction handler which is given the flags of each transaction. When
+ * this is set, it is called instead of the AIBinder_Class_onTransact handler
+ * passed to AIBinder_Class_define.
+ *
+ * This must be called before any instance of the class is created.
+ *
+ * \param clazz class which should use this transaction handler.
+ * \param onTransact function to call for incoming transactions.
+ */
+void AIBinder_Class_setOnTransactWithFlags(AIBinder_Class* clazz,
+                                           AIBinder_Class_onTransactWithFlags onTransact)
+        __INTRODUCED_IN(__ANDROID_API_FUTURE__);
+
 /**
  * Sets a minimum scheduler policy for all transactions coming into this
  * AIBinder.
@@ -80,4 +122,45 @@ void AIBinder_setMinSchedulerPolicy(AIBinder* binder, int policy, int priority)
  */
 void AIBinder_setInheritRt(AIBinder* binder, bool inheritRt) __INTRODUCED_IN(33);
 
+/**
+ * Gets the interface descriptor of a binder. For a remote binder, this is
+ * requested from the remote process with INTERFACE_TRANSACTION, so it works for
+ * binders which aren't associated with a class.
+ *
+ * \param binder the binder to get the interface descriptor of.
+ * \param stringData some external representation of a string, passed to the
+ * allocator.
+ * \param allocator allocates the string, see AParcel_stringAllocator. It is
+ * given the length of the descriptor in UTF-8, including a null-terminator.
+ *
+ * \return STATUS_OK on success, or the error of the transaction. STATUS_NO_MEMORY
+ * if the allocator fails.
+ */
+binder_status_t AIBinder_getRemoteDescriptor(AIBinder* binder, void* stringData,
+                                             AParcel_stringAllocator allocator)
+        __INTRODUCED_IN(__ANDROID_API_FUTURE__);
+
+/**
+ * Gets the PID of the process hosting a binder, with DEBUG_PID_TRANSACTION. This
+ * is meant for debugging; the PID may be reused once the process dies.
+ *
+ * \param binder the binder to get the hosting process of.
+ * \param outPid the PID of the process hosting the binder.
+ *
+ * \return STATUS_OK on success, or the error of the transaction.
+ */
+binder_status_t AIBinder_getDebugPid(AIBinder* binder, pid_t* outPid)
+        __INTRODUCED_IN(__ANDROID_API_FUTURE__);
+
+/**
+ * Notifies the process hosting a binder that system properties changed, with
+ * SYSPROPS_TRANSACTION, so that it reloads them.
+ *
+ * \param binder a binder in the process to notify.
+ *
+ * \return STATUS_OK on success, or the error of the transaction.
+ */
+binder_status_t AIBinder_notifySyspropsChanged(AIBinder* binder)
+        __INTRODUCED_IN(__ANDROID_API_FUTURE__);
+
 __END_DECLS
@@ -51,4 +51,40 @@ typedef binder_status_t (*AIBinder_handleShellCommand)(AIBinder* binder, int in,
 __attribute__((weak)) void AIBinder_Class_setHandleShellCommand(
         AIBinder_Class* clazz, AIBinder_handleShellCommand handleShellCommand) __INTRODUCED_IN(30);
 
+/**
+ * Like AIBinder_handleShellCommand, but also given the IShellCallback of the
+ * command, which lets it open files with the permissions of the caller.
+ *
+ * \param binder the binder executing the command
+ * \param in input file descriptor, should be flushed, ownership is not passed
+ * \param out output file descriptor, should be flushed, ownership is not passed
+ * \param err error file descriptor, should be flushed, ownership is not passed
+ * \param argv array of null-terminated strings for command (may be null if argc
+ * is 0)
+ * \param argc length of argv array
+ * \param shellCallback the IShellCallback of the caller, or null if it didn't
+ * pass one. Ownership is not passed.
+ *
+ * \return binder_status_t result of transaction
+ */
+typedef binder_status_t (*AIBinder_handleShellCommandWithCallback)(AIBinder* binder, int in,
+                                                                   int out, int err,
+                                                                   const char** argv,
+                                                                   uint32_t argc,
+                                                                   AIBinder* shellCallback);
+
+/**
+ * This sets the implementation of handleShellCommand for a class, which is
+ * also given the IShellCallback of each command. When this is set, it is called
+ * instead of the function passed to AIBinder_Class_setHandleShellCommand.
+ *
+ * This must be called before any instance of the class is created.
+ *
+ * \param handleShellCommand function to call when a shell transaction is
+ * received
+ */
+void AIBinder_Class_setHandleShellCommandWithCallback(
+        AIBinder_Class* clazz, AIBinder_handleShellCommandWithCallback handleShellCommand)
+        __INTRODUCED_IN(__ANDROID_API_FUTURE__);
+
 __END_DECLS
@@ -0,0 +1,402 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Pure Rust implementation of binder RPC over Unix domain sockets.
+//!
+//! Unlike `rpcbinder`, which wraps the C++ `RpcSession` and `RpcServer` of
+//! libbinder, this crate implements the wire protocol and the session state
+//! machine itself, on top of the parcel codec of `libbinder_rs_parcel`. It
+//! doesn't depend on libbinder or libbinder_ndk, so it can be used where they
+//! aren't available, and it interoperates with the C++ implementation.
+//!
+//! Binders are the [`SpIBinder`]s of `libbinder_rs_parcel`. Local objects
+//! implement [`BinderObject`], and binders received from the other side are
+//! [`RpcProxy`]s. Binders are written to and read from parcels as usual, as
+//! long as the parcels belong to the session, e.g. because they were created
+//! with [`SpIBinder::prepare_transact`] for a proxy.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use binder::binder_impl::{BinderObject, BorrowedParcel, TransactionFlags};
+//! use binder::{SpIBinder, StatusCode};
+//! use binder_rpc::{RpcServer, RpcSession};
+//!
+//! struct Echo;
+//!
+//! impl BinderObject for Echo {
+//!     fn transact(
+//!         &self,
+//!         _code: u32,
+//!         data: &BorrowedParcel<'_>,
+//!         reply: &mut BorrowedParcel<'_>,
+//!         _flags: TransactionFlags,
+//!     ) -> Result<(), StatusCode> {
+//!         reply.write(&data.read::<String>()?)
+//!     }
+//! }
+//!
+//! # fn example() -> Result<(), StatusCode> {
+//! let server = RpcServer::new_unix_domain("/tmp/example.sock", SpIBinder::new(Echo))?;
+//! server.start();
+//!
+//! let session = RpcSession::new();
+//! let echo = session.setup_unix_domain_client("/tmp/example.sock")?;
+//! let mut data = echo.prepare_transact()?;
+//! data.write("hello")?;
+//! let reply = echo.submit_transact(1, data, 0)?;
+//! assert_eq!(reply.read::<String>()?, "hello");
+//! # Ok(())
+//! # }
+//! ```
+
+mod parcel;
+mod server;
+mod session;
+mod state;
+pub mod wire;
+
+pub use parcel::{enforce_interface, write_interface_token};
+pub use server::RpcServer;
+pub use session::{RpcProxy, RpcSession};
+
+use binder::binder_impl::status_result;
+use binder::StatusCode;
+
+#[cfg(doc)]
+use binder::{binder_impl::BinderObject, SpIBinder};
+
+/// Convert an I/O error on a socket into the status that libbinder reports for
+/// it.
+pub(crate) fn status_from_io(e: std::io::Error) -> StatusCode {
+    match e.raw_os_error() {
+        Some(errno) => status_result(-errno).err().unwrap_or(StatusCode::UNKNOWN_ERROR),
+        None => StatusCode::DEAD_OBJECT,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use binder::binder_impl::{
+        BinderObject, BorrowedParcel, Parcel, TransactionCode, TransactionFlags,
+        FIRST_CALL_TRANSACTION, FLAG_ONEWAY,
+    };
+    use binder::SpIBinder;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::time::Duration;
+
+    const ECHO: TransactionCode = FIRST_CALL_TRANSACTION;
+    const CALL_BACK: TransactionCode = FIRST_CALL_TRANSACTION + 1;
+    const RECORD: TransactionCode = FIRST_CALL_TRANSACTION + 2;
+    const GET_RECORDED: TransactionCode = FIRST_CALL_TRANSACTION + 3;
+
+    /// Root object of the test server.
+    #[derive(Default)]
+    struct TestService {
+        recorded: Mutex<Vec<i32>>,
+        recorded_cv: Condvar,
+    }
+
+    impl BinderObject for TestService {
+        fn transact(
+            &self,
+            code: TransactionCode,
+            data: &BorrowedParcel<'_>,
+            reply: &mut BorrowedParcel<'_>,
+            _flags: TransactionFlags,
+        ) -> Result<(), StatusCode> {
+            match code {
+                ECHO => reply.write(&data.read::<String>()?),
+                CALL_BACK => {
+                    // Call the binder from the client, which must be handled
+                    // on the connection that this transaction came from.
+                    let callback: SpIBinder = data.read()?;
+                    if callback.downcast_ref::<RpcProxy>().is_none() {
+                        return Err(StatusCode::BAD_VALUE);
+                    }
+                    let mut callback_data = callback.prepare_transact()?;
+                    callback_data.write(&data.read::<String>()?)?;
+                    let callback_reply = callback.submit_transact(ECHO, callback_data, 0)?;
+                    reply.write(&callback_reply.read::<String>()?)
+                }
+                RECORD => {
+                    self.recorded.lock().unwrap().push(data.read()?);
+                    self.recorded_cv.notify_all();
+                    Ok(())
+                }
+                GET_RECORDED => reply.write(&*self.recorded.lock().unwrap()),
+                _ => Err(StatusCode::UNKNOWN_TRANSACTION),
+            }
+        }
+    }
+
+    struct Callback;
+
+    impl BinderObject for Callback {
+        fn transact(
+            &self,
+            code: TransactionCode,
+            data: &BorrowedParcel<'_>,
+            reply: &mut BorrowedParcel<'_>,
+            _flags: TransactionFlags,
+        ) -> Result<(), StatusCode> {
+            match code {
+                ECHO => reply.write(&format!("callback: {}", data.read::<String>()?)),
+                _ => Err(StatusCode::UNKNOWN_TRANSACTION),
+            }
+        }
+    }
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("binder_rpc_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn start_server(name: &str, threads: usize) -> (RpcServer, SpIBinder, PathBuf) {
+        let path = socket_path(name);
+        let service = SpIBinder::new(TestService::default());
+        let server = RpcServer::new_unix_domain(&path, service.clone())
+            .expect("Could not create RPC server");
+        server.set_max_threads(threads);
+        server.start();
+        (server, service, path)
+    }
+
+    fn connect(path: &PathBuf) -> (RpcSession, SpIBinder) {
+        let session = RpcSession::new();
+        let root = session.setup_unix_domain_client(path).expect("Could not connect to RPC server");
+        assert!(root.downcast_ref::<RpcProxy>().is_some(), "Root object of the server is local");
+        (session, root)
+    }
+
+    fn echo(binder: &SpIBinder, value: &str) -> Result<String, StatusCode> {
+        let mut data = binder.prepare_transact()?;
+        data.write(value)?;
+        binder.submit_transact(ECHO, data, 0)?.read()
+    }
+
+    #[test]
+    fn round_trip() {
+        let (server, _service, path) = start_server("round_trip", 3);
+        let (session, root) = connect(&path);
+        assert_eq!(echo(&root, "hello").as_deref(), Ok("hello"));
+        assert_eq!(
+            root.submit_transact(FIRST_CALL_TRANSACTION + 100, root.prepare_transact().unwrap(), 0)
+                .err(),
+            Some(StatusCode::UNKNOWN_TRANSACTION)
+        );
+
+        // Transactions from several threads use separate connections.
+        let threads: Vec<_> = (0..3)
+            .map(|i| {
+                let root = root.clone();
+                std::thread::spawn(move || echo(&root, &i.to_string()))
+            })
+            .collect();
+        for (i, thread) in threads.into_iter().enumerate() {
+            assert_eq!(thread.join().unwrap(), Ok(i.to_string()));
+        }
+
+        session.shutdown().expect("Could not shut down session");
+        assert_eq!(echo(&root, "hello").err(), Some(StatusCode::DEAD_OBJECT));
+        server.shutdown().expect("Could not shut down RPC server");
+        assert_eq!(server.shutdown(), Err(StatusCode::INVALID_OPERATION));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn nested_callback() {
+        let (server, _service, path) = start_server("nested_callback", 1);
+        let (session, root) = connect(&path);
+
+        let callback = SpIBinder::new(Callback);
+        let mut data = root.prepare_transact().unwrap();
+        data.write(&callback).unwrap();
+        data.write("hello").unwrap();
+        let reply = root.submit_transact(CALL_BACK, data, 0).expect("Callback transaction failed");
+        assert_eq!(reply.read::<String>().as_deref(), Ok("callback: hello"));
+
+        // The server dropped its proxy of the callback, so only the root object
+        // is left.
+        assert_eq!(session.count_binders(), 1);
+
+        drop(server);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn oneway_order() {
+        let (server, service, path) = start_server("oneway_order", 4);
+        let service = service.downcast_ref::<TestService>().unwrap();
+        let (_session, root) = connect(&path);
+
+        for i in 0..100 {
+            let mut data = root.prepare_transact().unwrap();
+            data.write(&i).unwrap();
+            root.submit_transact(RECORD, data, FLAG_ONEWAY).expect("Oneway transaction failed");
+        }
+        let recorded = service.recorded.lock().unwrap();
+        let (recorded, timeout) = service
+            .recorded_cv
+            .wait_timeout_while(recorded, Duration::from_secs(10), |recorded| recorded.len() < 100)
+            .unwrap();
+        assert!(!timeout.timed_out());
+        assert_eq!(*recorded, (0..100).collect::<Vec<i32>>());
+        drop(recorded);
+
+        let reply =
+            root.submit_transact(GET_RECORDED, root.prepare_transact().unwrap(), 0).unwrap();
+        assert_eq!(reply.read::<Vec<i32>>(), Ok((0..100).collect()));
+
+        drop(server);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn foreign_proxy() {
+        let (server, _service, path) = start_server("foreign_proxy", 1);
+        let (_session, root) = connect(&path);
+        let other_session = Arc::new(RpcSession::new());
+        let mut data = Parcel::new();
+        data.mark_for_rpc(other_session);
+        assert_eq!(data.write(&root), Err(StatusCode::INVALID_OPERATION));
+        drop(server);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    /// Bytes of a connection between a client and a server.
+    enum Message {
+        /// Sent by the client.
+        Sent(&'static str),
+        /// Received by the client.
+        Received(&'static str),
+    }
+
+    /// Check the bytes that a client exchanges with a server while it connects
+    /// and drops the root object, as the C++ `RpcServer` sends and expects
+    /// them.
+    #[test]
+    fn client_transcript() {
+        use Message::*;
+        const TRANSCRIPT: &[Message] = &[
+            // Connection header for a new session, and connection init.
+            Sent("00000000000000000000000000000000"),
+            Sent("6363690000000000"),
+            // New session response.
+            Received("0000000000000000"),
+            // GET_MAX_THREADS special transaction, and the reply with 1 thread.
+            Sent(concat!(
+                "00000000280000000000000000000000",
+                "000000000000000001000000000000000000000000000000",
+                "00000000000000000000000000000000",
+            )),
+            Received(concat!("01000000080000000000000000000000", "00000000", "01000000")),
+            // GET_SESSION_ID special transaction, and the reply with the ID.
+            Sent(concat!(
+                "00000000280000000000000000000000",
+                "000000000000000002000000000000000000000000000000",
+                "00000000000000000000000000000000",
+            )),
+            Received(concat!(
+                "01000000280000000000000000000000",
+                "00000000",
+                "20000000",
+                "abababababababababababababababababababababababababababababababab",
+            )),
+            // GET_ROOT special transaction, and the reply with a binder created
+            // by the server at address 0 with SYSTEM stability.
+            Sent(concat!(
+                "00000000280000000000000000000000",
+                "000000000000000000000000000000000000000000000000",
+                "00000000000000000000000000000000",
+            )),
+            Received(concat!(
+                "01000000140000000000000000000000",
+                "00000000",
+                "0100000003000000000000000c000000",
+            )),
+            // DEC_STRONG of the root object after the client dropped it.
+            Sent(concat!("02000000100000000000000000000000", "03000000000000000100000000000000")),
+        ];
+
+        let path = socket_path("client_transcript");
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            for message in TRANSCRIPT {
+                match message {
+                    Sent(expected) => {
+                        let mut bytes = vec![0; expected.len() / 2];
+                        stream.read_exact(&mut bytes).unwrap();
+                        assert_eq!(hex(&bytes), *expected);
+                    }
+                    Received(bytes) => stream.write_all(&unhex(bytes)).unwrap(),
+                }
+            }
+            // The client doesn't send anything else before it disconnects.
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).unwrap();
+            assert_eq!(hex(&rest), "");
+        });
+
+        let (session, root) = connect(&path);
+        assert_eq!(session.count_binders(), 1);
+        drop(root);
+        assert_eq!(session.count_binders(), 0);
+        session.shutdown().unwrap();
+        server.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// Check that a client rejects a command which is larger than any
+    /// transaction before it allocates its body.
+    #[test]
+    fn oversized_command() {
+        let path = socket_path("oversized_command");
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // Connection header and connection init, then GET_MAX_THREADS.
+            let mut request = [0; 24];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(&unhex("0000000000000000")).unwrap();
+            let mut request = [0; 56];
+            stream.read_exact(&mut request).unwrap();
+            // Reply with a body of 4 GiB.
+            stream.write_all(&unhex("01000000ffffffff0000000000000000")).unwrap();
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).unwrap();
+        });
+
+        let session = RpcSession::new();
+        assert_eq!(session.setup_unix_domain_client(&path).err(), Some(StatusCode::NO_MEMORY));
+        server.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,310 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Parcel contents specific to binder RPC.
+//!
+//! RPC parcels use the same format as kernel binder parcels for plain data, so
+//! they are written and read with [`Parcel`] as usual. The differences are the
+//! interface token, which is only the interface descriptor since the strict
+//! mode policy and work source are not sent over RPC, and binders, which are
+//! encoded by the [`RpcSession`](crate::RpcSession) that the parcel is marked
+//! for.
+
+use binder::binder_impl::Parcel;
+use binder::StatusCode;
+
+/// Write the interface token which starts the data of an AIDL transaction, like
+/// `Parcel::writeInterfaceToken` for an RPC parcel.
+pub fn write_interface_token(parcel: &mut Parcel, descriptor: &str) -> Result<(), StatusCode> {
+    parcel.write(descriptor)
+}
+
+/// Read the interface token of an AIDL transaction, like
+/// `Parcel::enforceInterface` for an RPC parcel.
+///
+/// Returns `BAD_TYPE` if the token is not `descriptor`.
+pub fn enforce_interface(parcel: &Parcel, descriptor: &str) -> Result<(), StatusCode> {
+    let token: String = parcel.read()?;
+    if token == descriptor {
+        Ok(())
+    } else {
+        Err(StatusCode::BAD_TYPE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RpcSession;
+    use binder::binder_impl::BorrowedParcel;
+    use binder::{declare_binder_enum, impl_serialize_for_parcelable, Parcelable, SpIBinder};
+    use std::sync::Arc;
+
+    /// `kCurrentRepr` from `binderRpcWireProtocolTest.cpp`: the hex encoded
+    /// contents of an RPC parcel after each of its `kFillFuns`, separated by
+    /// `|`.
+    const CURRENT_REPR: &str = concat!(
+        "0300000074006f006b000000|ffffffff|00000000|11000000|00000000|01000000|13270000|",
+        "ffffffffffffffff|0000000000000000|1100000000000000|0000000000000000|0100000000000000|",
+        "1327000000000000|00000000|cdcccc3d|9a991141|0000000000000000|9a9999999999b93f|",
+        "3333333333332240|00000000|61000000|6261626100000000|0000000000000000|0100000061000000|",
+        "040000006261626100000000|0000000000000000|0100000061000000|",
+        "04000000620061006200610000000000|0000000000000000|03000000ffffffff0000000011000000|",
+        "030000000011ff00|01000000|00000000|61000000|3f000000|00000000|80ffffff|00000000|7f000000|",
+        "0000000000000000|0100000061000000|04000000610062006100620000000000|ffffffff|",
+        "0000000000000000|0100000061000000|04000000610062006100620000000000|ffffffff|",
+        "03000000ff001100|00000000|03000000ff001100|ffffffff|0300000000011100|00000000|",
+        "0300000000011100|ffffffff|03000000ffffffff0000000011000000|00000000|",
+        "03000000ffffffff0000000011000000|ffffffff|",
+        "03000000ffffffffffffffff00000000000000001100000000000000|00000000|",
+        "03000000ffffffffffffffff00000000000000001100000000000000|ffffffff|",
+        "03000000000000000000000001000000000000001100000000000000|00000000|",
+        "03000000000000000000000001000000000000001100000000000000|ffffffff|",
+        "0300000000000000cdcccc3d9a991141|00000000|0300000000000000cdcccc3d9a991141|ffffffff|",
+        "0300000000000000000000009a9999999999b93f3333333333332240|00000000|",
+        "0300000000000000000000009a9999999999b93f3333333333332240|ffffffff|",
+        "020000000100000000000000|00000000|020000000100000000000000|ffffffff|",
+        "0300000061000000000000003f000000|00000000|0300000061000000000000003f000000|ffffffff|",
+        "03000000ffffffff00000000000000000100000061000000|00000000|",
+        "03000000ffffffff00000000000000000100000061000000|ffffffff|",
+        "03000000ffffffff00000000000000000100000061000000|00000000|",
+        "03000000ffffffff00000000000000000100000061000000|ffffffff|010000000000000000000000|",
+        "00000000|010000000000000000000000|ffffffff|0200000000010000|0200000000010000|ffffffff|",
+        "020000000000000001000000|020000000000000001000000|ffffffff|",
+        "0200000000000000000000000100000000000000|0200000000000000000000000100000000000000|",
+        "ffffffff|010000000100000025000000|010000000100000025000000|00000000|0100000025000000|",
+        "0100000025000000|03000000|00000000|ffffffff|03000000|00000000|00000000|",
+        "07000000020000003a0044000000000000000000|f8ffffff020000003a002f00000000000000000008000000",
+    );
+
+    declare_binder_enum! {
+        EnumInt8 : [i8; 2] {
+            INT8_A = 0,
+            INT8_B = 1,
+        }
+    }
+
+    declare_binder_enum! {
+        EnumInt32 : [i32; 2] {
+            INT32_A = 0,
+            INT32_B = 1,
+        }
+    }
+
+    declare_binder_enum! {
+        EnumInt64 : [i64; 2] {
+            INT64_A = 0,
+            INT64_B = 1,
+        }
+    }
+
+    /// Equivalent of the `AParcelable` of the C++ test.
+    #[derive(Default)]
+    struct AParcelable;
+
+    impl Parcelable for AParcelable {
+        fn write_to_parcel(&self, parcel: &mut BorrowedParcel<'_>) -> Result<(), StatusCode> {
+            parcel.write(&37i32)
+        }
+
+        fn read_from_parcel(&mut self, _parcel: &BorrowedParcel<'_>) -> Result<(), StatusCode> {
+            Ok(())
+        }
+    }
+
+    impl_serialize_for_parcelable!(AParcelable);
+
+    type FillFun = Box<dyn Fn(&mut Parcel) -> Result<(), StatusCode>>;
+
+    fn fill(fill: impl Fn(&mut Parcel) -> Result<(), StatusCode> + 'static) -> Option<FillFun> {
+        Some(Box::new(fill))
+    }
+
+    macro_rules! write_value {
+        ($value:expr) => {
+            fill(|p| p.write($value))
+        };
+    }
+
+    /// The Rust equivalents of `kFillFuns`, in the same order. Functions of
+    /// the C++ `Parcel` which have no Rust equivalent are `None`.
+    fn fill_funs() -> Vec<Option<FillFun>> {
+        let strings = || vec![None, Some(String::new()), Some("a".to_owned())];
+        vec![
+            fill(|p| write_interface_token(p, "tok")),
+            write_value!(&-1i32),
+            write_value!(&0i32),
+            write_value!(&17i32),
+            write_value!(&0u32),
+            write_value!(&1u32),
+            write_value!(&10003u32),
+            write_value!(&-1i64),
+            write_value!(&0i64),
+            write_value!(&17i64),
+            write_value!(&0u64),
+            write_value!(&1u64),
+            write_value!(&10003u64),
+            write_value!(&0.0f32),
+            write_value!(&0.1f32),
+            write_value!(&9.1f32),
+            write_value!(&0.0f64),
+            write_value!(&0.1f64),
+            write_value!(&9.1f64),
+            // writeCString
+            None,
+            None,
+            None,
+            // writeString8
+            None,
+            None,
+            None,
+            write_value!(""),
+            write_value!("a"),
+            write_value!("baba"),
+            write_value!(&None::<SpIBinder>),
+            write_value!(&[-1i32, 0, 17][..]),
+            write_value!(&[0u8, 17, 255][..]),
+            write_value!(&true),
+            write_value!(&false),
+            write_value!(&(b'a' as u16)),
+            write_value!(&(b'?' as u16)),
+            write_value!(&0u16),
+            write_value!(&-128i8),
+            write_value!(&0i8),
+            write_value!(&127i8),
+            write_value!(""),
+            write_value!("a"),
+            write_value!("abab"),
+            write_value!(&None::<String>),
+            write_value!(&Some(String::new())),
+            write_value!(&Some("a".to_owned())),
+            write_value!(&Some("abab".to_owned())),
+            write_value!(&None::<Vec<i8>>),
+            write_value!(&Some(vec![-1i8, 0, 17])),
+            write_value!(&Vec::<i8>::new()),
+            write_value!(&vec![-1i8, 0, 17]),
+            write_value!(&None::<Vec<u8>>),
+            write_value!(&Some(vec![0u8, 1, 17])),
+            write_value!(&Vec::<u8>::new()),
+            write_value!(&vec![0u8, 1, 17]),
+            write_value!(&None::<Vec<i32>>),
+            write_value!(&Some(vec![-1i32, 0, 17])),
+            write_value!(&Vec::<i32>::new()),
+            write_value!(&vec![-1i32, 0, 17]),
+            write_value!(&None::<Vec<i64>>),
+            write_value!(&Some(vec![-1i64, 0, 17])),
+            write_value!(&Vec::<i64>::new()),
+            write_value!(&vec![-1i64, 0, 17]),
+            write_value!(&None::<Vec<u64>>),
+            write_value!(&Some(vec![0u64, 1, 17])),
+            write_value!(&Vec::<u64>::new()),
+            write_value!(&vec![0u64, 1, 17]),
+            write_value!(&None::<Vec<f32>>),
+            write_value!(&Some(vec![0.0f32, 0.1, 9.1])),
+            write_value!(&Vec::<f32>::new()),
+            write_value!(&vec![0.0f32, 0.1, 9.1]),
+            write_value!(&None::<Vec<f64>>),
+            write_value!(&Some(vec![0.0f64, 0.1, 9.1])),
+            write_value!(&Vec::<f64>::new()),
+            write_value!(&vec![0.0f64, 0.1, 9.1]),
+            write_value!(&None::<Vec<bool>>),
+            write_value!(&Some(vec![true, false])),
+            write_value!(&Vec::<bool>::new()),
+            write_value!(&vec![true, false]),
+            write_value!(&None::<Vec<u16>>),
+            write_value!(&Some(vec![b'a' as u16, 0, b'?' as u16])),
+            write_value!(&Vec::<u16>::new()),
+            write_value!(&vec![b'a' as u16, 0, b'?' as u16]),
+            // writeString16Vector
+            write_value!(&None::<Vec<Option<String>>>),
+            fill(move |p| p.write(&Some(strings()))),
+            write_value!(&Vec::<Option<String>>::new()),
+            fill(move |p| p.write(&strings())),
+            // writeUtf8VectorAsUtf16Vector
+            write_value!(&None::<Vec<Option<String>>>),
+            fill(move |p| p.write(&Some(strings()))),
+            write_value!(&Vec::<Option<String>>::new()),
+            fill(move |p| p.write(&strings())),
+            write_value!(&None::<Vec<SpIBinder>>),
+            write_value!(&Some(vec![None::<SpIBinder>])),
+            write_value!(&Vec::<SpIBinder>::new()),
+            write_value!(&vec![None::<SpIBinder>]),
+            write_value!(&None::<Vec<EnumInt8>>),
+            write_value!(&Some(vec![EnumInt8::INT8_A, EnumInt8::INT8_B])),
+            write_value!(&vec![EnumInt8::INT8_A, EnumInt8::INT8_B]),
+            write_value!(&None::<Vec<EnumInt32>>),
+            write_value!(&Some(vec![EnumInt32::INT32_A, EnumInt32::INT32_B])),
+            write_value!(&vec![EnumInt32::INT32_A, EnumInt32::INT32_B]),
+            write_value!(&None::<Vec<EnumInt64>>),
+            write_value!(&Some(vec![EnumInt64::INT64_A, EnumInt64::INT64_B])),
+            write_value!(&vec![EnumInt64::INT64_A, EnumInt64::INT64_B]),
+            write_value!(&None::<Vec<Option<AParcelable>>>),
+            write_value!(&Some(vec![Some(AParcelable)])),
+            write_value!(&vec![AParcelable]),
+            write_value!(&None::<AParcelable>),
+            write_value!(&Some(AParcelable)),
+            write_value!(&AParcelable),
+            fill(|p| p.write_slice_size(Some(&[0i32, 1, 17]))),
+            fill(|p| p.write_slice_size::<AParcelable>(Some(&[]))),
+            fill(|p| p.write_slice_size::<i32>(None)),
+            fill(|p| p.write_slice_size(Some(&[0i32, 1, 17]))),
+            // writeNoException and binder::Status, which is only available
+            // with libbinder_ndk.
+            None,
+            None,
+            None,
+            None,
+        ]
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn current_wire_protocol_version() {
+        let expected: Vec<&str> = CURRENT_REPR.split('|').collect();
+        let fill_funs = fill_funs();
+        assert_eq!(fill_funs.len(), expected.len());
+
+        let session = Arc::new(RpcSession::new());
+        let mut checked = 0;
+        for (index, (fill_fun, expected)) in fill_funs.iter().zip(expected).enumerate() {
+            let fill_fun = match fill_fun {
+                Some(fill_fun) => fill_fun,
+                None => continue,
+            };
+            let mut parcel = Parcel::new();
+            parcel.mark_for_rpc(session.clone());
+            fill_fun(&mut parcel).expect("Could not fill parcel");
+            let actual = hex(&parcel.marshal().expect("Could not marshal parcel"));
+            assert_eq!(actual, expected, "Format mismatch for kFillFuns[{}]", index);
+            checked += 1;
+        }
+        assert_eq!(checked, 107);
+    }
+
+    #[test]
+    fn interface_token() {
+        let mut parcel = Parcel::new();
+        write_interface_token(&mut parcel, "tok").unwrap();
+        let parcel = Parcel::unmarshal(&parcel.marshal().unwrap()).unwrap();
+        assert_eq!(enforce_interface(&parcel, "tok"), Ok(()));
+
+        let parcel = Parcel::unmarshal(&parcel.marshal().unwrap()).unwrap();
+        assert_eq!(enforce_interface(&parcel, "other"), Err(StatusCode::BAD_TYPE));
+    }
+}
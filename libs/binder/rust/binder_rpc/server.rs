@@ -0,0 +1,221 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::session::{RpcSession, SESSION_ID_SIZE};
+use crate::status_from_io;
+use crate::wire::*;
+use binder::{SpIBinder, StatusCode};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// A server that accepts binder RPC connections on a socket and serves a root
+/// object to every client.
+///
+/// Each connection from a client is served by its own thread. Dropping the
+/// server shuts it down, along with all of its sessions.
+pub struct RpcServer {
+    inner: Arc<ServerInner>,
+}
+
+struct ServerInner {
+    listener: UnixListener,
+    path: PathBuf,
+    root: SpIBinder,
+    max_threads: AtomicUsize,
+    shutdown: AtomicBool,
+    sessions: Mutex<HashMap<Vec<u8>, RpcSession>>,
+    join_thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl RpcServer {
+    /// Create a server listening on a Unix domain socket bound to the given
+    /// path, serving `root` to every client.
+    ///
+    /// The server doesn't accept connections until it is started with
+    /// [`start`](Self::start) or [`join`](Self::join).
+    pub fn new_unix_domain(path: impl AsRef<Path>, root: SpIBinder) -> Result<Self, StatusCode> {
+        let path = path.as_ref().to_owned();
+        let listener = UnixListener::bind(&path).map_err(status_from_io)?;
+        Ok(Self {
+            inner: Arc::new(ServerInner {
+                listener,
+                path,
+                root,
+                max_threads: AtomicUsize::new(1),
+                shutdown: AtomicBool::new(false),
+                sessions: Mutex::new(HashMap::new()),
+                join_thread: Mutex::new(None),
+            }),
+        })
+    }
+
+    /// Set the number of connections that each client opens to send
+    /// transactions, and so the number of threads that serve them. The default
+    /// is 1.
+    ///
+    /// This must be called before the server is started.
+    pub fn set_max_threads(&self, threads: usize) {
+        self.inner.max_threads.store(threads, Ordering::Relaxed);
+    }
+
+    /// Start accepting connections on a background thread, and return
+    /// immediately.
+    pub fn start(&self) {
+        let inner = self.inner.clone();
+        let thread = thread::spawn(move || inner.join());
+        *self.inner.join_thread.lock().unwrap() = Some(thread);
+    }
+
+    /// Accept connections on the calling thread until the server is shut down
+    /// from another thread.
+    pub fn join(&self) {
+        self.inner.join()
+    }
+
+    /// Shut down the server and all of its sessions, and wait for the thread
+    /// accepting connections to stop if it was started with
+    /// [`start`](Self::start).
+    ///
+    /// Returns `INVALID_OPERATION` if the server was already shut down.
+    pub fn shutdown(&self) -> Result<(), StatusCode> {
+        if self.inner.shutdown.swap(true, Ordering::SeqCst) {
+            return Err(StatusCode::INVALID_OPERATION);
+        }
+        // Wake up the thread blocked in accept.
+        let _ = UnixStream::connect(&self.inner.path);
+
+        let sessions: Vec<RpcSession> =
+            self.inner.sessions.lock().unwrap().drain().map(|(_, session)| session).collect();
+        for session in sessions {
+            session.shutdown()?;
+        }
+        if let Some(thread) = self.inner.join_thread.lock().unwrap().take() {
+            thread.join().map_err(|_| StatusCode::UNKNOWN_ERROR)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+impl ServerInner {
+    fn join(self: &Arc<Self>) {
+        for stream in self.listener.incoming() {
+            if self.shutdown.load(Ordering::SeqCst) {
+                break;
+            }
+            // Connections that fail are dropped, like in the C++ `RpcServer`.
+            if let Ok(stream) = stream {
+                let server = self.clone();
+                thread::spawn(move || server.establish_connection(stream));
+            }
+        }
+    }
+
+    fn establish_connection(&self, stream: UnixStream) {
+        let mut header = [0; RpcConnectionHeader::SIZE];
+        if (&stream).read_exact(&mut header).is_err() {
+            return;
+        }
+        let header = match RpcConnectionHeader::decode(&header) {
+            Ok(header) => header,
+            Err(_) => return,
+        };
+        let mut session_id = vec![0; header.session_id_size.into()];
+        if !session_id.is_empty()
+            && (session_id.len() != SESSION_ID_SIZE
+                || (&stream).read_exact(&mut session_id).is_err())
+        {
+            return;
+        }
+
+        let incoming = header.options & RPC_CONNECTION_OPTION_INCOMING != 0;
+        // The only protocol version is 0 for now.
+        #[allow(clippy::unnecessary_min_or_max)]
+        let protocol_version = header.version.min(RPC_WIRE_PROTOCOL_VERSION);
+        let requesting_new_session = session_id.is_empty();
+        if requesting_new_session {
+            let response = RpcNewSessionResponse { version: protocol_version };
+            if (&stream).write_all(&response.to_bytes()).is_err() {
+                return;
+            }
+        }
+
+        let session = {
+            let mut sessions = self.sessions.lock().unwrap();
+            if self.shutdown.load(Ordering::SeqCst) {
+                return;
+            }
+            if requesting_new_session {
+                // A session can't be created with an incoming connection,
+                // since it would never be cleaned up.
+                if incoming {
+                    return;
+                }
+                let session_id = loop {
+                    match new_session_id() {
+                        Ok(id) if !sessions.contains_key(&id) => break id,
+                        Ok(_) => continue,
+                        Err(_) => return,
+                    }
+                };
+                let session = RpcSession::new_for_server(
+                    self.root.clone(),
+                    self.max_threads.load(Ordering::Relaxed),
+                    session_id.clone(),
+                    protocol_version,
+                );
+                sessions.insert(session_id, session.clone());
+                session
+            } else {
+                match sessions.get(&session_id) {
+                    Some(session) => session.clone(),
+                    None => return,
+                }
+            }
+        };
+
+        if incoming {
+            // The connection is only used to send transactions to the client.
+            let _ = session.add_outgoing_connection(stream);
+            return;
+        }
+
+        session.join(stream);
+
+        // Every error on a connection ends its session, so the session can be
+        // forgotten once its last connection is gone.
+        if !session.has_incoming_connections() {
+            self.sessions.lock().unwrap().retain(|_, other| !other.ptr_eq(&session));
+        }
+    }
+}
+
+fn new_session_id() -> std::io::Result<Vec<u8>> {
+    let mut id = vec![0; SESSION_ID_SIZE];
+    File::open("/dev/urandom")?.read_exact(&mut id)?;
+    Ok(id)
+}
@@ -0,0 +1,855 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::state::RpcState;
+use crate::status_from_io;
+use crate::wire::*;
+use binder::binder_impl::{
+    status_result, BinderObject, BorrowedParcel, Parcel, RpcParcelSession, TransactionCode,
+    TransactionFlags, FLAG_ONEWAY,
+};
+use binder::{SpIBinder, StatusCode};
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle, ThreadId};
+
+/// Size of the session IDs handed out by servers.
+pub(crate) const SESSION_ID_SIZE: usize = 32;
+
+/// Default maximum number of connections that a client opens to send
+/// transactions, like in the C++ `RpcSession`.
+const DEFAULT_MAX_OUTGOING_THREADS: usize = 10;
+
+/// Maximum size of a command body that the session receives, like in
+/// `RpcState::CommandData`, so that the other side can't make it allocate
+/// arbitrary amounts of memory.
+const MAX_COMMAND_DATA: usize = 100000;
+
+/// `Stability::Level::UNDECLARED`, written after null binders.
+const STABILITY_UNDECLARED: i32 = 0;
+/// `Stability::Level::SYSTEM`, written after non-null binders. Binders sent by
+/// this crate are never marked as VINTF or vendor stable.
+const STABILITY_SYSTEM: i32 = 0b001100;
+/// All declared `Stability::Level`s.
+const DECLARED_STABILITY_LEVELS: [i32; 3] = [0b000011, STABILITY_SYSTEM, 0b111111];
+
+/// A binder object hosted by the other side of a session.
+///
+/// Binders received over an [`RpcSession`] are [`SpIBinder`]s of an
+/// `RpcProxy`, which sends their transactions over the session. Parcels for
+/// these transactions should be created with [`SpIBinder::prepare_transact`],
+/// so that binders can be written to them. When the last reference to a proxy
+/// is dropped, the other side is told to release the object.
+pub struct RpcProxy {
+    session: Arc<SessionInner>,
+    address: u64,
+    /// Identifies this proxy among the proxies that the session created for
+    /// `address` over time.
+    id: u64,
+}
+
+impl RpcProxy {
+    /// The session that this proxy was received from.
+    pub fn session(&self) -> RpcSession {
+        RpcSession { inner: self.session.clone() }
+    }
+}
+
+impl BinderObject for RpcProxy {
+    fn prepare_transact(&self) -> Result<Parcel, StatusCode> {
+        Ok(self.session.new_parcel())
+    }
+
+    fn transact(
+        &self,
+        code: TransactionCode,
+        data: &BorrowedParcel<'_>,
+        reply: &mut BorrowedParcel<'_>,
+        flags: TransactionFlags,
+    ) -> Result<(), StatusCode> {
+        let received = self.session.transact_address(self.address, code, data, flags)?;
+        reply.mark_for_rpc(self.session.parcel_session());
+        reply.append_all_from(&received)
+    }
+}
+
+impl fmt::Debug for RpcProxy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RpcProxy").field("address", &self.address).finish()
+    }
+}
+
+impl Drop for RpcProxy {
+    fn drop(&mut self) {
+        let is_last_proxy = self.session.lock_state().is_proxy_of(self.address, self.id);
+        if is_last_proxy {
+            // There is nobody to report an error to, and the session is
+            // terminated if the command can't be sent.
+            let _ = self.session.send_dec_strong_to_target(self.address, 0);
+        }
+    }
+}
+
+/// A session of binder RPC, i.e. a set of connections between a client and a
+/// server which share the binders sent over them.
+///
+/// A client session is configured first and then connected with
+/// [`setup_unix_domain_client`](Self::setup_unix_domain_client), which returns
+/// the root object of the server. Server sessions are created by
+/// [`RpcServer`](crate::RpcServer).
+///
+/// Binders are sent and received as [`SpIBinder`]s in the parcels of the
+/// session's transactions, which the session encodes as the
+/// [`RpcParcelSession`] of these parcels.
+///
+/// The session stays connected as long as any proxy received from it is
+/// alive, even after the `RpcSession` itself is dropped.
+#[derive(Clone)]
+pub struct RpcSession {
+    pub(crate) inner: Arc<SessionInner>,
+}
+
+impl RpcSession {
+    /// Create a new, unconnected client session.
+    pub fn new() -> Self {
+        Self { inner: Arc::new(SessionInner::new(false, None)) }
+    }
+
+    pub(crate) fn new_for_server(
+        root: SpIBinder,
+        max_incoming_threads: usize,
+        id: Vec<u8>,
+        protocol_version: u32,
+    ) -> Self {
+        let inner = SessionInner::new(true, Some(root));
+        {
+            let mut config = inner.config.lock().unwrap();
+            config.max_incoming_threads = max_incoming_threads;
+            config.id = id;
+            config.protocol_version = protocol_version;
+        }
+        Self { inner: Arc::new(inner) }
+    }
+
+    /// Set the maximum number of threads that the session uses to handle
+    /// transactions sent by the server, e.g. callbacks on binders that the
+    /// client passed to it. The default is 0, so the client only receives
+    /// transactions which are nested in its own calls to the server.
+    ///
+    /// This must be called before the session is connected.
+    pub fn set_max_incoming_threads(&self, threads: usize) {
+        self.inner.config.lock().unwrap().max_incoming_threads = threads;
+    }
+
+    /// Set the maximum number of connections that the session uses to send
+    /// transactions to the server. The number actually used is the smaller of
+    /// this and the thread count of the server.
+    ///
+    /// This must be called before the session is connected.
+    pub fn set_max_outgoing_threads(&self, threads: usize) {
+        self.inner.config.lock().unwrap().max_outgoing_threads = threads;
+    }
+
+    /// Connect to a server listening on a Unix domain socket bound to the given
+    /// path, and return its root object.
+    ///
+    /// Returns `NAME_NOT_FOUND` if the server has no root object.
+    pub fn setup_unix_domain_client(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<SpIBinder, StatusCode> {
+        let path = path.as_ref();
+        self.inner.setup_client(|| UnixStream::connect(path).map_err(status_from_io))?;
+        self.inner.get_root_object()
+    }
+
+    /// Shut down the session, and wait for its incoming threads to stop.
+    ///
+    /// Proxies received from the session can no longer be used after this.
+    pub fn shutdown(&self) -> Result<(), StatusCode> {
+        let threads = self.inner.terminate();
+        let current = thread::current().id();
+        for thread in threads {
+            if thread.thread().id() != current {
+                thread.join().map_err(|_| StatusCode::UNKNOWN_ERROR)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of binders, local or remote, known to the session.
+    pub fn count_binders(&self) -> usize {
+        self.inner.lock_state().count_binders()
+    }
+
+    pub(crate) fn join(&self, stream: UnixStream) {
+        self.inner.join(Arc::new(stream))
+    }
+
+    pub(crate) fn add_outgoing_connection(&self, stream: UnixStream) -> Result<(), StatusCode> {
+        self.inner.add_outgoing_connection(Arc::new(stream))
+    }
+
+    pub(crate) fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    pub(crate) fn has_incoming_connections(&self) -> bool {
+        self.inner.connections.lock().unwrap().slots.iter().any(|slot| slot.incoming)
+    }
+}
+
+impl Default for RpcSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RpcParcelSession for RpcSession {
+    /// Returns `INVALID_OPERATION` if `binder` is a proxy from another
+    /// session.
+    fn write_binder(
+        &self,
+        parcel: &mut BorrowedParcel<'_>,
+        binder: Option<&SpIBinder>,
+    ) -> Result<(), StatusCode> {
+        let binder = match binder {
+            Some(binder) => binder,
+            None => {
+                parcel.write(&0i32)?;
+                return parcel.write(&STABILITY_UNDECLARED);
+            }
+        };
+        if let Some(proxy) = binder.downcast_ref::<RpcProxy>() {
+            if !Arc::ptr_eq(&proxy.session, &self.inner) {
+                return Err(StatusCode::INVALID_OPERATION);
+            }
+        }
+        parcel.write(&1i32)?;
+        let address = self.inner.lock_state().on_binder_leaving(binder, self.inner.for_server)?;
+        parcel.write(&address)?;
+        parcel.write(&STABILITY_SYSTEM)
+    }
+
+    fn read_binder(&self, parcel: &BorrowedParcel<'_>) -> Result<Option<SpIBinder>, StatusCode> {
+        let is_present: i32 = parcel.read()?;
+        let mut binder = None;
+        if is_present & 1 != 0 {
+            let address: u64 = parcel.read()?;
+            binder = self.inner.on_binder_entering(address)?;
+            if binder.is_none() {
+                // A local object was sent back after it was destroyed, which
+                // the other side should not be able to do.
+                return Err(StatusCode::BAD_VALUE);
+            }
+            self.inner.flush_excess_binder_refs(address)?;
+        }
+        let stability: i32 = parcel.read()?;
+        let stability_valid = if binder.is_some() {
+            DECLARED_STABILITY_LEVELS.contains(&stability)
+        } else {
+            stability == STABILITY_UNDECLARED
+        };
+        if !stability_valid {
+            return Err(StatusCode::BAD_TYPE);
+        }
+        Ok(binder)
+    }
+}
+
+impl fmt::Debug for RpcSession {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RpcSession").field("for_server", &self.inner.for_server).finish()
+    }
+}
+
+struct SessionConfig {
+    max_incoming_threads: usize,
+    max_outgoing_threads: usize,
+    id: Vec<u8>,
+    protocol_version: u32,
+}
+
+struct ConnectionSlot {
+    stream: Arc<UnixStream>,
+    /// Whether the other side sends transactions on this connection.
+    incoming: bool,
+    /// The thread which is currently using the connection.
+    owner: Option<ThreadId>,
+    /// Whether the owner can send nested transactions on an incoming
+    /// connection, i.e. whether it is processing a synchronous transaction.
+    allow_nested: bool,
+}
+
+#[derive(Default)]
+struct Connections {
+    slots: Vec<ConnectionSlot>,
+    threads: Vec<JoinHandle<()>>,
+    terminated: bool,
+}
+
+/// What a connection is needed for, like `RpcSession::ConnectionUse`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ConnectionUse {
+    Client,
+    ClientAsync,
+    ClientRefcount,
+}
+
+/// A connection used by the current thread until it is dropped.
+struct ExclusiveConnection<'a> {
+    session: &'a SessionInner,
+    stream: Arc<UnixStream>,
+    /// Whether the connection was already used by the current thread, e.g. for
+    /// a transaction which this one is nested in.
+    reused: bool,
+}
+
+impl Drop for ExclusiveConnection<'_> {
+    fn drop(&mut self) {
+        if !self.reused {
+            let mut connections = self.session.connections.lock().unwrap();
+            if let Some(slot) =
+                connections.slots.iter_mut().find(|slot| Arc::ptr_eq(&slot.stream, &self.stream))
+            {
+                slot.owner = None;
+            }
+            self.session.connections_cv.notify_all();
+        }
+    }
+}
+
+pub(crate) struct SessionInner {
+    for_server: bool,
+    root: Option<SpIBinder>,
+    config: Mutex<SessionConfig>,
+    state: Mutex<RpcState>,
+    connections: Mutex<Connections>,
+    connections_cv: Condvar,
+}
+
+impl SessionInner {
+    fn new(for_server: bool, root: Option<SpIBinder>) -> Self {
+        Self {
+            for_server,
+            root,
+            config: Mutex::new(SessionConfig {
+                max_incoming_threads: 0,
+                max_outgoing_threads: DEFAULT_MAX_OUTGOING_THREADS,
+                id: Vec::new(),
+                protocol_version: RPC_WIRE_PROTOCOL_VERSION,
+            }),
+            state: Mutex::new(RpcState::default()),
+            connections: Mutex::new(Connections::default()),
+            connections_cv: Condvar::new(),
+        }
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, RpcState> {
+        self.state.lock().unwrap()
+    }
+
+    fn parcel_session(self: &Arc<Self>) -> Arc<dyn RpcParcelSession> {
+        Arc::new(RpcSession { inner: self.clone() })
+    }
+
+    /// Create a parcel for binders sent over this session.
+    fn new_parcel(self: &Arc<Self>) -> Parcel {
+        let mut parcel = Parcel::new();
+        parcel.mark_for_rpc(self.parcel_session());
+        parcel
+    }
+
+    fn setup_client(
+        self: &Arc<Self>,
+        connect: impl Fn() -> Result<UnixStream, StatusCode>,
+    ) -> Result<(), StatusCode> {
+        if !self.connections.lock().unwrap().slots.is_empty() {
+            return Err(StatusCode::INVALID_OPERATION);
+        }
+        let result = self.setup_client_connections(connect);
+        if result.is_err() {
+            self.terminate();
+        }
+        result
+    }
+
+    fn setup_client_connections(
+        self: &Arc<Self>,
+        connect: impl Fn() -> Result<UnixStream, StatusCode>,
+    ) -> Result<(), StatusCode> {
+        let stream = self.init_connection(connect()?, &[], false)?;
+        let mut response = [0; RpcNewSessionResponse::SIZE];
+        (&*stream).read_exact(&mut response).map_err(status_from_io)?;
+        let response = RpcNewSessionResponse::decode(&response)?;
+        if response.version > RPC_WIRE_PROTOCOL_VERSION {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        self.config.lock().unwrap().protocol_version = response.version;
+        self.add_outgoing_connection(stream)?;
+
+        let reply = self.transact_address(
+            0,
+            RPC_SPECIAL_TRANSACT_GET_MAX_THREADS,
+            Parcel::new().borrowed_ref(),
+            0,
+        )?;
+        let max_threads: i32 = reply.read()?;
+        if max_threads <= 0 {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        let reply = self.transact_address(
+            0,
+            RPC_SPECIAL_TRANSACT_GET_SESSION_ID,
+            Parcel::new().borrowed_ref(),
+            0,
+        )?;
+        let id: Vec<u8> = reply.read()?;
+
+        let (outgoing_threads, incoming_threads) = {
+            let mut config = self.config.lock().unwrap();
+            config.id = id.clone();
+            (config.max_outgoing_threads.min(max_threads as usize), config.max_incoming_threads)
+        };
+        for _ in 1..outgoing_threads {
+            let stream = self.init_connection(connect()?, &id, false)?;
+            self.add_outgoing_connection(stream)?;
+        }
+        for _ in 0..incoming_threads {
+            let stream = self.init_connection(connect()?, &id, true)?;
+            let session = self.clone();
+            let thread = thread::spawn(move || session.join(stream));
+            self.connections.lock().unwrap().threads.push(thread);
+        }
+        Ok(())
+    }
+
+    /// Send the connection header of a new client connection, and the
+    /// connection init if the connection is outgoing.
+    fn init_connection(
+        &self,
+        stream: UnixStream,
+        session_id: &[u8],
+        incoming: bool,
+    ) -> Result<Arc<UnixStream>, StatusCode> {
+        let header = RpcConnectionHeader {
+            version: self.config.lock().unwrap().protocol_version,
+            options: if incoming { RPC_CONNECTION_OPTION_INCOMING } else { 0 },
+            session_id_size: session_id.len().try_into().or(Err(StatusCode::BAD_VALUE))?,
+        };
+        let mut message = header.to_bytes();
+        message.extend_from_slice(session_id);
+        if !incoming {
+            RpcOutgoingConnectionInit.encode(&mut message);
+        }
+        (&stream).write_all(&message).map_err(status_from_io)?;
+        Ok(Arc::new(stream))
+    }
+
+    fn get_root_object(self: &Arc<Self>) -> Result<SpIBinder, StatusCode> {
+        let reply = self.transact_address(
+            0,
+            RPC_SPECIAL_TRANSACT_GET_ROOT,
+            Parcel::new().borrowed_ref(),
+            0,
+        )?;
+        reply.read::<Option<SpIBinder>>()?.ok_or(StatusCode::NAME_NOT_FOUND)
+    }
+
+    /// Add a connection on which this side sends transactions. A server sends
+    /// the connection init on the connections that clients open for incoming
+    /// transactions.
+    fn add_outgoing_connection(&self, stream: Arc<UnixStream>) -> Result<(), StatusCode> {
+        if self.for_server {
+            (&*stream).write_all(&RpcOutgoingConnectionInit.to_bytes()).map_err(status_from_io)?;
+        }
+        let mut connections = self.connections.lock().unwrap();
+        if connections.terminated {
+            return Err(StatusCode::DEAD_OBJECT);
+        }
+        connections.slots.push(ConnectionSlot {
+            stream,
+            incoming: false,
+            owner: None,
+            allow_nested: false,
+        });
+        self.connections_cv.notify_all();
+        Ok(())
+    }
+
+    /// Process the commands sent by the other side on an incoming connection,
+    /// until the session is terminated.
+    fn join(self: &Arc<Self>, stream: Arc<UnixStream>) {
+        {
+            let mut connections = self.connections.lock().unwrap();
+            if connections.terminated {
+                return;
+            }
+            connections.slots.push(ConnectionSlot {
+                stream: stream.clone(),
+                incoming: true,
+                owner: Some(thread::current().id()),
+                allow_nested: false,
+            });
+        }
+
+        let mut init = [0; RpcOutgoingConnectionInit::SIZE];
+        let mut result = self
+            .recv(&stream, &mut init)
+            .and_then(|()| RpcOutgoingConnectionInit::decode(&init).map(|_| ()));
+        while result.is_ok() {
+            result = self.recv_command(&stream).and_then(|command| match command {
+                RpcCommand::Reply(..) => Err(StatusCode::DEAD_OBJECT),
+                command => self.process_command(&stream, command),
+            });
+        }
+        // Like in `RpcState`, every error on a connection ends the session.
+        self.terminate();
+
+        let mut connections = self.connections.lock().unwrap();
+        connections.slots.retain(|slot| !Arc::ptr_eq(&slot.stream, &stream));
+        self.connections_cv.notify_all();
+    }
+
+    /// Shut down all connections and forget all binders, and return the
+    /// incoming threads to join.
+    fn terminate(&self) -> Vec<JoinHandle<()>> {
+        let threads = {
+            let mut connections = self.connections.lock().unwrap();
+            connections.terminated = true;
+            for slot in &connections.slots {
+                let _ = slot.stream.shutdown(Shutdown::Both);
+            }
+            self.connections_cv.notify_all();
+            std::mem::take(&mut connections.threads)
+        };
+        // Dropping the references may drop proxies, which lock the state.
+        let references = self.lock_state().terminate();
+        drop(references);
+        threads
+    }
+
+    fn find_connection(&self, usage: ConnectionUse) -> Result<ExclusiveConnection<'_>, StatusCode> {
+        let current = thread::current().id();
+        let mut connections = self.connections.lock().unwrap();
+        loop {
+            if connections.terminated {
+                return Err(StatusCode::DEAD_OBJECT);
+            }
+            let owned = |slot: &ConnectionSlot| slot.owner == Some(current);
+            let available = |slot: &ConnectionSlot| !slot.incoming && slot.owner.is_none();
+
+            // Like in `RpcSession`, a thread keeps using the outgoing
+            // connection it already uses, and sends nested transactions on
+            // the incoming connection of the transaction it is processing.
+            // Oneway transactions are never nested, and reference counts may
+            // be sent on the incoming connection so as not to wait for an
+            // outgoing one.
+            let mut exclusive = connections.slots.iter().find(|slot| owned(slot) && !slot.incoming);
+            if exclusive.is_none() && usage != ConnectionUse::ClientAsync {
+                exclusive = connections.slots.iter().find(|slot| {
+                    owned(slot)
+                        && slot.incoming
+                        && (slot.allow_nested
+                            || (usage == ConnectionUse::ClientRefcount
+                                && !connections.slots.iter().any(available)))
+                });
+            }
+            if let Some(slot) = exclusive {
+                return Ok(ExclusiveConnection {
+                    session: self,
+                    stream: slot.stream.clone(),
+                    reused: true,
+                });
+            }
+
+            if !connections.slots.iter().any(|slot| !slot.incoming) {
+                // A server can only send transactions which aren't nested in
+                // one of the client's if the client opened incoming
+                // connections.
+                return Err(StatusCode::WOULD_BLOCK);
+            }
+            if let Some(slot) = connections.slots.iter_mut().find(|slot| available(slot)) {
+                slot.owner = Some(current);
+                return Ok(ExclusiveConnection {
+                    session: self,
+                    stream: slot.stream.clone(),
+                    reused: false,
+                });
+            }
+            connections = self.connections_cv.wait(connections).unwrap();
+        }
+    }
+
+    fn set_allow_nested(&self, stream: &Arc<UnixStream>, allow_nested: bool) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        match connections.slots.iter_mut().find(|slot| Arc::ptr_eq(&slot.stream, stream)) {
+            Some(slot) => std::mem::replace(&mut slot.allow_nested, allow_nested),
+            None => false,
+        }
+    }
+
+    fn send(&self, stream: &UnixStream, bytes: &[u8]) -> Result<(), StatusCode> {
+        let mut stream = stream;
+        stream.write_all(bytes).map_err(|_| {
+            self.terminate();
+            StatusCode::DEAD_OBJECT
+        })
+    }
+
+    fn recv(&self, stream: &UnixStream, buffer: &mut [u8]) -> Result<(), StatusCode> {
+        let mut stream = stream;
+        stream.read_exact(buffer).map_err(|_| {
+            self.terminate();
+            StatusCode::DEAD_OBJECT
+        })
+    }
+
+    fn recv_command(&self, stream: &UnixStream) -> Result<RpcCommand, StatusCode> {
+        let mut header = [0; RpcWireHeader::SIZE];
+        self.recv(stream, &mut header)?;
+        let header = RpcWireHeader::decode(&header)?;
+        if header.body_size as usize > MAX_COMMAND_DATA {
+            self.terminate();
+            return Err(StatusCode::NO_MEMORY);
+        }
+
+        let mut body = Vec::new();
+        body.try_reserve_exact(header.body_size as usize).or(Err(StatusCode::NO_MEMORY))?;
+        body.resize(header.body_size as usize, 0);
+        self.recv(stream, &mut body)?;
+
+        RpcCommand::decode(&header, &body).inspect_err(|_| {
+            // The protocol is not self-synchronizing, so there is no way to
+            // find the next command.
+            self.terminate();
+        })
+    }
+
+    /// Send a transaction to the binder at `address`, and return the reply
+    /// marked for this session.
+    fn transact_address(
+        self: &Arc<Self>,
+        address: u64,
+        code: TransactionCode,
+        data: &BorrowedParcel<'_>,
+        flags: TransactionFlags,
+    ) -> Result<Parcel, StatusCode> {
+        let oneway = flags & FLAG_ONEWAY != 0;
+        let async_number =
+            if address != 0 && oneway { self.lock_state().next_async_number(address)? } else { 0 };
+        let transaction = RpcWireTransaction {
+            address: RpcWireAddress::from_raw(address),
+            code,
+            flags,
+            async_number,
+        };
+        let command = RpcCommand::Transact(transaction, data.marshal()?).encode()?;
+
+        let usage = if oneway { ConnectionUse::ClientAsync } else { ConnectionUse::Client };
+        let connection = self.find_connection(usage)?;
+        self.send(&connection.stream, &command)?;
+        if oneway {
+            return Ok(self.new_parcel());
+        }
+        loop {
+            match self.recv_command(&connection.stream)? {
+                RpcCommand::Reply(reply, data) => {
+                    status_result(reply.status)?;
+                    let mut reply = Parcel::unmarshal(&data)?;
+                    reply.mark_for_rpc(self.parcel_session());
+                    return Ok(reply);
+                }
+                command => self.process_command(&connection.stream, command)?,
+            }
+        }
+    }
+
+    fn process_command(
+        self: &Arc<Self>,
+        stream: &Arc<UnixStream>,
+        command: RpcCommand,
+    ) -> Result<(), StatusCode> {
+        match command {
+            RpcCommand::Transact(transaction, data) => {
+                self.process_transact(stream, transaction, data)
+            }
+            RpcCommand::DecStrong(dec_strong) => {
+                let released =
+                    self.lock_state().on_dec_strong(dec_strong.address.to_raw(), dec_strong.amount);
+                // Dropping the reference may drop a proxy, which locks the
+                // state.
+                drop(released);
+                Ok(())
+            }
+            RpcCommand::Reply(..) => {
+                self.terminate();
+                Err(StatusCode::DEAD_OBJECT)
+            }
+        }
+    }
+
+    fn process_transact(
+        self: &Arc<Self>,
+        stream: &Arc<UnixStream>,
+        transaction: RpcWireTransaction,
+        data: Vec<u8>,
+    ) -> Result<(), StatusCode> {
+        let address = transaction.address.to_raw();
+        let oneway = transaction.flags & FLAG_ONEWAY != 0;
+
+        let mut target = None;
+        let mut todo = Some((transaction, data));
+        if address != 0 {
+            target = match self.on_binder_entering(address) {
+                Ok(Some(object)) if object.downcast_ref::<RpcProxy>().is_none() => Some(object),
+                Ok(_) => {
+                    // Transactions can only be sent to live local objects, so
+                    // the other side is misbehaving.
+                    self.terminate();
+                    return Err(StatusCode::BAD_VALUE);
+                }
+                // Like in `RpcState`, failed oneway transactions are dropped.
+                Err(_) if oneway => return Ok(()),
+                Err(e) => return self.send_reply(stream, Err(e), None),
+            };
+            if oneway {
+                let todo_now = self.lock_state().enqueue_async(address, todo.take().unwrap());
+                match todo_now {
+                    Ok(Some(todo_now)) => todo = Some(todo_now),
+                    // Another oneway transaction must be processed first.
+                    Ok(None) => return Ok(()),
+                    Err(e) => {
+                        self.terminate();
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        while let Some((transaction, data)) = todo.take() {
+            let mut reply = self.new_parcel();
+            let result = Parcel::unmarshal(&data).and_then(|mut data| {
+                data.mark_for_rpc(self.parcel_session());
+                match &target {
+                    Some(object) => {
+                        let allow_nested = self.set_allow_nested(stream, !oneway);
+                        let result = object.transact(
+                            transaction.code,
+                            data.borrowed_ref(),
+                            &mut reply.borrowed(),
+                            transaction.flags,
+                        );
+                        self.set_allow_nested(stream, allow_nested);
+                        result
+                    }
+                    None => self.process_special_transact(transaction.code, &mut reply),
+                }
+            });
+
+            if !oneway {
+                if address != 0 && result.is_ok() {
+                    self.flush_excess_binder_refs(address)?;
+                }
+                return self.send_reply(stream, result, Some(reply));
+            }
+            if address != 0 {
+                match self.lock_state().progress_async(address) {
+                    Ok(next) => todo = next,
+                    Err(e) => {
+                        self.terminate();
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        // References received with oneway transactions are released once all
+        // queued transactions are processed.
+        if address != 0 {
+            self.flush_excess_binder_refs(address)?;
+        }
+        Ok(())
+    }
+
+    fn process_special_transact(
+        &self,
+        code: TransactionCode,
+        reply: &mut Parcel,
+    ) -> Result<(), StatusCode> {
+        match code {
+            RPC_SPECIAL_TRANSACT_GET_MAX_THREADS => {
+                let max_threads = self.config.lock().unwrap().max_incoming_threads;
+                reply.write(&i32::try_from(max_threads).unwrap_or(i32::MAX))
+            }
+            RPC_SPECIAL_TRANSACT_GET_SESSION_ID => reply.write(&self.config.lock().unwrap().id),
+            RPC_SPECIAL_TRANSACT_GET_ROOT if self.for_server => reply.write(&self.root),
+            _ if self.for_server => Err(StatusCode::UNKNOWN_TRANSACTION),
+            // Like in `RpcState`, a client replies to other special
+            // transactions without an error.
+            _ => Ok(()),
+        }
+    }
+
+    fn send_reply(
+        &self,
+        stream: &UnixStream,
+        result: Result<(), StatusCode>,
+        reply: Option<Parcel>,
+    ) -> Result<(), StatusCode> {
+        let status = match result {
+            Ok(()) => 0,
+            Err(e) => e as i32,
+        };
+        let data = match reply {
+            Some(reply) => reply.marshal()?,
+            None => Vec::new(),
+        };
+        let command = RpcCommand::Reply(RpcWireReply { status }, data).encode()?;
+        self.send(stream, &command)
+    }
+
+    fn on_binder_entering(self: &Arc<Self>, address: u64) -> Result<Option<SpIBinder>, StatusCode> {
+        let new_proxy = |id| SpIBinder::new(RpcProxy { session: self.clone(), address, id });
+        self.lock_state().on_binder_entering(address, self.for_server, new_proxy)
+    }
+
+    /// Release the references received with a local object right away, since
+    /// the object is kept alive by this side anyway.
+    fn flush_excess_binder_refs(&self, address: u64) -> Result<(), StatusCode> {
+        if self.lock_state().has_excess_local_refs(address) {
+            self.send_dec_strong_to_target(address, 0)?;
+        }
+        Ok(())
+    }
+
+    fn send_dec_strong_to_target(&self, address: u64, target: usize) -> Result<(), StatusCode> {
+        let amount = match self.lock_state().dec_strong_to_target(address, target) {
+            Some(amount) => amount,
+            None => return Ok(()),
+        };
+        let command = RpcCommand::DecStrong(RpcDecStrong {
+            address: RpcWireAddress::from_raw(address),
+            amount,
+        })
+        .encode()?;
+        let connection = self.find_connection(ConnectionUse::ClientRefcount)?;
+        self.send(&connection.stream, &command)
+    }
+}
@@ -0,0 +1,297 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Bookkeeping of the binders known to a session, equivalent to the node table
+//! of the C++ `RpcState`.
+//!
+//! Every binder sent over a session in either direction gets a node, keyed by
+//! its wire address. A node counts how many times the binder was sent to the
+//! other side (which keeps a local object alive until the other side sends a
+//! `DEC_STRONG` for it) and how many times it was received from the other side
+//! (which the proxy releases with a `DEC_STRONG` when it is dropped).
+
+use crate::session::RpcProxy;
+use crate::wire::{
+    RpcWireAddress, RpcWireTransaction, RPC_WIRE_ADDRESS_OPTION_CREATED,
+    RPC_WIRE_ADDRESS_OPTION_FOR_SERVER,
+};
+use binder::{SpIBinder, StatusCode, WpIBinder};
+use std::collections::{BTreeMap, HashMap};
+
+/// Maximum number of nodes in a session, like in `RpcState`, so that addresses
+/// can't run out.
+const MAX_NODES: usize = 100000;
+
+/// Number of queued oneway transactions on a single binder at which the
+/// session is terminated, like in `RpcState`.
+pub(crate) const MAX_PENDING_ONEWAY: usize = 10000;
+
+/// A oneway transaction received before the ones preceding it.
+pub(crate) type AsyncTodo = (RpcWireTransaction, Vec<u8>);
+
+struct BinderNode {
+    binder: WpIBinder,
+    /// The ID of the current proxy of the binder if it is hosted by the other
+    /// side, or `None` for a local object.
+    proxy_id: Option<u64>,
+    /// Strong reference held while the other side has references to the
+    /// binder, i.e. while `times_sent` is non-zero.
+    sent_ref: Option<SpIBinder>,
+    times_sent: usize,
+    times_recd: usize,
+    /// Number of the next oneway transaction sent to or processed by this
+    /// binder.
+    async_number: u64,
+    async_todo: BTreeMap<u64, AsyncTodo>,
+}
+
+/// The binders of a session.
+#[derive(Default)]
+pub(crate) struct RpcState {
+    nodes: HashMap<u64, BinderNode>,
+    next_id: u32,
+    next_proxy_id: u64,
+    terminated: bool,
+}
+
+impl RpcState {
+    /// Record that `binder` is being sent to the other side, and return its
+    /// address.
+    pub(crate) fn on_binder_leaving(
+        &mut self,
+        binder: &SpIBinder,
+        for_server: bool,
+    ) -> Result<u64, StatusCode> {
+        if self.terminated {
+            return Err(StatusCode::DEAD_OBJECT);
+        }
+
+        let weak = binder.downgrade();
+        if let Some((address, node)) = self.nodes.iter_mut().find(|(_, node)| node.binder == weak) {
+            node.times_sent += 1;
+            if node.sent_ref.is_none() {
+                node.sent_ref = Some(binder.clone());
+            }
+            return Ok(*address);
+        }
+        if binder.downcast_ref::<RpcProxy>().is_some() {
+            // Proxies always have a node while they are alive, unless the
+            // session was terminated.
+            return Err(StatusCode::DEAD_OBJECT);
+        }
+
+        if self.nodes.len() > MAX_NODES {
+            return Err(StatusCode::NO_MEMORY);
+        }
+        loop {
+            let mut address =
+                RpcWireAddress { options: RPC_WIRE_ADDRESS_OPTION_CREATED, address: self.next_id };
+            if for_server {
+                address.options |= RPC_WIRE_ADDRESS_OPTION_FOR_SERVER;
+            }
+            self.next_id = self.next_id.wrapping_add(1);
+
+            let address = address.to_raw();
+            if self.nodes.contains_key(&address) {
+                continue;
+            }
+            self.nodes.insert(
+                address,
+                BinderNode {
+                    binder: weak,
+                    proxy_id: None,
+                    sent_ref: Some(binder.clone()),
+                    times_sent: 1,
+                    times_recd: 0,
+                    async_number: 0,
+                    async_todo: BTreeMap::new(),
+                },
+            );
+            return Ok(address);
+        }
+    }
+
+    /// Record that the binder at `address` was received from the other side,
+    /// and return it. `new_proxy` creates the proxy with the given ID for a
+    /// binder which this side doesn't know yet.
+    ///
+    /// Returns `None` if the binder is a local object which was already
+    /// destroyed.
+    pub(crate) fn on_binder_entering(
+        &mut self,
+        address: u64,
+        for_server: bool,
+        new_proxy: impl FnOnce(u64) -> SpIBinder,
+    ) -> Result<Option<SpIBinder>, StatusCode> {
+        let options = RpcWireAddress::from_raw(address).options;
+        let known_options = RPC_WIRE_ADDRESS_OPTION_CREATED | RPC_WIRE_ADDRESS_OPTION_FOR_SERVER;
+        if options & !known_options != 0 {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        if self.terminated {
+            return Err(StatusCode::DEAD_OBJECT);
+        }
+        let proxy_id = self.next_proxy_id;
+
+        if let Some(node) = self.nodes.get_mut(&address) {
+            node.times_recd += 1;
+            if let Some(binder) = node.binder.promote() {
+                return Ok(Some(binder));
+            }
+            if node.proxy_id.is_none() {
+                return Ok(None);
+            }
+            // The last proxy is being dropped on another thread. It only
+            // releases its references if it is still the proxy of this node,
+            // so replace it with a new one which takes over the count.
+            let binder = new_proxy(proxy_id);
+            node.binder = binder.downgrade();
+            node.proxy_id = Some(proxy_id);
+            self.next_proxy_id += 1;
+            return Ok(Some(binder));
+        }
+
+        // The other side must have created a binder which we don't know yet.
+        if (options & RPC_WIRE_ADDRESS_OPTION_FOR_SERVER != 0) == for_server {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        let binder = new_proxy(proxy_id);
+        self.next_proxy_id += 1;
+        self.nodes.insert(
+            address,
+            BinderNode {
+                binder: binder.downgrade(),
+                proxy_id: Some(proxy_id),
+                sent_ref: None,
+                times_sent: 0,
+                times_recd: 1,
+                async_number: 0,
+                async_todo: BTreeMap::new(),
+            },
+        );
+        Ok(Some(binder))
+    }
+
+    /// Whether the binder at `address` is a local object which was received
+    /// back from the other side, so that the references received with it
+    /// should be released right away.
+    pub(crate) fn has_excess_local_refs(&self, address: u64) -> bool {
+        self.nodes.get(&address).is_some_and(|node| node.proxy_id.is_none() && node.times_recd != 0)
+    }
+
+    /// Lower the number of references received for the binder at `address` to
+    /// `target`, and return how many references the other side should be told
+    /// to release, if any.
+    pub(crate) fn dec_strong_to_target(&mut self, address: u64, target: usize) -> Option<u32> {
+        if self.terminated {
+            return None;
+        }
+        let node = self.nodes.get_mut(&address)?;
+        if node.times_recd <= target {
+            return None;
+        }
+        let amount = node.times_recd - target;
+        node.times_recd = target;
+        // The node can't have a sent reference here, so nothing is released.
+        let _ = self.try_erase_node(address);
+        Some(amount.try_into().unwrap_or(u32::MAX))
+    }
+
+    /// Only for `RpcProxy::drop`: whether the proxy with `proxy_id` is still
+    /// the proxy of the binder at `address`.
+    pub(crate) fn is_proxy_of(&self, address: u64, proxy_id: u64) -> bool {
+        self.nodes.get(&address).is_some_and(|node| node.proxy_id == Some(proxy_id))
+    }
+
+    /// Process a `DEC_STRONG` from the other side, and return the reference
+    /// which it released, if any. The reference must be dropped after the
+    /// state is unlocked, since dropping a proxy may send a command.
+    pub(crate) fn on_dec_strong(&mut self, address: u64, amount: u32) -> Option<SpIBinder> {
+        let node = self.nodes.get_mut(&address)?;
+        let amount = amount as usize;
+        if node.times_sent < amount {
+            return None;
+        }
+        node.times_sent -= amount;
+        self.try_erase_node(address)
+    }
+
+    fn try_erase_node(&mut self, address: u64) -> Option<SpIBinder> {
+        let node = self.nodes.get_mut(&address)?;
+        if node.times_sent != 0 {
+            return None;
+        }
+        let sent_ref = node.sent_ref.take();
+        if node.times_recd == 0 {
+            self.nodes.remove(&address);
+        }
+        sent_ref
+    }
+
+    /// Return the number of an outgoing oneway transaction to the binder at
+    /// `address`.
+    pub(crate) fn next_async_number(&mut self, address: u64) -> Result<u64, StatusCode> {
+        if self.terminated {
+            return Err(StatusCode::DEAD_OBJECT);
+        }
+        let node = self.nodes.get_mut(&address).ok_or(StatusCode::DEAD_OBJECT)?;
+        let async_number = node.async_number;
+        node.async_number = async_number.checked_add(1).ok_or(StatusCode::DEAD_OBJECT)?;
+        Ok(async_number)
+    }
+
+    /// Queue an incoming oneway transaction unless it is the next one to be
+    /// processed by its binder, in which case it is returned.
+    pub(crate) fn enqueue_async(
+        &mut self,
+        address: u64,
+        todo: AsyncTodo,
+    ) -> Result<Option<AsyncTodo>, StatusCode> {
+        let node = self.nodes.get_mut(&address).ok_or(StatusCode::BAD_VALUE)?;
+        if todo.0.async_number == node.async_number {
+            return Ok(Some(todo));
+        }
+        if node.async_todo.len() >= MAX_PENDING_ONEWAY {
+            return Err(StatusCode::FAILED_TRANSACTION);
+        }
+        node.async_todo.insert(todo.0.async_number, todo);
+        Ok(None)
+    }
+
+    /// Record that an incoming oneway transaction to the binder at `address`
+    /// was processed, and return the next one if it was already received.
+    pub(crate) fn progress_async(&mut self, address: u64) -> Result<Option<AsyncTodo>, StatusCode> {
+        // The last reference may have been released during the transaction.
+        let node = match self.nodes.get_mut(&address) {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+        node.async_number = node.async_number.checked_add(1).ok_or(StatusCode::DEAD_OBJECT)?;
+        Ok(node.async_todo.remove(&node.async_number))
+    }
+
+    /// Forget all binders, and return the references held by the state so that
+    /// they can be dropped after it is unlocked.
+    pub(crate) fn terminate(&mut self) -> Vec<SpIBinder> {
+        self.terminated = true;
+        self.nodes.drain().filter_map(|(_, node)| node.sent_ref).collect()
+    }
+
+    /// The number of binders known to the session.
+    pub(crate) fn count_binders(&self) -> usize {
+        self.nodes.len()
+    }
+}
@@ -0,0 +1,514 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Encoding of the binder RPC wire protocol, as defined in `RpcWireFormat.h`.
+//!
+//! Every struct is encoded with the layout of its C++ counterpart in little
+//! endian byte order, so reserved fields are written as zeroes and ignored when
+//! reading. Everything that `RpcState` sends after the connection is set up is
+//! a [`RpcWireHeader`] followed by the body of the command it designates, which
+//! [`RpcCommand`] encodes and decodes as a whole.
+//!
+//! This only covers the framing of the protocol. The parcels carried by
+//! transactions and replies are opaque bytes here, and the binders inside them
+//! are tracked by [`RpcSession`](crate::RpcSession).
+
+use binder::StatusCode;
+
+/// The wire protocol version of this implementation.
+pub const RPC_WIRE_PROTOCOL_VERSION: u32 = 0;
+/// The wire protocol version under development.
+pub const RPC_WIRE_PROTOCOL_VERSION_NEXT: u32 = 1;
+/// The version used to test features of the next wire protocol version.
+pub const RPC_WIRE_PROTOCOL_VERSION_EXPERIMENTAL: u32 = 0xF0000000;
+
+/// [`RpcConnectionHeader::options`] flag for a connection that the server uses
+/// to send transactions to the client. Connections are outgoing by default.
+pub const RPC_CONNECTION_OPTION_INCOMING: u8 = 0x1;
+
+/// [`RpcWireAddress::options`] flag set on every valid address, to distinguish
+/// it from a zero address.
+pub const RPC_WIRE_ADDRESS_OPTION_CREATED: u32 = 1 << 0;
+/// [`RpcWireAddress::options`] flag for an address allocated by the server.
+pub const RPC_WIRE_ADDRESS_OPTION_FOR_SERVER: u32 = 1 << 1;
+
+/// The message of [`RpcOutgoingConnectionInit`].
+pub const RPC_CONNECTION_INIT_OKAY: [u8; 4] = *b"cci\0";
+
+/// Command for a [`RpcWireTransaction`].
+pub const RPC_COMMAND_TRANSACT: u32 = 0;
+/// Command for a [`RpcWireReply`].
+pub const RPC_COMMAND_REPLY: u32 = 1;
+/// Command for a [`RpcDecStrong`].
+pub const RPC_COMMAND_DEC_STRONG: u32 = 2;
+
+/// Special transaction code, sent to a zero address, to get the root object.
+pub const RPC_SPECIAL_TRANSACT_GET_ROOT: u32 = 0;
+/// Special transaction code, sent to a zero address, to get the thread count
+/// of the server.
+pub const RPC_SPECIAL_TRANSACT_GET_MAX_THREADS: u32 = 1;
+/// Special transaction code, sent to a zero address, to get the session ID.
+pub const RPC_SPECIAL_TRANSACT_GET_SESSION_ID: u32 = 2;
+
+/// A struct with a fixed-size encoding in the wire protocol.
+pub trait WireStruct: Sized {
+    /// Size of the encoded struct in bytes, i.e. `sizeof` the C++ struct.
+    const SIZE: usize;
+
+    /// Append the encoded struct to `out`.
+    fn encode(&self, out: &mut Vec<u8>);
+
+    /// Decode the struct from the start of `bytes`, which may be longer than
+    /// [`Self::SIZE`].
+    ///
+    /// Returns `NOT_ENOUGH_DATA` if `bytes` is too short.
+    fn decode(bytes: &[u8]) -> Result<Self, StatusCode>;
+
+    /// Encode the struct into a new buffer.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(Self::SIZE);
+        self.encode(&mut out);
+        out
+    }
+}
+
+fn check_size(bytes: &[u8], size: usize) -> Result<(), StatusCode> {
+    if bytes.len() < size {
+        Err(StatusCode::NOT_ENOUGH_DATA)
+    } else {
+        Ok(())
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// The address of a binder object within a session.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RpcWireAddress {
+    /// `RPC_WIRE_ADDRESS_OPTION_*` flags.
+    pub options: u32,
+    /// Address allocated by the side of the session which owns the object.
+    pub address: u32,
+}
+
+impl RpcWireAddress {
+    /// Convert from the 64-bit representation used in parcels and by
+    /// `RpcState`.
+    pub fn from_raw(raw: u64) -> Self {
+        Self { options: raw as u32, address: (raw >> 32) as u32 }
+    }
+
+    /// Convert to the 64-bit representation used in parcels and by `RpcState`.
+    pub fn to_raw(self) -> u64 {
+        u64::from(self.options) | (u64::from(self.address) << 32)
+    }
+}
+
+impl WireStruct for RpcWireAddress {
+    const SIZE: usize = 8;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_raw().to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, StatusCode> {
+        check_size(bytes, Self::SIZE)?;
+        Ok(Self::from_raw(u64_at(bytes, 0)))
+    }
+}
+
+/// Sent by a client to request a new connection, either for a new session or
+/// for an existing one. It is followed by `session_id_size` bytes of session
+/// ID, and requests a new session if the size is zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RpcConnectionHeader {
+    /// Maximum protocol version supported by the client.
+    pub version: u32,
+    /// `RPC_CONNECTION_OPTION_*` flags.
+    pub options: u8,
+    /// Size of the session ID which follows this header.
+    pub session_id_size: u16,
+}
+
+impl WireStruct for RpcConnectionHeader {
+    const SIZE: usize = 16;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.push(self.options);
+        out.extend_from_slice(&[0; 9]);
+        out.extend_from_slice(&self.session_id_size.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, StatusCode> {
+        check_size(bytes, Self::SIZE)?;
+        Ok(Self {
+            version: u32_at(bytes, 0),
+            options: bytes[4],
+            session_id_size: u16_at(bytes, 14),
+        })
+    }
+}
+
+/// Sent by the server in response to a [`RpcConnectionHeader`] which requests
+/// a new session.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RpcNewSessionResponse {
+    /// Protocol version of the session, which is at most the version requested
+    /// by the client.
+    pub version: u32,
+}
+
+impl WireStruct for RpcNewSessionResponse {
+    const SIZE: usize = 8;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, StatusCode> {
+        check_size(bytes, Self::SIZE)?;
+        Ok(Self { version: u32_at(bytes, 0) })
+    }
+}
+
+/// Sent by a client as the first message on every outgoing connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RpcOutgoingConnectionInit;
+
+impl WireStruct for RpcOutgoingConnectionInit {
+    const SIZE: usize = 8;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&RPC_CONNECTION_INIT_OKAY);
+        out.extend_from_slice(&[0; 4]);
+    }
+
+    /// Returns `BAD_VALUE` if the message is not [`RPC_CONNECTION_INIT_OKAY`].
+    fn decode(bytes: &[u8]) -> Result<Self, StatusCode> {
+        check_size(bytes, Self::SIZE)?;
+        if bytes[..4] == RPC_CONNECTION_INIT_OKAY {
+            Ok(Self)
+        } else {
+            Err(StatusCode::BAD_VALUE)
+        }
+    }
+}
+
+/// Header of every command, followed by `body_size` bytes of the struct
+/// designated by `command`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RpcWireHeader {
+    /// One of the `RPC_COMMAND_*` constants.
+    pub command: u32,
+    /// Size of the body which follows this header.
+    pub body_size: u32,
+}
+
+impl WireStruct for RpcWireHeader {
+    const SIZE: usize = 16;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.command.to_le_bytes());
+        out.extend_from_slice(&self.body_size.to_le_bytes());
+        out.extend_from_slice(&[0; 8]);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, StatusCode> {
+        check_size(bytes, Self::SIZE)?;
+        Ok(Self { command: u32_at(bytes, 0), body_size: u32_at(bytes, 4) })
+    }
+}
+
+/// Body of [`RPC_COMMAND_DEC_STRONG`], which releases strong references to a
+/// binder held by the sender.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RpcDecStrong {
+    /// Address of the binder.
+    pub address: RpcWireAddress,
+    /// Number of strong references released.
+    pub amount: u32,
+}
+
+impl WireStruct for RpcDecStrong {
+    const SIZE: usize = 16;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.address.encode(out);
+        out.extend_from_slice(&self.amount.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, StatusCode> {
+        check_size(bytes, Self::SIZE)?;
+        Ok(Self { address: RpcWireAddress::decode(bytes)?, amount: u32_at(bytes, 8) })
+    }
+}
+
+/// Body of [`RPC_COMMAND_TRANSACT`], followed by the parcel data of the
+/// transaction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RpcWireTransaction {
+    /// Address of the target binder, or zero for a special transaction.
+    pub address: RpcWireAddress,
+    /// Transaction code, or one of the `RPC_SPECIAL_TRANSACT_*` constants.
+    pub code: u32,
+    /// Transaction flags, e.g. `FLAG_ONEWAY`.
+    pub flags: u32,
+    /// Sequence number of oneway transactions to the same binder, which are
+    /// processed in this order.
+    pub async_number: u64,
+}
+
+impl WireStruct for RpcWireTransaction {
+    const SIZE: usize = 40;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.address.encode(out);
+        out.extend_from_slice(&self.code.to_le_bytes());
+        out.extend_from_slice(&self.flags.to_le_bytes());
+        out.extend_from_slice(&self.async_number.to_le_bytes());
+        out.extend_from_slice(&[0; 16]);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, StatusCode> {
+        check_size(bytes, Self::SIZE)?;
+        Ok(Self {
+            address: RpcWireAddress::decode(bytes)?,
+            code: u32_at(bytes, 8),
+            flags: u32_at(bytes, 12),
+            async_number: u64_at(bytes, 16),
+        })
+    }
+}
+
+/// Body of [`RPC_COMMAND_REPLY`], followed by the parcel data of the reply.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RpcWireReply {
+    /// The `status_t` returned by the transaction.
+    pub status: i32,
+}
+
+impl WireStruct for RpcWireReply {
+    const SIZE: usize = 4;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.status.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, StatusCode> {
+        check_size(bytes, Self::SIZE)?;
+        Ok(Self { status: u32_at(bytes, 0) as i32 })
+    }
+}
+
+/// A command sent on an established connection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RpcCommand {
+    /// A transaction and its parcel data.
+    Transact(RpcWireTransaction, Vec<u8>),
+    /// A reply and its parcel data.
+    Reply(RpcWireReply, Vec<u8>),
+    /// A release of strong references.
+    DecStrong(RpcDecStrong),
+}
+
+impl RpcCommand {
+    /// Encode the command, including its [`RpcWireHeader`].
+    ///
+    /// Returns `BAD_VALUE` if the parcel data is too large for the header.
+    pub fn encode(&self) -> Result<Vec<u8>, StatusCode> {
+        let (command, body_size) = match self {
+            Self::Transact(_, data) => {
+                (RPC_COMMAND_TRANSACT, RpcWireTransaction::SIZE + data.len())
+            }
+            Self::Reply(_, data) => (RPC_COMMAND_REPLY, RpcWireReply::SIZE + data.len()),
+            Self::DecStrong(_) => (RPC_COMMAND_DEC_STRONG, RpcDecStrong::SIZE),
+        };
+        let header = RpcWireHeader {
+            command,
+            body_size: body_size.try_into().or(Err(StatusCode::BAD_VALUE))?,
+        };
+
+        let mut out = Vec::with_capacity(RpcWireHeader::SIZE + body_size);
+        header.encode(&mut out);
+        match self {
+            Self::Transact(transaction, data) => {
+                transaction.encode(&mut out);
+                out.extend_from_slice(data);
+            }
+            Self::Reply(reply, data) => {
+                reply.encode(&mut out);
+                out.extend_from_slice(data);
+            }
+            Self::DecStrong(dec_strong) => dec_strong.encode(&mut out),
+        }
+        Ok(out)
+    }
+
+    /// Decode a command from its header and a body of `header.body_size`
+    /// bytes.
+    ///
+    /// Like `RpcState`, this returns `BAD_VALUE` if the body is too small for
+    /// the command, and `DEAD_OBJECT` if the command is unknown, in which case
+    /// the session should be terminated.
+    pub fn decode(header: &RpcWireHeader, body: &[u8]) -> Result<Self, StatusCode> {
+        if body.len() != header.body_size as usize {
+            return Err(StatusCode::NOT_ENOUGH_DATA);
+        }
+        match header.command {
+            RPC_COMMAND_TRANSACT => {
+                let transaction =
+                    RpcWireTransaction::decode(body).or(Err(StatusCode::BAD_VALUE))?;
+                Ok(Self::Transact(transaction, body[RpcWireTransaction::SIZE..].to_vec()))
+            }
+            RPC_COMMAND_REPLY => {
+                let reply = RpcWireReply::decode(body).or(Err(StatusCode::BAD_VALUE))?;
+                Ok(Self::Reply(reply, body[RpcWireReply::SIZE..].to_vec()))
+            }
+            RPC_COMMAND_DEC_STRONG if body.len() == RpcDecStrong::SIZE => {
+                Ok(Self::DecStrong(RpcDecStrong::decode(body)?))
+            }
+            RPC_COMMAND_DEC_STRONG => Err(StatusCode::BAD_VALUE),
+            _ => Err(StatusCode::DEAD_OBJECT),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn struct_sizes() {
+        assert_eq!(RpcWireAddress::default().to_bytes().len(), RpcWireAddress::SIZE);
+        assert_eq!(RpcConnectionHeader::default().to_bytes().len(), RpcConnectionHeader::SIZE);
+        assert_eq!(RpcNewSessionResponse::default().to_bytes().len(), RpcNewSessionResponse::SIZE);
+        assert_eq!(RpcOutgoingConnectionInit.to_bytes().len(), RpcOutgoingConnectionInit::SIZE);
+        assert_eq!(RpcWireHeader::default().to_bytes().len(), RpcWireHeader::SIZE);
+        assert_eq!(RpcDecStrong::default().to_bytes().len(), RpcDecStrong::SIZE);
+        assert_eq!(RpcWireTransaction::default().to_bytes().len(), RpcWireTransaction::SIZE);
+        assert_eq!(RpcWireReply::default().to_bytes().len(), RpcWireReply::SIZE);
+    }
+
+    #[test]
+    fn connection_setup() {
+        let header = RpcConnectionHeader {
+            version: RPC_WIRE_PROTOCOL_VERSION_EXPERIMENTAL,
+            options: RPC_CONNECTION_OPTION_INCOMING,
+            session_id_size: 32,
+        };
+        let bytes = header.to_bytes();
+        assert_eq!(hex(&bytes), "000000f0010000000000000000002000");
+        assert_eq!(RpcConnectionHeader::decode(&bytes), Ok(header));
+
+        assert_eq!(hex(&RpcNewSessionResponse { version: 1 }.to_bytes()), "0100000000000000");
+
+        let init = RpcOutgoingConnectionInit.to_bytes();
+        assert_eq!(hex(&init), "6363690000000000");
+        assert_eq!(RpcOutgoingConnectionInit::decode(&init), Ok(RpcOutgoingConnectionInit));
+        assert_eq!(RpcOutgoingConnectionInit::decode(b"ccx\0\0\0\0\0"), Err(StatusCode::BAD_VALUE));
+    }
+
+    #[test]
+    fn address_raw() {
+        let address = RpcWireAddress {
+            options: RPC_WIRE_ADDRESS_OPTION_CREATED | RPC_WIRE_ADDRESS_OPTION_FOR_SERVER,
+            address: 5,
+        };
+        assert_eq!(address.to_raw(), 0x0000_0005_0000_0003);
+        assert_eq!(RpcWireAddress::from_raw(address.to_raw()), address);
+        assert_eq!(hex(&address.to_bytes()), "0300000005000000");
+    }
+
+    #[test]
+    fn transact_command() {
+        let command = RpcCommand::Transact(
+            RpcWireTransaction {
+                address: RpcWireAddress { options: RPC_WIRE_ADDRESS_OPTION_CREATED, address: 2 },
+                code: 1,
+                flags: 1,
+                async_number: 7,
+            },
+            vec![0x11, 0x00, 0x00, 0x00],
+        );
+        let bytes = command.encode().unwrap();
+        assert_eq!(
+            hex(&bytes),
+            concat!(
+                "000000002c0000000000000000000000",
+                "010000000200000001000000010000000700000000000000",
+                "00000000000000000000000000000000",
+                "11000000",
+            )
+        );
+
+        let header = RpcWireHeader::decode(&bytes).unwrap();
+        assert_eq!(header, RpcWireHeader { command: RPC_COMMAND_TRANSACT, body_size: 44 });
+        assert_eq!(RpcCommand::decode(&header, &bytes[RpcWireHeader::SIZE..]), Ok(command));
+    }
+
+    #[test]
+    fn reply_and_dec_strong_commands() {
+        let reply = RpcCommand::Reply(RpcWireReply { status: -32 }, vec![]);
+        let bytes = reply.encode().unwrap();
+        assert_eq!(hex(&bytes), concat!("01000000040000000000000000000000", "e0ffffff"));
+        let header = RpcWireHeader::decode(&bytes).unwrap();
+        assert_eq!(RpcCommand::decode(&header, &bytes[RpcWireHeader::SIZE..]), Ok(reply));
+
+        let dec_strong = RpcCommand::DecStrong(RpcDecStrong {
+            address: RpcWireAddress { options: RPC_WIRE_ADDRESS_OPTION_CREATED, address: 9 },
+            amount: 2,
+        });
+        let bytes = dec_strong.encode().unwrap();
+        assert_eq!(
+            hex(&bytes),
+            concat!("02000000100000000000000000000000", "01000000090000000200000000000000")
+        );
+        let header = RpcWireHeader::decode(&bytes).unwrap();
+        assert_eq!(RpcCommand::decode(&header, &bytes[RpcWireHeader::SIZE..]), Ok(dec_strong));
+    }
+
+    #[test]
+    fn invalid_commands() {
+        let short = RpcWireHeader { command: RPC_COMMAND_TRANSACT, body_size: 4 };
+        assert_eq!(RpcCommand::decode(&short, &[0; 4]), Err(StatusCode::BAD_VALUE));
+
+        let long = RpcWireHeader { command: RPC_COMMAND_DEC_STRONG, body_size: 20 };
+        assert_eq!(RpcCommand::decode(&long, &[0; 20]), Err(StatusCode::BAD_VALUE));
+
+        let unknown = RpcWireHeader { command: 3, body_size: 0 };
+        assert_eq!(RpcCommand::decode(&unknown, &[]), Err(StatusCode::DEAD_OBJECT));
+
+        let truncated = RpcWireHeader { command: RPC_COMMAND_REPLY, body_size: 8 };
+        assert_eq!(RpcCommand::decode(&truncated, &[0; 4]), Err(StatusCode::NOT_ENOUGH_DATA));
+    }
+}
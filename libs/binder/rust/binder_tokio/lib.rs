@@ -26,11 +26,28 @@
 //! binder::get_interface::<dyn SomeAsyncInterface<Tokio>>("...").
 //! ```
 //!
+//! Incoming transactions to async native binders are still handled by the
+//! binder thread pool, which blocks on their futures with [`TokioRuntime`].
+//! They can't run as ordinary tasks on the Tokio reactor instead: the binder
+//! driver expects the reply to a transaction from the thread that received it,
+//! and a thread handling nested transactions must reply to them in the reverse
+//! order of their arrival. A thread therefore can't go back to the driver for
+//! more commands while a handler is pending, so a reactor polling the driver
+//! fd would block on every transaction just like the thread pool does.
+//!
 //! [`Tokio`]: crate::Tokio
 
-use binder::{BinderAsyncPool, BoxFuture, FromIBinder, StatusCode, Strong};
+use binder::{BinderAsyncPool, BoxFuture, FromIBinder, SpIBinder, Status, StatusCode, Strong};
 use binder::binder_impl::BinderAsyncRuntime;
+use binder::service_manager::ServiceNotificationRegistration;
 use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::sync::oneshot;
+use tokio_stream::Stream;
 
 /// Retrieve an existing service for a particular interface, sleeping for a few
 /// seconds if it doesn't yet exist.
@@ -80,6 +97,117 @@ pub async fn wait_for_interface<T: FromIBinder + ?Sized + 'static>(name: &str) -
     }
 }
 
+/// A stream of the binders of a service, yielding a new binder whenever the
+/// service is registered with the service manager.
+///
+/// Created by [`service_notifications`]. Dropping the stream unregisters it
+/// from the service manager.
+pub struct ServiceNotifications {
+    receiver: UnboundedReceiver<SpIBinder>,
+    _registration: ServiceNotificationRegistration,
+}
+
+impl Stream for ServiceNotifications {
+    type Item = SpIBinder;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<SpIBinder>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Get a stream of the binders of a service, which yields the current binder
+/// first if the service is already registered, and then a new binder each time
+/// the service is registered again, e.g. after it restarts.
+///
+/// Notifications are delivered on a binder thread, so the binder thread pool
+/// must be started with `binder::ProcessState::start_thread_pool`.
+pub async fn service_notifications(name: &str) -> Result<ServiceNotifications, Status> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let register = move |name: &str| {
+        binder::service_manager::register_for_notifications(name, move |_, binder| {
+            // The stream may have been dropped before it is unregistered.
+            let _ = sender.send(binder);
+        })
+    };
+
+    let res = if binder::is_handling_transaction() {
+        // See comment in the BinderAsyncPool impl.
+        register(name)
+    } else {
+        let name = name.to_string();
+        let res = tokio::task::spawn_blocking(move || register(&name)).await;
+
+        // The `is_panic` branch is not actually reachable in Android as we compile
+        // with `panic = abort`.
+        match res {
+            Ok(res) => res,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(e) if e.is_cancelled() => Err(StatusCode::FAILED_TRANSACTION.into()),
+            Err(_) => Err(StatusCode::UNKNOWN_ERROR.into()),
+        }
+    };
+
+    Ok(ServiceNotifications { receiver, _registration: res? })
+}
+
+/// Wait until the process hosting `binder` dies.
+///
+/// The death notification is linked when this is called, so deaths before the
+/// future is first polled are not missed, and it is unlinked when the future is
+/// dropped. The future completes immediately if the process is already dead,
+/// and never completes for local binders, which can't die while this process
+/// is alive.
+///
+/// Notifications are delivered on a binder thread, so the binder thread pool
+/// must be started with `binder::ProcessState::start_thread_pool`.
+pub fn death(binder: &SpIBinder) -> impl Future<Output = ()> + Send + 'static {
+    let (sender, receiver) = oneshot::channel();
+    let sender = Mutex::new(Some(sender));
+    let link = binder.watch_death(move || {
+        if let Some(sender) = sender.lock().unwrap().take() {
+            // The future may have been dropped before it is unlinked.
+            let _ = sender.send(());
+        }
+    });
+
+    async move {
+        match link {
+            Ok(_link) => {
+                // The sender is owned by the link, so it can't be dropped while
+                // we are waiting.
+                let _ = receiver.await;
+            }
+            Err(StatusCode::DEAD_OBJECT) => {}
+            Err(_) => std::future::pending().await,
+        }
+    }
+}
+
+/// Wait for a binder call made with [`Tokio`], failing with `TIMED_OUT` if it
+/// doesn't complete within `duration`.
+///
+/// This is [`tokio::time::timeout`] with the error mapped to a binder status.
+/// The transaction is made on the `spawn_blocking` pool, so when it times out
+/// the blocking task still waits for the reply, which is discarded when it
+/// arrives. Calls made while handling a transaction run on the current thread
+/// before the future is returned, so they can't time out.
+///
+/// # Examples
+///
+/// ```ignore
+/// let name = binder_tokio::timeout(Duration::from_millis(500), foo.get_name()).await?;
+/// ```
+pub async fn timeout<F, T, E>(duration: Duration, future: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+    E: From<StatusCode>,
+{
+    match tokio::time::timeout(duration, future).await {
+        Ok(result) => result,
+        Err(_) => Err(StatusCode::TIMED_OUT.into()),
+    }
+}
+
 /// Use the Tokio `spawn_blocking` pool with AIDL.
 pub enum Tokio {}
 
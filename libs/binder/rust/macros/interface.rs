@@ -0,0 +1,445 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Attribute macro for binder interfaces defined in Rust.
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::spanned::Spanned;
+use syn::{
+    parse_quote, Error, FnArg, GenericArgument, Ident, ItemTrait, LitStr, PathArguments,
+    Result, ReturnType, Token, TraitItem, TraitItemFn, Type, TypeParamBound,
+};
+
+/// Arguments of the `#[interface(...)]` attribute.
+pub(crate) struct InterfaceArgs {
+    descriptor: LitStr,
+    async_interface: Option<Ident>,
+}
+
+impl Parse for InterfaceArgs {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let descriptor = input.parse()?;
+        let mut async_interface = None;
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+            let key: Ident = input.call(syn::ext::IdentExt::parse_any)?;
+            input.parse::<Token![=]>()?;
+            if key == "async" {
+                async_interface = Some(input.parse()?);
+            } else {
+                return Err(Error::new(key.span(), "expected `async = <trait name>`"));
+            }
+        }
+        Ok(Self { descriptor, async_interface })
+    }
+}
+
+/// How an argument is read from the parcel on the native side and passed to
+/// the service.
+enum ArgKind {
+    /// Passed by value.
+    Value(Type),
+    /// Passed by reference, read as the given owned type.
+    Ref(Type),
+}
+
+struct Method {
+    name: Ident,
+    code: Ident,
+    oneway: bool,
+    args: Vec<(Ident, ArgKind)>,
+    /// The argument types as declared in the trait.
+    arg_types: Vec<Type>,
+    ret: Type,
+    returns_unit: bool,
+}
+
+impl Method {
+    fn parse(item: &mut TraitItemFn) -> Result<Self> {
+        let sig = &item.sig;
+        if !sig.generics.params.is_empty() || sig.asyncness.is_some() {
+            return Err(Error::new(
+                sig.span(),
+                "binder interface methods can't be generic or async",
+            ));
+        }
+        let mut inputs = sig.inputs.iter();
+        match inputs.next() {
+            Some(FnArg::Receiver(r)) if r.reference.is_some() && r.mutability.is_none() => {}
+            _ => return Err(Error::new(sig.span(), "binder interface methods must take `&self`")),
+        }
+
+        let mut args = vec![];
+        let mut arg_types = vec![];
+        for (i, input) in inputs.enumerate() {
+            let ty = match input {
+                FnArg::Typed(pat) => &*pat.ty,
+                FnArg::Receiver(r) => return Err(Error::new(r.span(), "unexpected receiver")),
+            };
+            // The generated code uses its own argument names, so they can't
+            // clash with its local variables.
+            let name = format_ident!("arg{}", i);
+            let kind = match ty {
+                Type::Reference(r) if r.mutability.is_some() => {
+                    return Err(Error::new(ty.span(), "out parameters are not supported"));
+                }
+                Type::Reference(r) => ArgKind::Ref(owned_type(&r.elem)),
+                _ => ArgKind::Value(ty.clone()),
+            };
+            args.push((name, kind));
+            arg_types.push(ty.clone());
+        }
+
+        let ret = match &sig.output {
+            ReturnType::Type(_, ty) => (**ty).clone(),
+            ReturnType::Default => {
+                return Err(Error::new(
+                    sig.span(),
+                    "binder interface methods must return a `Result`",
+                ))
+            }
+        };
+        let returns_unit = match result_ok_type(&ret) {
+            Some(Type::Tuple(t)) => t.elems.is_empty(),
+            Some(_) => false,
+            None => {
+                return Err(Error::new(ret.span(), "binder interface methods must return a `Result`"))
+            }
+        };
+
+        let oneway = take_attr(&mut item.attrs, "oneway");
+        if oneway && !returns_unit {
+            return Err(Error::new(ret.span(), "oneway methods can't return a value"));
+        }
+
+        let name = sig.ident.clone();
+        let code = format_ident!("TRANSACTION_{}", name.to_string().to_uppercase());
+        Ok(Self { name, code, oneway, args, arg_types, ret, returns_unit })
+    }
+}
+
+/// The owned type to deserialize for a `&T` argument.
+fn owned_type(ty: &Type) -> Type {
+    match ty {
+        Type::Path(p) if p.qself.is_none() && p.path.is_ident("str") => parse_quote!(String),
+        Type::Slice(s) => {
+            let elem = &s.elem;
+            parse_quote!(Vec<#elem>)
+        }
+        _ => ty.clone(),
+    }
+}
+
+/// Returns `T` if `ty` looks like `Result<T, ...>`.
+fn result_ok_type(ty: &Type) -> Option<&Type> {
+    let segment = match ty {
+        Type::Path(p) => p.path.segments.last()?,
+        _ => return None,
+    };
+    if segment.ident != "Result" {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => match args.args.first()? {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Removes the attribute `#[name]` from `attrs`, returning whether it was
+/// present.
+fn take_attr(attrs: &mut Vec<syn::Attribute>, name: &str) -> bool {
+    let len = attrs.len();
+    attrs.retain(|attr| !attr.path().is_ident(name));
+    attrs.len() != len
+}
+
+/// The name of the native and proxy types: `IFoo` becomes `BnFoo` and `BpFoo`.
+fn binder_type_names(interface: &Ident) -> (Ident, Ident) {
+    let name = interface.to_string();
+    let mut chars = name.chars();
+    let base = match (chars.next(), chars.next()) {
+        (Some('I'), Some(c)) if c.is_uppercase() => &name[1..],
+        _ => &name[..],
+    };
+    (format_ident!("Bn{}", base), format_ident!("Bp{}", base))
+}
+
+pub(crate) fn interface(args: InterfaceArgs, mut item: ItemTrait) -> Result<TokenStream> {
+    if !item.generics.params.is_empty() {
+        return Err(Error::new(item.generics.span(), "binder interfaces can't be generic"));
+    }
+
+    let mut methods = vec![];
+    for trait_item in item.items.iter_mut() {
+        match trait_item {
+            TraitItem::Fn(f) => methods.push(Method::parse(f)?),
+            other => {
+                return Err(Error::new(other.span(), "binder interfaces can only contain methods"))
+            }
+        }
+    }
+
+    let has_interface_bound = item.supertraits.iter().any(|bound| match bound {
+        TypeParamBound::Trait(t) => t.path.segments.last().map_or(false, |s| s.ident == "Interface"),
+        _ => false,
+    });
+    if !has_interface_bound {
+        item.colon_token.get_or_insert_with(Default::default);
+        item.supertraits.push(parse_quote!(binder::Interface));
+    }
+
+    let interface = &item.ident;
+    let vis = &item.vis;
+    let descriptor = &args.descriptor;
+    let (native, proxy) = binder_type_names(interface);
+    let on_transact = format_ident!("__{}_on_transact", interface.to_string().to_lowercase());
+
+    let codes = methods.iter().enumerate().map(|(i, m)| {
+        let code = &m.code;
+        let doc = format!("Transaction code of [`{}::{}`].", interface, m.name);
+        quote! {
+            #[doc = #doc]
+            pub const #code: binder::binder_impl::TransactionCode =
+                binder::binder_impl::FIRST_CALL_TRANSACTION + #i as binder::binder_impl::TransactionCode;
+        }
+    });
+
+    let dispatch = methods.iter().map(|m| {
+        let code = &m.code;
+        let name = &m.name;
+        let reads = m.args.iter().map(|(arg, kind)| match kind {
+            ArgKind::Value(ty) | ArgKind::Ref(ty) => quote!(let #arg: #ty = data.read()?;),
+        });
+        let pass = m.args.iter().map(|(arg, kind)| match kind {
+            ArgKind::Value(_) => quote!(#arg),
+            ArgKind::Ref(_) => quote!(&#arg),
+        });
+        let call = quote!(service.#name(#(#pass),*)?);
+        let reply_write = if m.returns_unit {
+            quote!(#call; Ok(()))
+        } else {
+            quote!(reply.write(&#call))
+        };
+        quote! {
+            #native::#code => {
+                #(#reads)*
+                #reply_write
+            }
+        }
+    });
+
+    // The parcel only needs to be mutable if there are arguments to write.
+    let data_param = |m: &Method| {
+        if m.args.is_empty() {
+            quote!(data)
+        } else {
+            quote!(mut data)
+        }
+    };
+
+    let flags = |m: &Method| {
+        if m.oneway {
+            quote!(binder::binder_impl::FLAG_ONEWAY)
+        } else {
+            quote!(0)
+        }
+    };
+
+    let signature = |m: &Method, ret: TokenStream| {
+        let name = &m.name;
+        let params = m.args.iter().zip(&m.arg_types).map(|((arg, _), ty)| quote!(#arg: #ty));
+        quote!(fn #name(&self, #(#params),*) -> #ret)
+    };
+
+    let proxy_methods = methods.iter().map(|m| {
+        let code = &m.code;
+        let flags = flags(m);
+        let data_param = data_param(m);
+        let args = m.args.iter().map(|(arg, _)| arg);
+        let read_reply = if m.returns_unit || m.oneway {
+            quote!(let _ = reply; Ok(()))
+        } else {
+            quote!(Ok(reply.read()?))
+        };
+        let ret = &m.ret;
+        let sig = signature(m, quote!(#ret));
+        quote! {
+            #sig {
+                let reply = binder::binder_impl::IBinderInternal::transact(
+                    &self.binder,
+                    #native::#code,
+                    #flags,
+                    |#data_param| {
+                        #(data.write(&#args)?;)*
+                        Ok(())
+                    },
+                )?;
+                #read_reply
+            }
+        }
+    });
+
+    let native_methods = methods.iter().map(|m| {
+        let name = &m.name;
+        let args = m.args.iter().map(|(arg, _)| arg);
+        let ret = &m.ret;
+        let sig = signature(m, quote!(#ret));
+        quote! {
+            #sig {
+                self.0.#name(#(#args),*)
+            }
+        }
+    });
+
+    let async_items = args.async_interface.as_ref().map(|async_interface| {
+        let trait_doc = format!("Asynchronous version of [`{}`].", interface);
+        let async_sigs = methods.iter().map(|m| {
+            let ret = &m.ret;
+            let sig = signature(m, quote!(binder::BoxFuture<'static, #ret>));
+            quote!(#sig;)
+        });
+
+        let async_proxy_methods = methods.iter().map(|m| {
+            let ret = &m.ret;
+            let code = &m.code;
+            let flags = flags(m);
+            let data_param = data_param(m);
+            let args = m.args.iter().map(|(arg, _)| arg);
+            let sig = signature(m, quote!(binder::BoxFuture<'static, #ret>));
+            // The result type is spelled out because `?` can't infer the
+            // error type inside an async block.
+            let read_reply = if m.returns_unit || m.oneway {
+                quote! {
+                    let result: #ret = match reply {
+                        Ok(_) => Ok(()),
+                        Err(e) => Err(e.into()),
+                    };
+                    result
+                }
+            } else {
+                quote! {
+                    let result: #ret = match reply {
+                        Ok(reply) => reply.read().map_err(Into::into),
+                        Err(e) => Err(e.into()),
+                    };
+                    result
+                }
+            };
+            quote! {
+                #sig {
+                    use binder::binder_impl::IBinderInternal;
+                    // Write the arguments now, so that they don't need to be
+                    // moved to the thread that submits the transaction.
+                    let binder = self.binder.clone();
+                    let data = binder.prepare_transact().and_then(|#data_param| {
+                        #(data.write(&#args)?;)*
+                        Ok(data)
+                    });
+                    let data = match data {
+                        Ok(data) => data,
+                        Err(e) => {
+                            let result: #ret = Err(e.into());
+                            return Box::pin(std::future::ready(result));
+                        }
+                    };
+                    P::spawn(
+                        move || binder.submit_transact(#native::#code, data, #flags),
+                        |reply| async move { #read_reply },
+                    )
+                }
+            }
+        });
+
+        let async_native_methods = methods.iter().map(|m| {
+            let ret = &m.ret;
+            let name = &m.name;
+            let args = m.args.iter().map(|(arg, _)| arg);
+            let sig = signature(m, quote!(binder::BoxFuture<'static, #ret>));
+            quote! {
+                #sig {
+                    let res = self.0.#name(#(#args),*);
+                    Box::pin(async move { res })
+                }
+            }
+        });
+
+        quote! {
+            #[doc = #trait_doc]
+            #vis trait #async_interface<P>: binder::Interface {
+                #(#async_sigs)*
+            }
+
+            impl<P: binder::BinderAsyncPool> #async_interface<P> for #proxy {
+                #(#async_proxy_methods)*
+            }
+
+            impl<P: binder::BinderAsyncPool> #async_interface<P> for binder::binder_impl::Binder<#native> {
+                #(#async_native_methods)*
+            }
+        }
+    });
+    let async_decl = args.async_interface.as_ref().map(|i| quote!(async: #i,));
+    let transaction_names = methods.iter().map(|m| m.name.to_string());
+
+    Ok(quote! {
+        #item
+
+        binder::declare_binder_interface! {
+            #interface[#descriptor] {
+                native: #native(#on_transact),
+                proxy: #proxy,
+                #async_decl
+                transaction_names: [#(#transaction_names),*],
+            }
+        }
+
+        impl #native {
+            #(#codes)*
+        }
+
+        #[doc(hidden)]
+        #[allow(unused_variables)]
+        fn #on_transact(
+            service: &dyn #interface,
+            code: binder::binder_impl::TransactionCode,
+            data: &binder::binder_impl::BorrowedParcel<'_>,
+            reply: &mut binder::binder_impl::BorrowedParcel<'_>,
+        ) -> std::result::Result<(), binder::StatusCode> {
+            match code {
+                #(#dispatch)*
+                _ => Err(binder::StatusCode::UNKNOWN_TRANSACTION),
+            }
+        }
+
+        impl #interface for #proxy {
+            #(#proxy_methods)*
+        }
+
+        impl #interface for binder::binder_impl::Binder<#native> {
+            #(#native_methods)*
+        }
+
+        #async_items
+    })
+}
@@ -0,0 +1,133 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Procedural macros for the Rust Binder crate.
+//!
+//! These macros are re-exported by the `binder` crate, and should be used
+//! through it rather than by depending on this crate directly. The generated
+//! code refers to items in the `binder` crate by its absolute path.
+
+use proc_macro::TokenStream;
+use syn::{parse_macro_input, DeriveInput, Error, ItemTrait};
+
+mod interface;
+mod parcelable;
+
+/// Derive `binder::Parcelable` for a struct.
+///
+/// The fields are written in declaration order, framed with the same size
+/// header that AIDL structured parcelables use (see
+/// `BorrowedParcel::sized_write`). When reading, fields that are missing from
+/// the end of the parcel, e.g. because the sender uses an older version of the
+/// struct, are left unchanged.
+///
+/// Fields accept the following `#[parcelable(...)]` attributes:
+///
+/// * `nullable`: marks a field which may be null, like an AIDL `@nullable`
+///   field. The field must have an `Option` type, which is written with the
+///   nullable encoding and reads back as `None` for a null value.
+/// * `default = <expr>`: the value assigned to the field if it is missing from
+///   the parcel.
+///
+/// `default` only applies when reading. The `Default` implementation of the
+/// struct, which `Deserialize` uses to create the value before reading into it,
+/// is not derived from these attributes. Implement `Default` by hand if new
+/// values should start out with the same defaults.
+///
+/// # Examples
+///
+/// ```ignore
+/// #[derive(binder::Parcelable, binder::Serialize, binder::Deserialize)]
+/// struct Config {
+///     name: String,
+///     #[parcelable(nullable)]
+///     owner: Option<String>,
+///     #[parcelable(default = 10)]
+///     retries: i32,
+/// }
+///
+/// impl Default for Config {
+///     fn default() -> Self {
+///         Self { name: String::new(), owner: None, retries: 10 }
+///     }
+/// }
+/// ```
+#[proc_macro_derive(Parcelable, attributes(parcelable))]
+pub fn derive_parcelable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    parcelable::derive_parcelable(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+/// Derive `Serialize`, `SerializeArray` and `SerializeOption` for a type that
+/// implements `binder::Parcelable`.
+///
+/// This is equivalent to invoking `binder::impl_serialize_for_parcelable!`.
+#[proc_macro_derive(Serialize)]
+pub fn derive_serialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    parcelable::derive_serialize(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+/// Derive `Deserialize`, `DeserializeArray` and `DeserializeOption` for a type
+/// that implements `binder::Parcelable` and `Default`.
+///
+/// This is equivalent to invoking `binder::impl_deserialize_for_parcelable!`.
+#[proc_macro_derive(Deserialize)]
+pub fn derive_deserialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    parcelable::derive_deserialize(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+/// Define a binder interface from a Rust trait, without the AIDL compiler.
+///
+/// The attribute takes the interface descriptor and, optionally, the name of
+/// an async version of the trait to generate for use with
+/// `binder::BinderAsyncPool`:
+///
+/// ```ignore
+/// #[binder::interface("com.example.IFoo", async = IFooAsync)]
+/// pub trait IFoo {
+///     fn get_name(&self, id: i32) -> Result<String, binder::StatusCode>;
+///     #[oneway]
+///     fn notify(&self, message: &str) -> Result<(), binder::StatusCode>;
+/// }
+/// ```
+///
+/// For a trait `IFoo` this generates the `BnFoo` native type and the `BpFoo`
+/// proxy type with `binder::declare_binder_interface!`, together with the proxy
+/// methods and the native `on_transact` dispatcher. Methods are assigned
+/// sequential transaction codes starting at `FIRST_CALL_TRANSACTION` in
+/// declaration order, which are available as constants such as
+/// `BnFoo::TRANSACTION_GET_NAME`. The method names are also registered as the
+/// names of the transaction codes, see `Remotable::get_transaction_names`.
+///
+/// Every method must take `&self` and return a `Result` whose error type can
+/// be converted both from and into `binder::StatusCode`. Arguments are passed
+/// by value or by shared reference, where `&str` and `&[T]` are read as
+/// `String` and `Vec<T>` on the native side. Methods marked `#[oneway]` are
+/// sent with `FLAG_ONEWAY` and must return `Result<(), _>`. The trait gets
+/// `binder::Interface` as a supertrait if it doesn't already have it.
+///
+/// The transactions use the same encoding as the AIDL backends for the
+/// arguments and return value, but they do not include a status header, so
+/// these interfaces are only compatible with other interfaces defined with
+/// this attribute.
+#[proc_macro_attribute]
+pub fn interface(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as interface::InterfaceArgs);
+    let item = parse_macro_input!(item as ItemTrait);
+    interface::interface(args, item).unwrap_or_else(Error::into_compile_error).into()
+}
@@ -0,0 +1,152 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Derive macros for parcelables.
+
+use proc_macro2::TokenStream;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{Data, DeriveInput, Error, Expr, Field, Fields, Index, Member, Result, Type};
+
+/// Options parsed from the `#[parcelable(...)]` attributes of a field.
+#[derive(Default)]
+struct FieldOptions {
+    nullable: bool,
+    default: Option<Expr>,
+}
+
+impl FieldOptions {
+    fn parse(field: &Field) -> Result<Self> {
+        let mut options = Self::default();
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("parcelable")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("nullable") {
+                    options.nullable = true;
+                    Ok(())
+                } else if meta.path.is_ident("default") {
+                    options.default = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `nullable` or `default = ...`"))
+                }
+            })?;
+        }
+        Ok(options)
+    }
+}
+
+/// Whether `ty` is spelled as `Option<...>`. Type aliases can't be resolved by
+/// a derive macro, so they are not recognized.
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => {
+            matches!(path.path.segments.last(), Some(segment) if segment.ident == "Option")
+        }
+        _ => false,
+    }
+}
+
+/// Parcelables are referred to by name in the `impl_*_for_parcelable!` macros,
+/// so generic types can't be supported.
+fn check_not_generic(input: &DeriveInput) -> Result<()> {
+    if input.generics.params.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::new(input.generics.span(), "generic parcelables are not supported"))
+    }
+}
+
+pub(crate) fn derive_parcelable(input: &DeriveInput) -> Result<TokenStream> {
+    check_not_generic(input)?;
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => return Err(Error::new(input.span(), "only structs can derive `Parcelable`")),
+    };
+    let members: Vec<(Member, &Field)> = match fields {
+        Fields::Named(named) => {
+            named.named.iter().map(|f| (Member::Named(f.ident.clone().unwrap()), f)).collect()
+        }
+        Fields::Unnamed(unnamed) => unnamed
+            .unnamed
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (Member::Unnamed(Index::from(i)), f))
+            .collect(),
+        Fields::Unit => vec![],
+    };
+
+    let mut writes = vec![];
+    let mut reads = vec![];
+    for (member, field) in members {
+        let options = FieldOptions::parse(field)?;
+        let span = field.ty.span();
+        let default = options.default;
+
+        if options.nullable && !is_option(&field.ty) {
+            return Err(Error::new(span, "`nullable` fields must have an `Option` type"));
+        }
+
+        // `Option` already uses the nullable encoding, so nullable fields are
+        // written and read like any other field.
+        writes.push(quote_spanned!(span=> subparcel.write(&self.#member)?;));
+        let read = quote_spanned!(span=> self.#member = subparcel.read()?;);
+        let missing = default.map(|default| quote!(else { self.#member = #default; }));
+        reads.push(quote! {
+            if subparcel.has_more_data() {
+                #read
+            } #missing
+        });
+    }
+
+    // Avoid an unused variable warning for structs without fields.
+    let subparcel = if writes.is_empty() { quote!(_subparcel) } else { quote!(subparcel) };
+    let name = &input.ident;
+    Ok(quote! {
+        impl binder::Parcelable for #name {
+            fn write_to_parcel(
+                &self,
+                parcel: &mut binder::binder_impl::BorrowedParcel<'_>,
+            ) -> std::result::Result<(), binder::StatusCode> {
+                parcel.sized_write(|#subparcel| {
+                    #(#writes)*
+                    Ok(())
+                })
+            }
+
+            fn read_from_parcel(
+                &mut self,
+                parcel: &binder::binder_impl::BorrowedParcel<'_>,
+            ) -> std::result::Result<(), binder::StatusCode> {
+                parcel.sized_read(|#subparcel| {
+                    #(#reads)*
+                    Ok(())
+                })
+            }
+        }
+    })
+}
+
+pub(crate) fn derive_serialize(input: &DeriveInput) -> Result<TokenStream> {
+    check_not_generic(input)?;
+    let name = &input.ident;
+    Ok(quote!(binder::impl_serialize_for_parcelable!(#name);))
+}
+
+pub(crate) fn derive_deserialize(input: &DeriveInput) -> Result<TokenStream> {
+    check_not_generic(input)?;
+    let name = &input.ident;
+    Ok(quote!(binder::impl_deserialize_for_parcelable!(#name);))
+}
@@ -0,0 +1,109 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Rust API for binder RPC over sockets.
+//!
+//! RPC binder connects processes with a socket instead of the binder kernel
+//! driver, so it can also be used between virtual machines (over vsock) and on
+//! hosts without `/dev/binder` (over Unix domain sockets). Binders received
+//! from an [`RpcSession`] are regular [`SpIBinder`](binder::SpIBinder)s and
+//! can be used with any interface, but they can't be sent over kernel binder
+//! and vice versa.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rpcbinder::{RpcServer, RpcSession};
+//! # use binder::SpIBinder;
+//! # fn example(root: SpIBinder) -> Result<(), binder::StatusCode> {
+//!
+//! let server = RpcServer::new_unix_domain("/tmp/example.sock", root)?;
+//! server.set_max_threads(4);
+//! server.start();
+//!
+//! let session = RpcSession::new();
+//! let binder = session.setup_unix_domain_client("/tmp/example.sock")?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod server;
+mod session;
+
+pub use server::RpcServer;
+pub use session::RpcSession;
+
+use binder::StatusCode;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Convert a socket path into a C string, rejecting paths with interior nul
+/// bytes.
+fn path_to_cstring(path: &Path) -> Result<CString, StatusCode> {
+    CString::new(path.as_os_str().as_bytes()).or(Err(StatusCode::BAD_VALUE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RpcServer, RpcSession};
+    use binder::{BinderFeatures, FromIBinder, Interface, StatusCode, Strong};
+
+    #[binder::interface("android.os.IRpcTest")]
+    pub trait IRpcTest {
+        fn echo(&self, value: &str) -> Result<String, StatusCode>;
+    }
+
+    struct RpcTestService;
+
+    impl Interface for RpcTestService {}
+
+    impl IRpcTest for RpcTestService {
+        fn echo(&self, value: &str) -> Result<String, StatusCode> {
+            Ok(value.to_string())
+        }
+    }
+
+    #[test]
+    fn unix_domain_round_trip() {
+        let path = std::env::temp_dir().join(format!("rpcbinder_test_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let service = BnRpcTest::new_binder(RpcTestService, BinderFeatures::default());
+        let server = RpcServer::new_unix_domain(&path, service.as_binder())
+            .expect("Could not create RPC server");
+        server.set_max_threads(2);
+        server.start();
+
+        let session = RpcSession::new();
+        let binder =
+            session.setup_unix_domain_client(&path).expect("Could not connect to RPC server");
+        let proxy: Strong<dyn IRpcTest> =
+            FromIBinder::try_from(binder).expect("Root binder has the wrong interface");
+        assert_eq!(proxy.echo("hello").as_deref(), Ok("hello"));
+
+        server.shutdown().expect("Could not shut down RPC server");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn path_with_nul() {
+        assert_eq!(
+            RpcSession::new().setup_unix_domain_client("bad\0path").err(),
+            Some(StatusCode::BAD_VALUE)
+        );
+    }
+}
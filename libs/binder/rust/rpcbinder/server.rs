@@ -0,0 +1,130 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::path_to_cstring;
+use binder::binder_impl::status_result;
+use binder::unstable_api::AsNative;
+use binder::{SpIBinder, StatusCode};
+use binder_rpc_unstable_bindgen as sys;
+use std::os::raw::c_ulong;
+use std::path::Path;
+use std::ptr::{self, NonNull};
+
+/// A server that accepts RPC binder connections on a socket and serves a root
+/// binder object to every client.
+///
+/// Dropping the server shuts it down, along with all of its sessions.
+pub struct RpcServer {
+    server: NonNull<sys::ARpcServer>,
+}
+
+/// # Safety
+///
+/// `RpcServer` owns the only reference to the C++ `RpcServer`, which is
+/// thread-safe.
+unsafe impl Send for RpcServer {}
+
+/// # Safety
+///
+/// `RpcServer` owns the only reference to the C++ `RpcServer`, which is
+/// thread-safe.
+unsafe impl Sync for RpcServer {}
+
+impl RpcServer {
+    /// Create a server listening on a Unix domain socket bound to the given
+    /// path, serving `root` to every client.
+    ///
+    /// The server doesn't accept connections until it is started with
+    /// [`start`](Self::start) or [`join`](Self::join).
+    pub fn new_unix_domain(
+        path: impl AsRef<Path>,
+        mut root: SpIBinder,
+    ) -> Result<Self, StatusCode> {
+        let path = path_to_cstring(path.as_ref())?;
+        let mut server = ptr::null_mut();
+        // Safety: `root` is a valid binder, which the server takes its own
+        // reference to, and `path` is a valid C string which the C++ side only
+        // borrows for the duration of the call. On success `server` is set to
+        // a new server which we take ownership of.
+        let status = unsafe {
+            sys::ARpcServer_newUnixDomain(
+                root.as_native_mut() as *mut sys::AIBinder,
+                path.as_ptr(),
+                &mut server,
+            )
+        };
+        status_result(status)?;
+        Ok(Self { server: NonNull::new(server).ok_or(StatusCode::UNEXPECTED_NULL)? })
+    }
+
+    /// Create a server listening on the given vsock port, serving `root` to
+    /// every client.
+    ///
+    /// The server doesn't accept connections until it is started with
+    /// [`start`](Self::start) or [`join`](Self::join).
+    pub fn new_vsock(port: u32, mut root: SpIBinder) -> Result<Self, StatusCode> {
+        let mut server = ptr::null_mut();
+        // Safety: `root` is a valid binder, which the server takes its own
+        // reference to. On success `server` is set to a new server which we
+        // take ownership of.
+        let status = unsafe {
+            sys::ARpcServer_newVsock(root.as_native_mut() as *mut sys::AIBinder, port, &mut server)
+        };
+        status_result(status)?;
+        Ok(Self { server: NonNull::new(server).ok_or(StatusCode::UNEXPECTED_NULL)? })
+    }
+
+    /// Set the maximum number of threads that the server uses to handle
+    /// incoming transactions, across all of its sessions.
+    ///
+    /// This must be called before the server is started.
+    pub fn set_max_threads(&self, threads: usize) {
+        // Safety: `self.server` is always a valid server.
+        unsafe { sys::ARpcServer_setMaxThreads(self.server.as_ptr(), threads as c_ulong) }
+    }
+
+    /// Start accepting connections on a background thread, and return
+    /// immediately.
+    pub fn start(&self) {
+        // Safety: `self.server` is always a valid server.
+        unsafe { sys::ARpcServer_start(self.server.as_ptr()) }
+    }
+
+    /// Accept connections on the calling thread until the server is shut down
+    /// from another thread.
+    pub fn join(&self) {
+        // Safety: `self.server` is always a valid server.
+        unsafe { sys::ARpcServer_join(self.server.as_ptr()) }
+    }
+
+    /// Shut down the server and all of its sessions, and wait for the thread
+    /// accepting connections to stop.
+    pub fn shutdown(&self) -> Result<(), StatusCode> {
+        // Safety: `self.server` is always a valid server.
+        if unsafe { sys::ARpcServer_shutdown(self.server.as_ptr()) } {
+            Ok(())
+        } else {
+            Err(StatusCode::INVALID_OPERATION)
+        }
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        // Safety: We own `self.server`, and it is not used after this.
+        unsafe { sys::ARpcServer_delete(self.server.as_ptr()) }
+    }
+}
@@ -0,0 +1,133 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::path_to_cstring;
+use binder::binder_impl::status_result;
+use binder::unstable_api::new_spibinder;
+use binder::{SpIBinder, StatusCode};
+use binder_rpc_unstable_bindgen as sys;
+use std::os::raw::c_ulong;
+use std::path::Path;
+use std::ptr::{self, NonNull};
+
+/// A client connection to an [`RpcServer`](crate::RpcServer).
+///
+/// The session is configured first and then connected with one of the
+/// `setup_*_client` methods, which return the root binder of the server. The
+/// session stays connected as long as any binder received from it is alive,
+/// even after the `RpcSession` itself is dropped.
+pub struct RpcSession {
+    session: NonNull<sys::ARpcSession>,
+}
+
+/// # Safety
+///
+/// `RpcSession` owns its reference to the C++ `RpcSession`, which is
+/// thread-safe.
+unsafe impl Send for RpcSession {}
+
+/// # Safety
+///
+/// `RpcSession` owns its reference to the C++ `RpcSession`, which is
+/// thread-safe.
+unsafe impl Sync for RpcSession {}
+
+impl RpcSession {
+    /// Create a new, unconnected session.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        // Safety: `ARpcSession_new` always returns a new session, which we take
+        // ownership of.
+        let session = unsafe { sys::ARpcSession_new() };
+        Self { session: NonNull::new(session).expect("ARpcSession_new returned null") }
+    }
+
+    /// Set the maximum number of threads that the session uses to handle
+    /// transactions sent by the server, e.g. callbacks on binders that the
+    /// client passed to it. The default is 0, so the client can't receive
+    /// transactions.
+    ///
+    /// This must be called before the session is connected.
+    pub fn set_max_incoming_threads(&self, threads: usize) {
+        // Safety: `self.session` is always a valid session.
+        unsafe { sys::ARpcSession_setMaxIncomingThreads(self.session.as_ptr(), threads as c_ulong) }
+    }
+
+    /// Set the maximum number of connections that the session uses to send
+    /// transactions to the server. The number actually used is the smaller of
+    /// this and the thread count of the server.
+    ///
+    /// This must be called before the session is connected.
+    pub fn set_max_outgoing_threads(&self, threads: usize) {
+        // Safety: `self.session` is always a valid session.
+        unsafe { sys::ARpcSession_setMaxOutgoingThreads(self.session.as_ptr(), threads as c_ulong) }
+    }
+
+    /// Connect to a server listening on a Unix domain socket bound to the given
+    /// path, and return its root binder.
+    pub fn setup_unix_domain_client(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<SpIBinder, StatusCode> {
+        let path = path_to_cstring(path.as_ref())?;
+        let mut root = ptr::null_mut();
+        // Safety: `self.session` is always a valid session, and `path` is a
+        // valid C string which the C++ side only borrows for the duration of
+        // the call.
+        let status = unsafe {
+            sys::ARpcSession_setupUnixDomainClient(self.session.as_ptr(), path.as_ptr(), &mut root)
+        };
+        status_result(status)?;
+        // Safety: On success `root` is set to a new strong reference to the
+        // root binder, which we take ownership of.
+        unsafe { new_spibinder(root as *mut binder::unstable_api::AIBinder) }
+            .ok_or(StatusCode::UNEXPECTED_NULL)
+    }
+
+    /// Connect to a server listening on the given vsock CID and port, and return
+    /// its root binder.
+    pub fn setup_vsock_client(&self, cid: u32, port: u32) -> Result<SpIBinder, StatusCode> {
+        let mut root = ptr::null_mut();
+        // Safety: `self.session` is always a valid session.
+        let status = unsafe {
+            sys::ARpcSession_setupVsockClient(self.session.as_ptr(), cid, port, &mut root)
+        };
+        status_result(status)?;
+        // Safety: On success `root` is set to a new strong reference to the
+        // root binder, which we take ownership of.
+        unsafe { new_spibinder(root as *mut binder::unstable_api::AIBinder) }
+            .ok_or(StatusCode::UNEXPECTED_NULL)
+    }
+
+    /// Shut down the session, and wait for its incoming threads to stop.
+    ///
+    /// Binders received from the session can no longer be used after this.
+    pub fn shutdown(&self) -> Result<(), StatusCode> {
+        // Safety: `self.session` is always a valid session.
+        if unsafe { sys::ARpcSession_shutdown(self.session.as_ptr()) } {
+            Ok(())
+        } else {
+            Err(StatusCode::INVALID_OPERATION)
+        }
+    }
+}
+
+impl Drop for RpcSession {
+    fn drop(&mut self) {
+        // Safety: We own `self.session`, and it is not used after this.
+        unsafe { sys::ARpcSession_delete(self.session.as_ptr()) }
+    }
+}
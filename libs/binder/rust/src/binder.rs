@@ -17,32 +17,40 @@
 //! Trait definitions for binder objects
 
 use crate::error::{status_t, Result, StatusCode};
-use crate::parcel::{Parcel, BorrowedParcel};
+use crate::interceptor::TransactionInterceptor;
+use crate::parcel::{
+    BorrowedParcel, Deserialize, DeserializeArray, DeserializeOption, Parcel, Serialize,
+    SerializeArray, SerializeOption,
+};
 use crate::proxy::{DeathRecipient, SpIBinder, WpIBinder};
+use crate::shell::IShellCallback;
+use crate::state::TransactionContext;
 use crate::sys;
 
 use std::borrow::Borrow;
 use std::cmp::Ordering;
-use std::convert::TryFrom;
+use std::collections::BTreeMap;
 use std::ffi::{c_void, CStr, CString};
 use std::fmt;
 use std::fs::File;
 use std::marker::PhantomData;
-use std::ops::Deref;
+use std::ops::{Deref, RangeInclusive};
 use std::os::raw::c_char;
 use std::os::unix::io::AsRawFd;
-use std::ptr;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-/// Binder action to perform.
-///
-/// This must be a number between [`FIRST_CALL_TRANSACTION`] and
-/// [`LAST_CALL_TRANSACTION`].
-pub type TransactionCode = u32;
+mod common;
 
-/// Additional operation flags.
-///
-/// `FLAG_*` values.
-pub type TransactionFlags = u32;
+pub use self::common::{
+    AsNative, Stability, TransactionCode, TransactionFlags, DEBUG_PID_TRANSACTION,
+    DUMP_TRANSACTION, EXTENSION_TRANSACTION, FIRST_CALL_TRANSACTION, FLAG_CLEAR_BUF, FLAG_ONEWAY,
+    INTERFACE_TRANSACTION, LAST_CALL_TRANSACTION, LIKE_TRANSACTION, PING_TRANSACTION,
+    SET_RPC_CLIENT_TRANSACTION, SHELL_COMMAND_TRANSACTION, SYSPROPS_TRANSACTION,
+    TWEET_TRANSACTION,
+};
 
 /// Super-trait for Binder interfaces.
 ///
@@ -64,6 +72,28 @@ pub trait Interface: Send + Sync {
     fn dump(&self, _file: &File, _args: &[&CStr]) -> Result<()> {
         Ok(())
     }
+
+    /// Shell command handler for this Binder object, called for
+    /// `adb shell cmd <service> <args>`.
+    ///
+    /// `input`, `output` and `error` are the standard streams of the `cmd`
+    /// invocation, and `shell_callback` can open other files as the caller, if
+    /// it passed one. The returned status is reported to the caller as the
+    /// result of the command. Only root and shell are allowed to run shell
+    /// commands.
+    ///
+    /// This handler returns `INVALID_OPERATION` by default, like C++ services
+    /// which don't implement shell commands.
+    fn handle_shell_command(
+        &self,
+        _input: &File,
+        _output: &File,
+        _error: &File,
+        _args: &[&CStr],
+        _shell_callback: Option<&Strong<dyn IShellCallback>>,
+    ) -> Result<()> {
+        Err(StatusCode::INVALID_OPERATION)
+    }
 }
 
 /// Implemented by sync interfaces to specify what the associated async interface is.
@@ -95,49 +125,6 @@ where
     type Target: ?Sized;
 }
 
-/// Interface stability promise
-///
-/// An interface can promise to be a stable vendor interface ([`Vintf`]), or
-/// makes no stability guarantees ([`Local`]). [`Local`] is
-/// currently the default stability.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub enum Stability {
-    /// Default stability, visible to other modules in the same compilation
-    /// context (e.g. modules on system.img)
-    Local,
-
-    /// A Vendor Interface Object, which promises to be stable
-    Vintf,
-}
-
-impl Default for Stability {
-    fn default() -> Self {
-        Stability::Local
-    }
-}
-
-impl From<Stability> for i32 {
-    fn from(stability: Stability) -> i32 {
-        use Stability::*;
-        match stability {
-            Local => 0,
-            Vintf => 1,
-        }
-    }
-}
-
-impl TryFrom<i32> for Stability {
-    type Error = StatusCode;
-    fn try_from(stability: i32) -> Result<Stability> {
-        use Stability::*;
-        match stability {
-            0 => Ok(Local),
-            1 => Ok(Vintf),
-            _ => Err(StatusCode::BAD_VALUE)
-        }
-    }
-}
-
 /// A local service that can be remotable via Binder.
 ///
 /// An object that implement this interface made be made into a Binder service
@@ -155,15 +142,63 @@ pub trait Remotable: Send + Sync {
     /// the same between all implementations of that interface.
     fn get_descriptor() -> &'static str;
 
+    /// The names of the methods of this interface, where the method at index
+    /// `i` handles transaction code `FIRST_CALL_TRANSACTION + i`.
+    ///
+    /// These names are shown in binder tracing instead of raw transaction
+    /// codes, on both sides of the transaction. The default implementation
+    /// returns an empty slice.
+    fn get_transaction_names() -> &'static [&'static str] {
+        &[]
+    }
+
+    /// The name of the method which handles transaction `code`, if it is
+    /// known. This is the equivalent of `BBinder::getTransactionName` in C++,
+    /// see also [`InterfaceClass::get_transaction_name`].
+    fn get_transaction_name(code: TransactionCode) -> Option<&'static str> {
+        let index = code.checked_sub(FIRST_CALL_TRANSACTION)?;
+        Self::get_transaction_names().get(index as usize).copied()
+    }
+
     /// Handle and reply to a request to invoke a transaction on this object.
     ///
     /// `reply` may be [`None`] if the sender does not expect a reply.
     fn on_transact(&self, code: TransactionCode, data: &BorrowedParcel<'_>, reply: &mut BorrowedParcel<'_>) -> Result<()>;
 
+    /// Handle and reply to a request to invoke a transaction on this object,
+    /// with the context of the transaction.
+    ///
+    /// This is called for every incoming transaction. The default
+    /// implementation ignores the context and calls `on_transact`.
+    fn on_transact_with_context(
+        &self,
+        code: TransactionCode,
+        data: &BorrowedParcel<'_>,
+        reply: &mut BorrowedParcel<'_>,
+        _context: &TransactionContext,
+    ) -> Result<()> {
+        self.on_transact(code, data, reply)
+    }
+
     /// Handle a request to invoke the dump transaction on this
     /// object.
     fn on_dump(&self, file: &File, args: &[&CStr]) -> Result<()>;
 
+    /// Handle a request to run a shell command on this object, see
+    /// [`Interface::handle_shell_command`].
+    ///
+    /// The default implementation returns `INVALID_OPERATION`.
+    fn on_shell_command(
+        &self,
+        _input: &File,
+        _output: &File,
+        _error: &File,
+        _args: &[&CStr],
+        _shell_callback: Option<&Strong<dyn IShellCallback>>,
+    ) -> Result<()> {
+        Err(StatusCode::INVALID_OPERATION)
+    }
+
     /// Retrieve the class of this remote object.
     ///
     /// This method should always return the same InterfaceClass for the same
@@ -171,15 +206,6 @@ pub trait Remotable: Send + Sync {
     fn get_class() -> InterfaceClass;
 }
 
-/// First transaction code available for user commands (inclusive)
-pub const FIRST_CALL_TRANSACTION: TransactionCode = sys::FIRST_CALL_TRANSACTION;
-/// Last transaction code available for user commands (inclusive)
-pub const LAST_CALL_TRANSACTION: TransactionCode = sys::LAST_CALL_TRANSACTION;
-
-/// Corresponds to TF_ONE_WAY -- an asynchronous call.
-pub const FLAG_ONEWAY: TransactionFlags = sys::FLAG_ONEWAY;
-/// Corresponds to TF_CLEAR_BUF -- clear transaction buffers after call is made.
-pub const FLAG_CLEAR_BUF: TransactionFlags = sys::FLAG_CLEAR_BUF;
 /// Set to the vendor flag if we are building for the VNDK, 0 otherwise
 pub const FLAG_PRIVATE_LOCAL: TransactionFlags = sys::FLAG_PRIVATE_LOCAL;
 
@@ -284,6 +310,11 @@ pub trait IBinder {
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub struct InterfaceClass(*const sys::AIBinder_Class);
 
+/// The method names of the classes created by [`InterfaceClass::new`], by class
+/// pointer. Classes are never destroyed, so neither are their entries.
+static TRANSACTION_NAMES: Mutex<BTreeMap<usize, &'static [&'static str]>> =
+    Mutex::new(BTreeMap::new());
+
 impl InterfaceClass {
     /// Get a Binder NDK `AIBinder_Class` pointer for this object type.
     ///
@@ -297,11 +328,13 @@ impl InterfaceClass {
             // Safety: `AIBinder_Class_define` expects a valid C string, and
             // three valid callback functions, all non-null pointers. The C
             // string is copied and need not be valid for longer than the call,
-            // so we can drop it after the call. We can safely assign null to
-            // the onDump and handleShellCommand callbacks as long as the class
-            // pointer was non-null. Rust None for a Option<fn> is guaranteed to
-            // be a NULL pointer. Rust retains ownership of the pointer after it
-            // is defined.
+            // so we can drop it after the call. We can safely assign the
+            // onTransactWithFlags, onDump and handleShellCommand callbacks as
+            // long as the class pointer was non-null. Rust retains ownership
+            // of the pointer after it is defined. Without onTransactWithFlags
+            // and handleShellCommandWithCallback, which aren't available to
+            // vendor code, transactions are handled with flags of 0 and shell
+            // commands without a shell callback.
             let class = sys::AIBinder_Class_define(
                 descriptor.as_ptr(),
                 Some(I::on_create),
@@ -311,10 +344,21 @@ impl InterfaceClass {
             if class.is_null() {
                 panic!("Expected non-null class pointer from AIBinder_Class_define!");
             }
+            #[cfg(not(android_vndk))]
+            sys::AIBinder_Class_setOnTransactWithFlags(class, Some(I::on_transact_with_flags));
             sys::AIBinder_Class_setOnDump(class, Some(I::on_dump));
-            sys::AIBinder_Class_setHandleShellCommand(class, None);
+            sys::AIBinder_Class_setHandleShellCommand(class, Some(I::on_shell_command));
+            #[cfg(not(android_vndk))]
+            sys::AIBinder_Class_setHandleShellCommandWithCallback(
+                class,
+                Some(I::on_shell_command_with_callback),
+            );
             class
         };
+        let names = I::get_transaction_names();
+        if !names.is_empty() {
+            TRANSACTION_NAMES.lock().unwrap().insert(ptr as usize, names);
+        }
         InterfaceClass(ptr)
     }
 
@@ -345,6 +389,15 @@ impl InterfaceClass {
                 .into()
         }
     }
+
+    /// Get the name of the method of this class which handles transaction
+    /// `code`, if the class was created with method names, see
+    /// [`Remotable::get_transaction_name`].
+    pub fn get_transaction_name(&self, code: TransactionCode) -> Option<&'static str> {
+        let index = code.checked_sub(FIRST_CALL_TRANSACTION)?;
+        let names = *TRANSACTION_NAMES.lock().unwrap().get(&(self.0 as usize))?;
+        names.get(index as usize).copied()
+    }
 }
 
 impl From<InterfaceClass> for *const sys::AIBinder_Class {
@@ -386,6 +439,172 @@ impl<I: FromIBinder + ?Sized> Strong<I> {
         // object is also valid for the target type.
         FromIBinder::try_from(self.0.as_binder()).unwrap()
     }
+
+    /// Get the extension of this binder object as the interface `E`, if it has
+    /// one.
+    ///
+    /// Returns an error if the extension doesn't implement `E`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// if let Some(bar) = foo.get_extension::<dyn IBar>()? {
+    ///     bar.do_bar()?;
+    /// }
+    /// ```
+    pub fn get_extension<E: FromIBinder + ?Sized>(&self) -> Result<Option<Strong<E>>> {
+        self.0.as_binder().get_extension()?.map(FromIBinder::try_from).transpose()
+    }
+
+    /// Get a handle to this binder object whose calls fail with `TIMED_OUT` if
+    /// they don't complete within `timeout`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let name = foo.with_timeout(Duration::from_millis(500)).call(|foo| foo.get_name())?;
+    /// ```
+    pub fn with_timeout(&self, timeout: Duration) -> WithTimeout<I> {
+        WithTimeout {
+            binder: self.clone(),
+            timeout,
+            max_calls: DEFAULT_MAX_TIMEOUT_CALLS,
+            calls: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+/// A handle to a binder object whose calls fail with `TIMED_OUT` if they don't
+/// complete within a deadline.
+///
+/// Created by [`Strong::with_timeout`]. Each call is made on a helper thread
+/// from a pool shared by the whole process, and the calling thread stops
+/// waiting for it when the deadline expires. The binder driver can't cancel a
+/// transaction once it has been sent, so the remote process still handles the
+/// call, and the late reply is discarded when it arrives. Until then, the
+/// helper thread stays busy.
+///
+/// At most [`max_calls`](Self::max_calls) calls made through a handle and its
+/// clones can be in progress at once, including calls which timed out. When
+/// they are all busy, e.g. because the remote process hangs, further calls fail
+/// immediately with `WOULD_BLOCK` instead of piling up threads. Handles
+/// created by separate calls to [`Strong::with_timeout`] have separate limits,
+/// so a hung remote process doesn't stop calls to other binder objects.
+///
+/// Because the call is made on another thread:
+///
+/// * If the calling thread is handling a transaction, the call isn't nested in
+///   it. [`ThreadState`](crate::ThreadState) in `f` describes the helper
+///   thread rather than the transaction, e.g. the calling UID is this
+///   process's own UID. Any identity that the call depends on must be read on
+///   the calling thread and passed in explicitly.
+/// * The binder driver doesn't know that the calling thread is waiting for the
+///   call. If the remote process calls back into this process while handling
+///   it, the callback is handled by the binder thread pool rather than by the
+///   calling thread, so it can't reenter locks that the calling thread holds
+///   and it deadlocks until the timeout if there is no free binder thread.
+pub struct WithTimeout<I: FromIBinder + ?Sized> {
+    binder: Strong<I>,
+    timeout: Duration,
+    max_calls: usize,
+    /// The number of calls in progress, shared with clones.
+    calls: Arc<AtomicUsize>,
+}
+
+impl<I: FromIBinder + ?Sized + 'static> WithTimeout<I> {
+    /// The maximum time to wait for each call.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Set the maximum number of calls which can be in progress at once, see
+    /// [`WithTimeout`]. The default is [`DEFAULT_MAX_TIMEOUT_CALLS`].
+    ///
+    /// The limit is shared with the clones of this handle.
+    pub fn with_max_calls(mut self, max_calls: usize) -> Self {
+        self.max_calls = max_calls;
+        self
+    }
+
+    /// The maximum number of calls which can be in progress at once.
+    pub fn max_calls(&self) -> usize {
+        self.max_calls
+    }
+
+    /// Call `f` with the binder object, returning `TIMED_OUT` if it doesn't
+    /// return within the timeout, or `WOULD_BLOCK` if
+    /// [`max_calls`](Self::max_calls) calls are already in progress.
+    pub fn call<T, E, F>(&self, f: F) -> std::result::Result<T, E>
+    where
+        F: FnOnce(&Strong<I>) -> std::result::Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: From<StatusCode> + Send + 'static,
+    {
+        let call = TimeoutCall::start(&self.calls, self.max_calls)?;
+        let binder = self.binder.clone();
+        let (sender, receiver) = mpsc::sync_channel(1);
+        crate::timeout_pool::spawn(move || {
+            let _call = call;
+            // The receiver has been dropped if the call timed out, in which
+            // case the result is discarded.
+            let _ = sender.send(f(&binder));
+        })?;
+
+        match receiver.recv_timeout(self.timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(StatusCode::TIMED_OUT.into()),
+            // The call panicked.
+            Err(RecvTimeoutError::Disconnected) => Err(StatusCode::UNKNOWN_ERROR.into()),
+        }
+    }
+}
+
+impl<I: FromIBinder + ?Sized> Clone for WithTimeout<I> {
+    fn clone(&self) -> Self {
+        Self {
+            binder: self.binder.clone(),
+            timeout: self.timeout,
+            max_calls: self.max_calls,
+            calls: self.calls.clone(),
+        }
+    }
+}
+
+impl<I: FromIBinder + ?Sized> fmt::Debug for WithTimeout<I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WithTimeout")
+            .field("binder", &self.binder.as_binder())
+            .field("timeout", &self.timeout)
+            .field("max_calls", &self.max_calls)
+            .finish()
+    }
+}
+
+/// The default maximum number of calls which can be in progress at once
+/// through a [`WithTimeout`].
+pub const DEFAULT_MAX_TIMEOUT_CALLS: usize = 8;
+
+/// Counts a call made through a [`WithTimeout`] until it returns, even if the
+/// caller stopped waiting for it.
+struct TimeoutCall(Arc<AtomicUsize>);
+
+impl TimeoutCall {
+    /// Count a new call, or fail with `WOULD_BLOCK` if `max_calls` calls are
+    /// already in progress.
+    fn start(calls: &Arc<AtomicUsize>, max_calls: usize) -> Result<Self> {
+        calls
+            .fetch_update(atomic::Ordering::AcqRel, atomic::Ordering::Acquire, |calls| {
+                (calls < max_calls).then_some(calls + 1)
+            })
+            .map_err(|_| StatusCode::WOULD_BLOCK)?;
+        Ok(Self(calls.clone()))
+    }
+}
+
+impl Drop for TimeoutCall {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, atomic::Ordering::AcqRel);
+    }
 }
 
 impl<I: FromIBinder + ?Sized> Clone for Strong<I> {
@@ -443,6 +662,36 @@ impl<I: FromIBinder + ?Sized> PartialEq for Strong<I> {
 
 impl<I: FromIBinder + ?Sized> Eq for Strong<I> {}
 
+impl<T: Serialize + FromIBinder + ?Sized> Serialize for Strong<T> {
+    fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        Serialize::serialize(&**self, parcel)
+    }
+}
+
+impl<T: SerializeOption + FromIBinder + ?Sized> SerializeOption for Strong<T> {
+    fn serialize_option(this: Option<&Self>, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        SerializeOption::serialize_option(this.map(|b| &**b), parcel)
+    }
+}
+
+impl<T: Serialize + FromIBinder + ?Sized> SerializeArray for Strong<T> {}
+
+impl<T: FromIBinder + ?Sized> Deserialize for Strong<T> {
+    fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<Self> {
+        let ibinder: SpIBinder = parcel.read()?;
+        FromIBinder::try_from(ibinder)
+    }
+}
+
+impl<T: FromIBinder + ?Sized> DeserializeOption for Strong<T> {
+    fn deserialize_option(parcel: &BorrowedParcel<'_>) -> Result<Option<Self>> {
+        let ibinder: Option<SpIBinder> = parcel.read()?;
+        ibinder.map(FromIBinder::try_from).transpose()
+    }
+}
+
+impl<T: FromIBinder + ?Sized> DeserializeArray for Strong<T> {}
+
 /// Weak reference to a binder object
 #[derive(Debug)]
 pub struct Weak<I: FromIBinder + ?Sized> {
@@ -565,6 +814,15 @@ pub trait InterfaceClassMethods {
     where
         Self: Sized;
 
+    /// Get the method names for the transaction codes of this object type,
+    /// see [`Remotable::get_transaction_names`].
+    fn get_transaction_names() -> &'static [&'static str]
+    where
+        Self: Sized,
+    {
+        &[]
+    }
+
     /// Called during construction of a new `AIBinder` object of this interface
     /// class.
     ///
@@ -596,6 +854,21 @@ pub trait InterfaceClassMethods {
         reply: *mut sys::AParcel,
     ) -> status_t;
 
+    /// Called when a transaction needs to be processed by the local service
+    /// implementation, along with the flags of the transaction. The NDK calls
+    /// this instead of `on_transact`.
+    ///
+    /// # Safety
+    ///
+    /// Same as `on_transact`.
+    unsafe extern "C" fn on_transact_with_flags(
+        binder: *mut sys::AIBinder,
+        code: u32,
+        data: *const sys::AParcel,
+        reply: *mut sys::AParcel,
+        flags: sys::binder_flags_t,
+    ) -> status_t;
+
     /// Called whenever an `AIBinder` object is no longer referenced and needs
     /// to be destroyed.
     ///
@@ -615,6 +888,41 @@ pub trait InterfaceClassMethods {
     /// descriptor, and args must be an array of null-terminated string
     /// poiinters with length num_args.
     unsafe extern "C" fn on_dump(binder: *mut sys::AIBinder, fd: i32, args: *mut *const c_char, num_args: u32) -> status_t;
+
+    /// Called to handle the shell command transaction.
+    ///
+    /// # Safety
+    ///
+    /// Must be called with a non-null, valid pointer to a local `AIBinder` that
+    /// contains a `T` pointer in its user data. The file descriptors should be
+    /// non-owned, and argv must be an array of null-terminated string pointers
+    /// with length argc, or null if argc is 0.
+    unsafe extern "C" fn on_shell_command(
+        binder: *mut sys::AIBinder,
+        in_fd: i32,
+        out_fd: i32,
+        err_fd: i32,
+        argv: *mut *const c_char,
+        argc: u32,
+    ) -> status_t;
+
+    /// Called to handle the shell command transaction, along with the shell
+    /// callback of the caller. The NDK calls this instead of
+    /// `on_shell_command`.
+    ///
+    /// # Safety
+    ///
+    /// Same as `on_shell_command`. In addition, shell_callback must be null or
+    /// a valid, non-owned pointer to an `AIBinder`.
+    unsafe extern "C" fn on_shell_command_with_callback(
+        binder: *mut sys::AIBinder,
+        in_fd: i32,
+        out_fd: i32,
+        err_fd: i32,
+        argv: *mut *const c_char,
+        argc: u32,
+        shell_callback: *mut sys::AIBinder,
+    ) -> status_t;
 }
 
 /// Interface for transforming a generic SpIBinder into a specific remote
@@ -641,36 +949,6 @@ pub trait FromIBinder: Interface {
     fn try_from(ibinder: SpIBinder) -> Result<Strong<Self>>;
 }
 
-/// Trait for transparent Rust wrappers around android C++ native types.
-///
-/// The pointer return by this trait's methods should be immediately passed to
-/// C++ and not stored by Rust. The pointer is valid only as long as the
-/// underlying C++ object is alive, so users must be careful to take this into
-/// account, as Rust cannot enforce this.
-///
-/// # Safety
-///
-/// For this trait to be a correct implementation, `T` must be a valid android
-/// C++ type. Since we cannot constrain this via the type system, this trait is
-/// marked as unsafe.
-pub unsafe trait AsNative<T> {
-    /// Return a pointer to the native version of `self`
-    fn as_native(&self) -> *const T;
-
-    /// Return a mutable pointer to the native version of `self`
-    fn as_native_mut(&mut self) -> *mut T;
-}
-
-unsafe impl<T, V: AsNative<T>> AsNative<T> for Option<V> {
-    fn as_native(&self) -> *const T {
-        self.as_ref().map_or(ptr::null(), |v| v.as_native())
-    }
-
-    fn as_native_mut(&mut self) -> *mut T {
-        self.as_mut().map_or(ptr::null_mut(), |v| v.as_native_mut())
-    }
-}
-
 /// The features to enable when creating a native Binder.
 ///
 /// This should always be initialised with a default value, e.g.:
@@ -681,12 +959,27 @@ unsafe impl<T, V: AsNative<T>> AsNative<T> for Option<V> {
 ///   ..BinderFeatures::default(),
 /// }
 /// ```
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(Clone, Default)]
 pub struct BinderFeatures {
     /// Indicates that the service intends to receive caller security contexts. This must be true
     /// for `ThreadState::with_calling_sid` to work.
     #[cfg(not(android_vndk))]
     pub set_requesting_sid: bool,
+    /// Interceptors which run around every transaction handled by the service, in order. They are
+    /// installed before the service is returned, so unlike `Binder::add_interceptor` they can't
+    /// miss any transactions.
+    pub interceptors: Vec<Arc<dyn TransactionInterceptor>>,
+    /// An extension to attach to the service, which clients can retrieve with
+    /// [`Strong::get_extension`]. See
+    /// [`Binder::set_extension`](crate::binder_impl::Binder::set_extension).
+    pub extension: Option<SpIBinder>,
+    /// The minimum scheduler policy that incoming transactions are handled
+    /// with. See
+    /// [`Binder::set_min_scheduler_policy`](crate::binder_impl::Binder::set_min_scheduler_policy).
+    pub min_scheduler_policy: Option<SchedulerPolicy>,
+    /// Indicates that incoming transactions should inherit the realtime
+    /// scheduling policy of the caller.
+    pub inherit_rt: bool,
     // Ensure that clients include a ..BinderFeatures::default() to preserve backwards compatibility
     // when new fields are added. #[non_exhaustive] doesn't work because it prevents struct
     // expressions entirely.
@@ -694,6 +987,80 @@ pub struct BinderFeatures {
     pub _non_exhaustive: (),
 }
 
+impl fmt::Debug for BinderFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = f.debug_struct("BinderFeatures");
+        #[cfg(not(android_vndk))]
+        debug.field("set_requesting_sid", &self.set_requesting_sid);
+        debug.field("interceptors", &self.interceptors.len());
+        debug.field("extension", &self.extension);
+        debug.field("min_scheduler_policy", &self.min_scheduler_policy);
+        debug.field("inherit_rt", &self.inherit_rt).finish()
+    }
+}
+
+/// Interceptors are compared by identity.
+impl PartialEq for BinderFeatures {
+    fn eq(&self, other: &Self) -> bool {
+        #[cfg(not(android_vndk))]
+        if self.set_requesting_sid != other.set_requesting_sid {
+            return false;
+        }
+        self.extension == other.extension
+            && self.min_scheduler_policy == other.min_scheduler_policy
+            && self.inherit_rt == other.inherit_rt
+            && self.interceptors.len() == other.interceptors.len()
+            && self.interceptors.iter().zip(&other.interceptors).all(|(a, b)| Arc::ptr_eq(a, b))
+    }
+}
+
+impl Eq for BinderFeatures {}
+
+/// A Linux scheduler policy and priority for handling incoming transactions.
+///
+/// Use one of the constructors, which check that the priority is valid for the
+/// policy.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SchedulerPolicy {
+    policy: i32,
+    priority: i32,
+}
+
+impl SchedulerPolicy {
+    /// `SCHED_NORMAL` with the given nice value, between -20 and 19.
+    pub fn normal(nice: i32) -> Result<Self> {
+        Self::new(libc::SCHED_OTHER, nice, -20..=19)
+    }
+
+    /// `SCHED_FIFO` with the given realtime priority, between 1 and 99.
+    pub fn fifo(priority: i32) -> Result<Self> {
+        Self::new(libc::SCHED_FIFO, priority, 1..=99)
+    }
+
+    /// `SCHED_RR` with the given realtime priority, between 1 and 99.
+    pub fn round_robin(priority: i32) -> Result<Self> {
+        Self::new(libc::SCHED_RR, priority, 1..=99)
+    }
+
+    fn new(policy: i32, priority: i32, range: RangeInclusive<i32>) -> Result<Self> {
+        if range.contains(&priority) {
+            Ok(Self { policy, priority })
+        } else {
+            Err(StatusCode::BAD_VALUE)
+        }
+    }
+
+    /// The policy as passed to the kernel, e.g. `libc::SCHED_FIFO`.
+    pub fn policy(&self) -> i32 {
+        self.policy
+    }
+
+    /// The nice value for `SCHED_NORMAL`, or the realtime priority otherwise.
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+}
+
 /// Declare typed interfaces for a binder object.
 ///
 /// Given an interface trait and descriptor string, create a native and remote
@@ -717,6 +1084,12 @@ pub struct BinderFeatures {
 /// # }
 /// ```
 ///
+/// The declaration can optionally include `transaction_names: [...]` after
+/// `async` and before `stability`, listing the method names for the
+/// transaction codes starting at `FIRST_CALL_TRANSACTION`. These are returned
+/// by [`Remotable::get_transaction_names`](crate::binder_impl::Remotable::get_transaction_names)
+/// and recorded in binder tracing.
+///
 /// # Examples
 ///
 /// The following example declares the local service type `BnServiceManager` and
@@ -765,6 +1138,7 @@ macro_rules! declare_binder_interface {
             native: $native:ident($on_transact:path),
             proxy: $proxy:ident,
             $(async: $async_interface:ident,)?
+            $(transaction_names: [$($tname:expr),* $(,)?],)?
         }
     } => {
         $crate::declare_binder_interface! {
@@ -772,6 +1146,7 @@ macro_rules! declare_binder_interface {
                 native: $native($on_transact),
                 proxy: $proxy {},
                 $(async: $async_interface,)?
+                $(transaction_names: [$($tname),*],)?
                 stability: $crate::binder_impl::Stability::default(),
             }
         }
@@ -782,6 +1157,7 @@ macro_rules! declare_binder_interface {
             native: $native:ident($on_transact:path),
             proxy: $proxy:ident,
             $(async: $async_interface:ident,)?
+            $(transaction_names: [$($tname:expr),* $(,)?],)?
             stability: $stability:expr,
         }
     } => {
@@ -790,6 +1166,7 @@ macro_rules! declare_binder_interface {
                 native: $native($on_transact),
                 proxy: $proxy {},
                 $(async: $async_interface,)?
+                $(transaction_names: [$($tname),*],)?
                 stability: $stability,
             }
         }
@@ -802,6 +1179,7 @@ macro_rules! declare_binder_interface {
                 $($fname:ident: $fty:ty = $finit:expr),*
             },
             $(async: $async_interface:ident,)?
+            $(transaction_names: [$($tname:expr),* $(,)?],)?
         }
     } => {
         $crate::declare_binder_interface! {
@@ -811,6 +1189,7 @@ macro_rules! declare_binder_interface {
                     $($fname: $fty = $finit),*
                 },
                 $(async: $async_interface,)?
+                $(transaction_names: [$($tname),*],)?
                 stability: $crate::binder_impl::Stability::default(),
             }
         }
@@ -823,6 +1202,7 @@ macro_rules! declare_binder_interface {
                 $($fname:ident: $fty:ty = $finit:expr),*
             },
             $(async: $async_interface:ident,)?
+            $(transaction_names: [$($tname:expr),* $(,)?],)?
             stability: $stability:expr,
         }
     } => {
@@ -835,6 +1215,7 @@ macro_rules! declare_binder_interface {
                     $($fname: $fty = $finit),*
                 },
                 $(async: $async_interface,)?
+                $(transaction_names: [$($tname),*],)?
                 stability: $stability,
             }
         }
@@ -852,6 +1233,8 @@ macro_rules! declare_binder_interface {
 
             $( async: $async_interface:ident, )?
 
+            $( transaction_names: [$($tname:expr),* $(,)?], )?
+
             stability: $stability:expr,
         }
     } => {
@@ -875,7 +1258,7 @@ macro_rules! declare_binder_interface {
                 $descriptor
             }
 
-            fn from_binder(mut binder: $crate::SpIBinder) -> std::result::Result<Self, $crate::StatusCode> {
+            fn from_binder(binder: $crate::SpIBinder) -> std::result::Result<Self, $crate::StatusCode> {
                 Ok(Self { binder, $($fname: $finit),* })
             }
         }
@@ -887,9 +1270,7 @@ macro_rules! declare_binder_interface {
         impl $native {
             /// Create a new binder service.
             pub fn new_binder<T: $interface + Sync + Send + 'static>(inner: T, features: $crate::BinderFeatures) -> $crate::Strong<dyn $interface> {
-                let mut binder = $crate::binder_impl::Binder::new_with_stability($native(Box::new(inner)), $stability);
-                #[cfg(not(android_vndk))]
-                $crate::binder_impl::IBinderInternal::set_requesting_sid(&mut binder, features.set_requesting_sid);
+                let binder = $crate::binder_impl::Binder::new_with_features($native(Box::new(inner)), $stability, features);
                 $crate::Strong::new(Box::new(binder))
             }
         }
@@ -899,6 +1280,10 @@ macro_rules! declare_binder_interface {
                 $descriptor
             }
 
+            fn get_transaction_names() -> &'static [&'static str] {
+                &[$($($tname),*)?]
+            }
+
             fn on_transact(&self, code: $crate::binder_impl::TransactionCode, data: &$crate::binder_impl::BorrowedParcel<'_>, reply: &mut $crate::binder_impl::BorrowedParcel<'_>) -> std::result::Result<(), $crate::StatusCode> {
                 match $on_transact(&*self.0, code, data, reply) {
                     // The C++ backend converts UNEXPECTED_NULL into an exception
@@ -917,6 +1302,10 @@ macro_rules! declare_binder_interface {
                 self.0.dump(file, args)
             }
 
+            fn on_shell_command(&self, input: &std::fs::File, output: &std::fs::File, error: &std::fs::File, args: &[&std::ffi::CStr], shell_callback: Option<&$crate::Strong<dyn $crate::shell::IShellCallback>>) -> std::result::Result<(), $crate::StatusCode> {
+                self.0.handle_shell_command(input, output, error, args, shell_callback)
+            }
+
             fn get_class() -> $crate::binder_impl::InterfaceClass {
                 static CLASS_INIT: std::sync::Once = std::sync::Once::new();
                 static mut CLASS: Option<$crate::binder_impl::InterfaceClass> = None;
@@ -1077,57 +1466,3 @@ macro_rules! declare_binder_interface {
         )?
     };
 }
-
-/// Declare an AIDL enumeration.
-///
-/// This is mainly used internally by the AIDL compiler.
-#[macro_export]
-macro_rules! declare_binder_enum {
-    {
-        $( #[$attr:meta] )*
-        $enum:ident : [$backing:ty; $size:expr] {
-            $( $( #[$value_attr:meta] )* $name:ident = $value:expr, )*
-        }
-    } => {
-        $( #[$attr] )*
-        #[derive(Debug, Default, Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash)]
-        #[allow(missing_docs)]
-        pub struct $enum(pub $backing);
-        impl $enum {
-            $( $( #[$value_attr] )* #[allow(missing_docs)] pub const $name: Self = Self($value); )*
-
-            #[inline(always)]
-            #[allow(missing_docs)]
-            pub const fn enum_values() -> [Self; $size] {
-                [$(Self::$name),*]
-            }
-        }
-
-        impl $crate::binder_impl::Serialize for $enum {
-            fn serialize(&self, parcel: &mut $crate::binder_impl::BorrowedParcel<'_>) -> std::result::Result<(), $crate::StatusCode> {
-                parcel.write(&self.0)
-            }
-        }
-
-        impl $crate::binder_impl::SerializeArray for $enum {
-            fn serialize_array(slice: &[Self], parcel: &mut $crate::binder_impl::BorrowedParcel<'_>) -> std::result::Result<(), $crate::StatusCode> {
-                let v: Vec<$backing> = slice.iter().map(|x| x.0).collect();
-                <$backing as $crate::binder_impl::SerializeArray>::serialize_array(&v[..], parcel)
-            }
-        }
-
-        impl $crate::binder_impl::Deserialize for $enum {
-            fn deserialize(parcel: &$crate::binder_impl::BorrowedParcel<'_>) -> std::result::Result<Self, $crate::StatusCode> {
-                parcel.read().map(Self)
-            }
-        }
-
-        impl $crate::binder_impl::DeserializeArray for $enum {
-            fn deserialize_array(parcel: &$crate::binder_impl::BorrowedParcel<'_>) -> std::result::Result<Option<Vec<Self>>, $crate::StatusCode> {
-                let v: Option<Vec<$backing>> =
-                    <$backing as $crate::binder_impl::DeserializeArray>::deserialize_array(parcel)?;
-                Ok(v.map(|v| v.into_iter().map(Self).collect()))
-            }
-        }
-    };
-}
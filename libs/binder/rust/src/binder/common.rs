@@ -0,0 +1,214 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Binder definitions shared with the pure Rust parcel codec.
+
+use crate::error::{Result, StatusCode};
+use crate::sys;
+
+use std::convert::TryFrom;
+use std::ptr;
+
+/// Binder action to perform.
+///
+/// This must be a number between [`FIRST_CALL_TRANSACTION`] and
+/// [`LAST_CALL_TRANSACTION`].
+pub type TransactionCode = u32;
+
+/// Additional operation flags.
+///
+/// `FLAG_*` values.
+pub type TransactionFlags = u32;
+
+/// First transaction code available for user commands (inclusive)
+pub const FIRST_CALL_TRANSACTION: TransactionCode = sys::FIRST_CALL_TRANSACTION;
+/// Last transaction code available for user commands (inclusive)
+pub const LAST_CALL_TRANSACTION: TransactionCode = sys::LAST_CALL_TRANSACTION;
+
+/// Equivalent of `B_PACK_CHARS` in C++, used for the reserved transaction
+/// codes.
+const fn pack_chars(c1: u8, c2: u8, c3: u8, c4: u8) -> TransactionCode {
+    (c1 as u32) << 24 | (c2 as u32) << 16 | (c3 as u32) << 8 | c4 as u32
+}
+
+// The reserved transaction codes below are handled by libbinder itself. The
+// NDK only allows user transaction codes to be sent, so they can't be used with
+// `IBinderInternal::transact`; `SpIBinder` has methods which send some of them,
+// and they are provided to identify these transactions, e.g. in logs.
+
+/// Reserved transaction code of `ping_binder`.
+pub const PING_TRANSACTION: TransactionCode = pack_chars(b'_', b'P', b'N', b'G');
+/// Reserved transaction code of `dump`.
+pub const DUMP_TRANSACTION: TransactionCode = pack_chars(b'_', b'D', b'M', b'P');
+/// Reserved transaction code of shell commands, see
+/// `Interface::handle_shell_command`.
+pub const SHELL_COMMAND_TRANSACTION: TransactionCode = pack_chars(b'_', b'C', b'M', b'D');
+/// Reserved transaction code which returns the interface descriptor.
+pub const INTERFACE_TRANSACTION: TransactionCode = pack_chars(b'_', b'N', b'T', b'F');
+/// Reserved transaction code which notifies a process that system properties
+/// changed.
+pub const SYSPROPS_TRANSACTION: TransactionCode = pack_chars(b'_', b'S', b'P', b'R');
+/// Reserved transaction code of `get_extension`.
+pub const EXTENSION_TRANSACTION: TransactionCode = pack_chars(b'_', b'E', b'X', b'T');
+/// Reserved transaction code which returns the PID of the hosting process.
+pub const DEBUG_PID_TRANSACTION: TransactionCode = pack_chars(b'_', b'P', b'I', b'D');
+/// Reserved transaction code which sets up an RPC binder client.
+pub const SET_RPC_CLIENT_TRANSACTION: TransactionCode = pack_chars(b'_', b'R', b'P', b'C');
+/// Reserved transaction code, see `android.os.IBinder.TWEET_TRANSACTION`.
+pub const TWEET_TRANSACTION: TransactionCode = pack_chars(b'_', b'T', b'W', b'T');
+/// Reserved transaction code, see `android.os.IBinder.LIKE_TRANSACTION`.
+pub const LIKE_TRANSACTION: TransactionCode = pack_chars(b'_', b'L', b'I', b'K');
+
+/// Corresponds to TF_ONE_WAY -- an asynchronous call.
+pub const FLAG_ONEWAY: TransactionFlags = sys::FLAG_ONEWAY;
+/// Corresponds to TF_CLEAR_BUF -- clear transaction buffers after call is made.
+pub const FLAG_CLEAR_BUF: TransactionFlags = sys::FLAG_CLEAR_BUF;
+
+/// Interface stability promise
+///
+/// An interface can promise to be a stable vendor interface ([`Vintf`]), or
+/// makes no stability guarantees ([`Local`]). [`Local`] is
+/// currently the default stability.
+///
+/// The stability of a binder is checked when it is used: transactions on a
+/// binder which isn't stable enough for the partition of the calling process,
+/// e.g. a [`Local`] system binder called from a vendor process, fail with
+/// [`StatusCode::BAD_TYPE`]. A binder can be made less stable with
+/// `SpIBinder::force_downgrade_to_local_stability`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stability {
+    /// Default stability, visible to other modules in the same compilation
+    /// context (e.g. modules on system.img)
+    ///
+    /// This is the stability that `SpIBinder::mark_compilation_unit_stability`
+    /// sets, like `AIBinder_markCompilationUnitStability` in C++.
+    Local,
+
+    /// A Vendor Interface Object, which promises to be stable
+    Vintf,
+}
+
+impl Default for Stability {
+    fn default() -> Self {
+        Stability::Local
+    }
+}
+
+impl From<Stability> for i32 {
+    fn from(stability: Stability) -> i32 {
+        use Stability::*;
+        match stability {
+            Local => 0,
+            Vintf => 1,
+        }
+    }
+}
+
+impl TryFrom<i32> for Stability {
+    type Error = StatusCode;
+    fn try_from(stability: i32) -> Result<Stability> {
+        use Stability::*;
+        match stability {
+            0 => Ok(Local),
+            1 => Ok(Vintf),
+            _ => Err(StatusCode::BAD_VALUE),
+        }
+    }
+}
+
+/// Trait for transparent Rust wrappers around android C++ native types.
+///
+/// The pointer return by this trait's methods should be immediately passed to
+/// C++ and not stored by Rust. The pointer is valid only as long as the
+/// underlying C++ object is alive, so users must be careful to take this into
+/// account, as Rust cannot enforce this.
+///
+/// # Safety
+///
+/// For this trait to be a correct implementation, `T` must be a valid android
+/// C++ type. Since we cannot constrain this via the type system, this trait is
+/// marked as unsafe.
+pub unsafe trait AsNative<T> {
+    /// Return a pointer to the native version of `self`
+    fn as_native(&self) -> *const T;
+
+    /// Return a mutable pointer to the native version of `self`
+    fn as_native_mut(&mut self) -> *mut T;
+}
+
+unsafe impl<T, V: AsNative<T>> AsNative<T> for Option<V> {
+    fn as_native(&self) -> *const T {
+        self.as_ref().map_or(ptr::null(), |v| v.as_native())
+    }
+
+    fn as_native_mut(&mut self) -> *mut T {
+        self.as_mut().map_or(ptr::null_mut(), |v| v.as_native_mut())
+    }
+}
+
+/// Declare an AIDL enumeration.
+///
+/// This is mainly used internally by the AIDL compiler.
+#[macro_export]
+macro_rules! declare_binder_enum {
+    {
+        $( #[$attr:meta] )*
+        $enum:ident : [$backing:ty; $size:expr] {
+            $( $( #[$value_attr:meta] )* $name:ident = $value:expr, )*
+        }
+    } => {
+        $( #[$attr] )*
+        #[derive(Debug, Default, Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash)]
+        #[allow(missing_docs)]
+        pub struct $enum(pub $backing);
+        impl $enum {
+            $( $( #[$value_attr] )* #[allow(missing_docs)] pub const $name: Self = Self($value); )*
+
+            #[inline(always)]
+            #[allow(missing_docs)]
+            pub const fn enum_values() -> [Self; $size] {
+                [$(Self::$name),*]
+            }
+        }
+
+        impl $crate::binder_impl::Serialize for $enum {
+            fn serialize(&self, parcel: &mut $crate::binder_impl::BorrowedParcel<'_>) -> std::result::Result<(), $crate::StatusCode> {
+                parcel.write(&self.0)
+            }
+        }
+
+        impl $crate::binder_impl::SerializeArray for $enum {
+            fn serialize_array(slice: &[Self], parcel: &mut $crate::binder_impl::BorrowedParcel<'_>) -> std::result::Result<(), $crate::StatusCode> {
+                let v: Vec<$backing> = slice.iter().map(|x| x.0).collect();
+                <$backing as $crate::binder_impl::SerializeArray>::serialize_array(&v[..], parcel)
+            }
+        }
+
+        impl $crate::binder_impl::Deserialize for $enum {
+            fn deserialize(parcel: &$crate::binder_impl::BorrowedParcel<'_>) -> std::result::Result<Self, $crate::StatusCode> {
+                parcel.read().map(Self)
+            }
+        }
+
+        impl $crate::binder_impl::DeserializeArray for $enum {
+            fn deserialize_array(parcel: &$crate::binder_impl::BorrowedParcel<'_>) -> std::result::Result<Option<Vec<Self>>, $crate::StatusCode> {
+                let v: Option<Vec<$backing>> =
+                    <$backing as $crate::binder_impl::DeserializeArray>::deserialize_array(parcel)?;
+                Ok(v.map(|v| v.into_iter().map(Self).collect()))
+            }
+        }
+    };
+}
@@ -43,7 +43,7 @@ pub fn status_result(status: status_t) -> Result<()> {
     }
 }
 
-fn parse_status_code(code: i32) -> StatusCode {
+pub(crate) fn parse_status_code(code: i32) -> StatusCode {
     match code {
         e if e == StatusCode::OK as i32 => StatusCode::OK,
         e if e == StatusCode::NO_MEMORY as i32 => StatusCode::NO_MEMORY,
@@ -69,7 +69,7 @@ fn parse_status_code(code: i32) -> StatusCode {
 
 pub use sys::android_c_interface_ExceptionCode as ExceptionCode;
 
-fn parse_exception_code(code: i32) -> ExceptionCode {
+pub(crate) fn parse_exception_code(code: i32) -> ExceptionCode {
     match code {
         e if e == ExceptionCode::NONE as i32 => ExceptionCode::NONE,
         e if e == ExceptionCode::SECURITY as i32 => ExceptionCode::SECURITY,
@@ -0,0 +1,305 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Interceptors which run around the transactions handled by a [`Binder`].
+//!
+//! [`Binder`]: crate::binder_impl::Binder
+
+use crate::binder::{Remotable, TransactionCode};
+use crate::error::{ExceptionCode, Result, Status, StatusCode};
+use crate::parcel::BorrowedParcel;
+use crate::state::TransactionContext;
+
+use std::convert::TryInto;
+use std::ffi::CStr;
+use std::fs::File;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The dump argument which appends the output of
+/// [`TransactionInterceptor::on_dump`] to the dump of a binder object, e.g.
+/// `dumpsys myservice --binder-stats`.
+///
+/// The argument is removed before the remaining arguments are passed to the
+/// remotable object and the interceptors.
+pub const BINDER_STATS_DUMP_ARG: &str = "--binder-stats";
+
+/// A hook which runs before and after every transaction handled by a local
+/// binder object, added with
+/// [`Binder::add_interceptor`](crate::binder_impl::Binder::add_interceptor).
+///
+/// Interceptors are useful for cross-cutting concerns such as audit logging,
+/// permission checks, fault injection and metrics, which would otherwise have
+/// to be repeated in each service's `on_transact`.
+pub trait TransactionInterceptor: Send + Sync {
+    /// Called before the transaction is passed to the remotable object.
+    ///
+    /// Returning an error rejects the transaction without calling the
+    /// remotable object or any later interceptors. A [`Status`] created from a
+    /// [`StatusCode`] fails the transaction with that code. Any other status
+    /// is written to the reply as an AIDL status header and the transaction
+    /// succeeds, so the client sees it as the result of the method call.
+    /// Interfaces that don't use status headers should only reject with a
+    /// `StatusCode`.
+    fn before_transact(&self, _info: &TransactionInfo<'_>) -> crate::Result<()> {
+        Ok(())
+    }
+
+    /// Called after the transaction has been handled, or rejected by an
+    /// interceptor.
+    ///
+    /// This is only called if [`before_transact`](Self::before_transact)
+    /// succeeded for this interceptor. `reply` is the reply to the
+    /// transaction so far, e.g. so that the AIDL status header that a service
+    /// wrote at its start can be inspected. `result` is the error returned by
+    /// the remotable object, or the status that an interceptor rejected the
+    /// transaction with.
+    fn after_transact(
+        &self,
+        _info: &TransactionInfo<'_>,
+        _reply: &BorrowedParcel<'_>,
+        _result: &crate::Result<()>,
+    ) {
+    }
+
+    /// Called after the remotable object has been dumped with
+    /// [`BINDER_STATS_DUMP_ARG`], to append the state of the interceptor to
+    /// the dump.
+    fn on_dump(&self, _file: &File, _args: &[&CStr]) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A description of a transaction, passed to the hooks of a
+/// [`TransactionInterceptor`].
+#[derive(Debug)]
+pub struct TransactionInfo<'a> {
+    descriptor: &'static str,
+    code: TransactionCode,
+    method_name: Option<&'static str>,
+    context: &'a TransactionContext,
+    data_size: usize,
+    start: Instant,
+}
+
+impl<'a> TransactionInfo<'a> {
+    /// The interface descriptor of the binder object handling the transaction.
+    pub fn descriptor(&self) -> &'static str {
+        self.descriptor
+    }
+
+    /// The transaction code.
+    pub fn code(&self) -> TransactionCode {
+        self.code
+    }
+
+    /// The name of the method which handles the transaction, if it is known.
+    /// See [`Remotable::get_transaction_name`].
+    ///
+    /// [`Remotable::get_transaction_name`]: crate::binder_impl::Remotable::get_transaction_name
+    pub fn method_name(&self) -> Option<&'static str> {
+        self.method_name
+    }
+
+    /// The identity of the caller and the flags of the transaction.
+    pub fn context(&self) -> &'a TransactionContext {
+        self.context
+    }
+
+    /// The size of the transaction data in bytes.
+    pub fn data_size(&self) -> usize {
+        self.data_size
+    }
+
+    /// The time since the transaction was received, before the first
+    /// interceptor ran.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Run `on_transact` of the object of type `T` wrapped in the given
+/// interceptors.
+///
+/// The `before_transact` hooks run in the order that the interceptors were
+/// added and the `after_transact` hooks run in the reverse order, so the first
+/// interceptor sees the transaction before and after all others.
+pub(crate) fn intercept_transact<T, F>(
+    interceptors: &[Arc<dyn TransactionInterceptor>],
+    code: TransactionCode,
+    data: &BorrowedParcel<'_>,
+    reply: &mut BorrowedParcel<'_>,
+    context: &TransactionContext,
+    on_transact: F,
+) -> Result<()>
+where
+    T: Remotable,
+    F: FnOnce(&BorrowedParcel<'_>, &mut BorrowedParcel<'_>) -> Result<()>,
+{
+    if interceptors.is_empty() {
+        return on_transact(data, reply);
+    }
+
+    let info = TransactionInfo {
+        descriptor: T::get_descriptor(),
+        code,
+        method_name: T::get_transaction_name(code),
+        context,
+        data_size: data.get_data_size().try_into().unwrap_or_default(),
+        start: Instant::now(),
+    };
+    let mut entered = 0;
+    let mut rejection = None;
+    for interceptor in interceptors {
+        if let Err(status) = interceptor.before_transact(&info) {
+            rejection = Some(status);
+            break;
+        }
+        entered += 1;
+    }
+
+    let (result, status) = match rejection {
+        None => {
+            let result = on_transact(data, reply);
+            let status = result.map_err(Status::from);
+            (result, status)
+        }
+        Some(status) => (reject(reply, &status), Err(status)),
+    };
+
+    for interceptor in interceptors[..entered].iter().rev() {
+        interceptor.after_transact(&info, reply, &status);
+    }
+    result
+}
+
+/// Report `status` to the client of a rejected transaction.
+fn reject(reply: &mut BorrowedParcel<'_>, status: &Status) -> Result<()> {
+    match status.exception_code() {
+        ExceptionCode::TRANSACTION_FAILED => match status.transaction_error() {
+            StatusCode::OK => Err(StatusCode::UNKNOWN_ERROR),
+            error => Err(error),
+        },
+        _ => reply.write(status),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TransactionInfo, TransactionInterceptor};
+    use crate::binder::{
+        BinderFeatures, IBinderInternal, Interface, Remotable, Stability, TransactionCode,
+        FIRST_CALL_TRANSACTION,
+    };
+    use crate::error::{ExceptionCode, Status, StatusCode};
+    use crate::native::Binder;
+    use crate::parcel::BorrowedParcel;
+    use crate::test_utils::Echo;
+
+    use std::sync::{Arc, Mutex};
+
+    const REJECTED_CODE: TransactionCode = FIRST_CALL_TRANSACTION + 1;
+    const SECURITY_CODE: TransactionCode = FIRST_CALL_TRANSACTION + 2;
+
+    /// Records the hooks it sees in a shared log, and rejects some codes.
+    struct Recorder {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TransactionInterceptor for Recorder {
+        fn before_transact(&self, info: &TransactionInfo<'_>) -> crate::Result<()> {
+            assert_eq!(info.descriptor(), Echo::get_descriptor());
+            self.log.lock().unwrap().push(format!("{} before {}", self.name, info.code()));
+            match info.code() {
+                REJECTED_CODE => Err(StatusCode::PERMISSION_DENIED.into()),
+                SECURITY_CODE => Err(Status::new_exception(ExceptionCode::SECURITY, None)),
+                _ => Ok(()),
+            }
+        }
+
+        fn after_transact(
+            &self,
+            info: &TransactionInfo<'_>,
+            _reply: &BorrowedParcel<'_>,
+            result: &crate::Result<()>,
+        ) {
+            let result = match result {
+                Ok(()) => "ok".to_string(),
+                Err(status) => format!("{:?}", status.exception_code()),
+            };
+            let entry = format!("{} after {} {}", self.name, info.code(), result);
+            self.log.lock().unwrap().push(entry);
+        }
+    }
+
+    #[test]
+    fn test_interceptors() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let mut echo = Binder::new(Echo);
+        echo.add_interceptor(Arc::new(Recorder { name: "outer", log: log.clone() }));
+        echo.add_interceptor(Arc::new(Recorder { name: "inner", log: log.clone() }));
+        let binder = echo.as_binder();
+
+        let reply = binder
+            .transact(FIRST_CALL_TRANSACTION, 0, |mut data| data.write(&42i32))
+            .expect("Transaction failed");
+        assert_eq!(reply.read::<i32>(), Ok(42));
+        assert_eq!(
+            log.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            ["outer before 1", "inner before 1", "inner after 1 ok", "outer after 1 ok"]
+        );
+
+        assert_eq!(
+            binder.transact(REJECTED_CODE, 0, |mut data| data.write(&42i32)).err(),
+            Some(StatusCode::PERMISSION_DENIED)
+        );
+        assert_eq!(
+            log.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            ["outer before 2", "outer after 2 TRANSACTION_FAILED"]
+        );
+
+        let reply = binder
+            .transact(SECURITY_CODE, 0, |mut data| data.write(&42i32))
+            .expect("Transaction failed");
+        let status: Status = reply.read().expect("Reply has no status header");
+        assert_eq!(status.exception_code(), ExceptionCode::SECURITY);
+        assert_eq!(
+            log.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            ["outer before 3", "outer after 3 SECURITY"]
+        );
+    }
+
+    #[test]
+    fn test_feature_interceptors() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let features = BinderFeatures {
+            interceptors: vec![Arc::new(Recorder { name: "feature", log: log.clone() })],
+            ..BinderFeatures::default()
+        };
+        let mut echo = Binder::new_with_features(Echo, Stability::default(), features);
+        echo.add_interceptor(Arc::new(Recorder { name: "added", log: log.clone() }));
+
+        assert_eq!(
+            echo.as_binder().transact(REJECTED_CODE, 0, |mut data| data.write(&42i32)).err(),
+            Some(StatusCode::PERMISSION_DENIED)
+        );
+        assert_eq!(
+            log.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            ["feature before 2", "feature after 2 TRANSACTION_FAILED"]
+        );
+    }
+}
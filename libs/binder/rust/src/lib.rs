@@ -21,6 +21,11 @@
 //! top of the binder NDK library to be usable by APEX modules, and therefore
 //! only exposes functionality available in the NDK interface.
 //!
+//! With the optional `tracing` feature, every transaction sent or handled by
+//! this crate is recorded in a `binder_transaction` span with the
+//! [`tracing`](https://docs.rs/tracing) crate. On Android, products enable
+//! it with the `libbinder_rs` soong config variable `tracing`.
+//!
 //! # Example
 //!
 //! The following example illustrates how the AIDL backend will use this crate.
@@ -100,24 +105,39 @@ mod proxy;
 mod binder;
 mod binder_async;
 mod error;
+mod interceptor;
 mod native;
 mod parcel;
+pub mod metrics;
+pub mod permission;
+mod service_handle;
+pub mod service_manager;
+pub mod shell;
 mod state;
+#[cfg(test)]
+mod test_utils;
+mod timeout_pool;
+mod trace;
 
 use binder_ndk_sys as sys;
 
-pub use binder::{BinderFeatures, FromIBinder, IBinder, Interface, Strong, Weak};
+pub use binder::{
+    BinderFeatures, FromIBinder, IBinder, Interface, SchedulerPolicy, Strong, Weak, WithTimeout,
+};
+pub use binder_macros::{interface, Deserialize, Parcelable, Serialize};
 pub use crate::binder_async::{BinderAsyncPool, BoxFuture};
 pub use error::{ExceptionCode, Status, StatusCode};
 pub use native::{
-    add_service, force_lazy_services_persist, is_handling_transaction, register_lazy_service,
+    add_service, force_lazy_services_persist, is_handling_transaction, re_register,
+    register_lazy_service, set_active_services_callback, try_unregister,
 };
 pub use parcel::{ParcelFileDescriptor, Parcelable, ParcelableHolder};
 pub use proxy::{
     get_declared_instances, get_interface, get_service, is_declared, wait_for_interface,
-    wait_for_service, DeathRecipient, SpIBinder, WpIBinder,
+    wait_for_service, DeathLink, DeathRecipient, SpIBinder, WpIBinder,
 };
-pub use state::{ProcessState, ThreadState};
+pub use service_handle::{RetryPolicy, ServiceHandle};
+pub use state::{BinderPoller, ProcessState, ThreadState, TransactionContext};
 
 /// Binder result containing a [`Status`] on error.
 pub type Result<T> = std::result::Result<T, Status>;
@@ -127,11 +147,16 @@ pub type Result<T> = std::result::Result<T, Status>;
 pub mod binder_impl {
     pub use crate::binder::{
         IBinderInternal, InterfaceClass, Remotable, Stability, ToAsyncInterface, ToSyncInterface,
-        TransactionCode, TransactionFlags, FIRST_CALL_TRANSACTION, FLAG_CLEAR_BUF, FLAG_ONEWAY,
-        FLAG_PRIVATE_LOCAL, LAST_CALL_TRANSACTION,
+        TransactionCode, TransactionFlags, DEBUG_PID_TRANSACTION, DEFAULT_MAX_TIMEOUT_CALLS,
+        DUMP_TRANSACTION,
+        EXTENSION_TRANSACTION, FIRST_CALL_TRANSACTION, FLAG_CLEAR_BUF, FLAG_ONEWAY,
+        FLAG_PRIVATE_LOCAL, INTERFACE_TRANSACTION, LAST_CALL_TRANSACTION, LIKE_TRANSACTION,
+        PING_TRANSACTION, SET_RPC_CLIENT_TRANSACTION, SHELL_COMMAND_TRANSACTION,
+        SYSPROPS_TRANSACTION, TWEET_TRANSACTION,
     };
     pub use crate::binder_async::BinderAsyncRuntime;
-    pub use crate::error::status_t;
+    pub use crate::error::{status_result, status_t};
+    pub use crate::interceptor::{TransactionInfo, TransactionInterceptor, BINDER_STATS_DUMP_ARG};
     pub use crate::native::Binder;
     pub use crate::parcel::{
         BorrowedParcel, Deserialize, DeserializeArray, DeserializeOption, Parcel,
@@ -0,0 +1,538 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Per-method metrics for the transactions of native services.
+//!
+//! [`TransactionMetrics`] counts the calls, errors and in-flight transactions
+//! of each transaction code, and records a histogram of their latencies. It is
+//! added to a service with
+//! [`Binder::add_interceptor`](crate::binder_impl::Binder::add_interceptor),
+//! and appends its statistics to the dump of the service when it is dumped
+//! with [`BINDER_STATS_DUMP_ARG`](crate::binder_impl::BINDER_STATS_DUMP_ARG):
+//!
+//! ```ignore
+//! let metrics = Arc::new(TransactionMetrics::for_aidl());
+//! binder.add_interceptor(metrics.clone());
+//! // Later, e.g. from `dumpsys myservice --binder-stats`, or directly:
+//! for (code, stats) in metrics.stats() {
+//!     println!("{}: {} calls", code, stats.calls());
+//! }
+//! ```
+
+use crate::binder::{TransactionCode, FIRST_CALL_TRANSACTION};
+use crate::error::{ExceptionCode, Result, Status, StatusCode};
+use crate::interceptor::{TransactionInfo, TransactionInterceptor};
+use crate::parcel::BorrowedParcel;
+
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{self, Write};
+use std::iter;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The upper bounds of the buckets of a [`LatencyHistogram`]. Latencies above
+/// the last bound are counted in an additional, unbounded bucket.
+pub const LATENCY_BUCKETS: [Duration; 8] = [
+    Duration::from_micros(100),
+    Duration::from_micros(500),
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(500),
+];
+
+/// The number of transaction codes from `FIRST_CALL_TRANSACTION` which
+/// [`TransactionMetrics`] tracks separately, in addition to the codes of all
+/// methods which the interface names. All other codes are counted together in
+/// [`TransactionMetrics::other_stats`], so that clients sending arbitrary
+/// codes can't grow the metrics without bound.
+pub const MAX_TRACKED_CODES: u32 = 256;
+
+/// The reason that a transaction failed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TransactionError {
+    /// The transaction failed with a status code.
+    Status(StatusCode),
+    /// The transaction failed with a service-specific or other exception.
+    Exception(ExceptionCode),
+}
+
+impl From<&Status> for TransactionError {
+    fn from(status: &Status) -> Self {
+        match status.exception_code() {
+            ExceptionCode::TRANSACTION_FAILED => Self::Status(status.transaction_error()),
+            exception => Self::Exception(exception),
+        }
+    }
+}
+
+/// A histogram of transaction latencies, with the buckets in
+/// [`LATENCY_BUCKETS`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LatencyHistogram {
+    counts: [u64; LATENCY_BUCKETS.len() + 1],
+    total: Duration,
+    max: Duration,
+}
+
+impl LatencyHistogram {
+    fn record(&mut self, latency: Duration) {
+        let bucket = LATENCY_BUCKETS.iter().position(|&bound| latency <= bound);
+        self.counts[bucket.unwrap_or(LATENCY_BUCKETS.len())] += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    /// The upper bound of each bucket, or `None` for the last, unbounded
+    /// bucket, and the number of latencies in it.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        let bounds = LATENCY_BUCKETS.iter().copied().map(Some).chain(iter::once(None));
+        bounds.zip(self.counts.iter().copied())
+    }
+
+    /// The number of recorded latencies.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The mean latency, or zero if none have been recorded.
+    pub fn mean(&self) -> Duration {
+        match self.count() {
+            0 => Duration::ZERO,
+            count => Duration::from_nanos((self.total.as_nanos() / u128::from(count)) as u64),
+        }
+    }
+
+    /// The highest recorded latency.
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+}
+
+/// The metrics of a single transaction code.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MethodStats {
+    name: Option<&'static str>,
+    calls: u64,
+    in_flight: u64,
+    errors: Vec<(TransactionError, u64)>,
+    latency: LatencyHistogram,
+}
+
+impl MethodStats {
+    /// The name of the method, if the interface declares it. See
+    /// [`TransactionInfo::method_name`].
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    /// The number of completed transactions, including failed ones.
+    pub fn calls(&self) -> u64 {
+        self.calls
+    }
+
+    /// The number of transactions which are currently being handled.
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight
+    }
+
+    /// The number of failed transactions for each error, in the order that
+    /// the errors first occurred.
+    pub fn errors(&self) -> &[(TransactionError, u64)] {
+        &self.errors
+    }
+
+    /// The total number of failed transactions.
+    pub fn error_count(&self) -> u64 {
+        self.errors.iter().map(|(_, count)| count).sum()
+    }
+
+    /// The latencies of the completed transactions.
+    pub fn latency(&self) -> &LatencyHistogram {
+        &self.latency
+    }
+
+    fn record_error(&mut self, error: TransactionError) {
+        match self.errors.iter_mut().find(|(e, _)| *e == error) {
+            Some((_, count)) => *count += 1,
+            None => self.errors.push((error, 1)),
+        }
+    }
+}
+
+/// A [`TransactionInterceptor`] which collects [`MethodStats`] for each
+/// transaction code.
+///
+/// Latencies are measured from when the transaction is received until all
+/// interceptors have finished, so this should be the first interceptor added
+/// to a service. The errors returned by the remotable object or by an
+/// interceptor are always counted. AIDL services instead write exceptions to
+/// the reply themselves, in a status header; metrics created with
+/// [`for_aidl`](Self::for_aidl) read that header to count them too.
+///
+/// Transaction codes which the interface doesn't name are only tracked
+/// separately up to [`MAX_TRACKED_CODES`].
+#[derive(Debug, Default)]
+pub struct TransactionMetrics {
+    methods: Mutex<Methods>,
+    read_status_header: bool,
+}
+
+#[derive(Debug, Default)]
+struct Methods {
+    codes: BTreeMap<TransactionCode, MethodStats>,
+    other: MethodStats,
+}
+
+impl Methods {
+    fn get_mut(&mut self, info: &TransactionInfo<'_>) -> &mut MethodStats {
+        let code = info.code();
+        if info.method_name().is_some()
+            || code.saturating_sub(FIRST_CALL_TRANSACTION) < MAX_TRACKED_CODES
+        {
+            let stats = self.codes.entry(code).or_default();
+            stats.name = info.method_name();
+            stats
+        } else {
+            &mut self.other
+        }
+    }
+}
+
+impl TransactionMetrics {
+    /// Create an empty set of metrics.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty set of metrics for an AIDL service, which also counts
+    /// the exceptions in the status header of successful replies.
+    pub fn for_aidl() -> Self {
+        Self { read_status_header: true, ..Default::default() }
+    }
+
+    /// A snapshot of the metrics of each transaction code which has been
+    /// received and is tracked separately.
+    pub fn stats(&self) -> BTreeMap<TransactionCode, MethodStats> {
+        self.methods.lock().unwrap().codes.clone()
+    }
+
+    /// A snapshot of the combined metrics of all transaction codes which are
+    /// not tracked separately. See [`MAX_TRACKED_CODES`].
+    pub fn other_stats(&self) -> MethodStats {
+        self.methods.lock().unwrap().other.clone()
+    }
+
+    /// Clear the metrics of all completed transactions. Transactions which
+    /// are in flight are still counted when they complete.
+    pub fn reset(&self) {
+        let methods = &mut *self.methods.lock().unwrap();
+        methods.codes.retain(|_, stats| stats.in_flight > 0);
+        for stats in methods.codes.values_mut().chain(iter::once(&mut methods.other)) {
+            *stats =
+                MethodStats { name: stats.name, in_flight: stats.in_flight, ..Default::default() };
+        }
+    }
+
+    /// Write the metrics in a human readable format, as used by
+    /// [`on_dump`](TransactionInterceptor::on_dump).
+    pub fn write_stats(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "Binder transaction stats:")?;
+        for (code, stats) in self.stats() {
+            write!(out, "  code {}", code)?;
+            if let Some(name) = stats.name {
+                write!(out, " ({})", name)?;
+            }
+            write_method_stats(out, &stats)?;
+        }
+        let other = self.other_stats();
+        if other.calls > 0 || other.in_flight > 0 {
+            write!(out, "  other codes")?;
+            write_method_stats(out, &other)?;
+        }
+        Ok(())
+    }
+
+    /// Count the exception in the status header at the start of `reply`, if
+    /// it isn't `OK`.
+    fn record_status_header(stats: &mut MethodStats, reply: &BorrowedParcel<'_>) {
+        if reply.get_data_size() <= 0 {
+            return;
+        }
+        let position = reply.get_data_position();
+        let rewound = unsafe {
+            // Safety: 0 is less than the size of the reply, which isn't
+            // empty.
+            reply.set_data_position(0)
+        };
+        if rewound.is_err() {
+            return;
+        }
+        if let Ok(status) = reply.read::<Status>() {
+            if !status.is_ok() {
+                stats.record_error((&status).into());
+            }
+        }
+        unsafe {
+            // Safety: `position` was the position of the reply before it was
+            // moved, so it is within its data.
+            let _ = reply.set_data_position(position);
+        }
+    }
+}
+
+/// Write the line with the counts of `stats`, after its label, and its errors
+/// and latencies.
+fn write_method_stats(out: &mut dyn Write, stats: &MethodStats) -> io::Result<()> {
+    writeln!(
+        out,
+        ": calls={} errors={} in_flight={} mean={:?} max={:?}",
+        stats.calls,
+        stats.error_count(),
+        stats.in_flight,
+        stats.latency.mean(),
+        stats.latency.max(),
+    )?;
+    for (error, count) in &stats.errors {
+        writeln!(out, "    {:?}: {}", error, count)?;
+    }
+    let buckets: Vec<_> = stats
+        .latency
+        .buckets()
+        .map(|(bound, count)| match bound {
+            Some(bound) => format!("<={:?}:{}", bound, count),
+            None => format!(">{:?}:{}", LATENCY_BUCKETS[LATENCY_BUCKETS.len() - 1], count),
+        })
+        .collect();
+    writeln!(out, "    latency {}", buckets.join(" "))
+}
+
+impl TransactionInterceptor for TransactionMetrics {
+    fn before_transact(&self, info: &TransactionInfo<'_>) -> crate::Result<()> {
+        let mut methods = self.methods.lock().unwrap();
+        methods.get_mut(info).in_flight += 1;
+        Ok(())
+    }
+
+    fn after_transact(
+        &self,
+        info: &TransactionInfo<'_>,
+        reply: &BorrowedParcel<'_>,
+        result: &crate::Result<()>,
+    ) {
+        let latency = info.elapsed();
+        let mut methods = self.methods.lock().unwrap();
+        let stats = methods.get_mut(info);
+        stats.in_flight = stats.in_flight.saturating_sub(1);
+        stats.calls += 1;
+        stats.latency.record(latency);
+        match result {
+            Err(status) => stats.record_error(status.into()),
+            Ok(()) if self.read_status_header => Self::record_status_header(stats, reply),
+            Ok(()) => {}
+        }
+    }
+
+    fn on_dump(&self, file: &File, _args: &[&CStr]) -> Result<()> {
+        let mut file = file;
+        self.write_stats(&mut file).map_err(|_| StatusCode::UNKNOWN_ERROR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LatencyHistogram, MethodStats, TransactionError, LATENCY_BUCKETS};
+    use crate::error::{ExceptionCode, Status, StatusCode};
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_latency_histogram() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.mean(), Duration::ZERO);
+
+        histogram.record(Duration::from_micros(50));
+        histogram.record(Duration::from_micros(100));
+        histogram.record(Duration::from_millis(2));
+        histogram.record(Duration::from_secs(1));
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.max(), Duration::from_secs(1));
+        assert_eq!(histogram.mean(), Duration::from_nanos(250_537_500));
+
+        let buckets: Vec<_> = histogram.buckets().collect();
+        assert_eq!(buckets.len(), LATENCY_BUCKETS.len() + 1);
+        assert_eq!(buckets[0], (Some(Duration::from_micros(100)), 2));
+        assert_eq!(buckets[3], (Some(Duration::from_millis(5)), 1));
+        assert_eq!(buckets[LATENCY_BUCKETS.len()], (None, 1));
+    }
+
+    #[test]
+    fn test_record_error() {
+        let mut stats = MethodStats::default();
+        stats.record_error((&Status::from(StatusCode::DEAD_OBJECT)).into());
+        stats.record_error((&Status::new_exception(ExceptionCode::SECURITY, None)).into());
+        stats.record_error((&Status::from(StatusCode::DEAD_OBJECT)).into());
+        assert_eq!(
+            stats.errors(),
+            [
+                (TransactionError::Status(StatusCode::DEAD_OBJECT), 2),
+                (TransactionError::Exception(ExceptionCode::SECURITY), 1),
+            ]
+        );
+        assert_eq!(stats.error_count(), 3);
+    }
+
+    mod transactions {
+        use super::super::{TransactionError, TransactionMetrics, MAX_TRACKED_CODES};
+        use crate::binder::{
+            IBinderInternal, Interface, Remotable, TransactionCode, FIRST_CALL_TRANSACTION,
+        };
+        use crate::error::{ExceptionCode, Result, Status, StatusCode};
+        use crate::native::Binder;
+        use crate::parcel::BorrowedParcel;
+        use crate::test_utils::Echo;
+
+        use std::ffi::CStr;
+        use std::fs::File;
+        use std::io::Read;
+        use std::os::unix::io::FromRawFd;
+        use std::sync::Arc;
+
+        /// Replies with an `IllegalStateException`, as an AIDL service does.
+        struct Thrower;
+
+        impl Remotable for Thrower {
+            fn get_descriptor() -> &'static str {
+                "android.os.MetricsThrowerTest"
+            }
+
+            fn on_transact(
+                &self,
+                _code: TransactionCode,
+                _data: &BorrowedParcel<'_>,
+                reply: &mut BorrowedParcel<'_>,
+            ) -> Result<()> {
+                reply.write(&Status::new_exception(ExceptionCode::ILLEGAL_STATE, None))
+            }
+
+            fn on_dump(&self, _file: &File, _args: &[&CStr]) -> Result<()> {
+                Ok(())
+            }
+
+            binder_fn_get_class!(Binder::<Self>);
+        }
+        #[test]
+        fn test_transaction_metrics() {
+            let metrics = Arc::new(TransactionMetrics::new());
+            let mut echo = Binder::new(Echo);
+            echo.add_interceptor(metrics.clone());
+            let mut binder = echo.as_binder();
+
+            let reply = binder
+                .transact(FIRST_CALL_TRANSACTION, 0, |mut data| data.write(&42i32))
+                .expect("Transaction failed");
+            assert_eq!(reply.read::<i32>(), Ok(42));
+            assert_eq!(
+                binder.transact(FIRST_CALL_TRANSACTION, 0, |_| Ok(())).err(),
+                Some(StatusCode::NOT_ENOUGH_DATA)
+            );
+
+            let stats = metrics.stats();
+            let echo_stats = &stats[&FIRST_CALL_TRANSACTION];
+            assert_eq!(echo_stats.name(), Some("echo"));
+            assert_eq!(echo_stats.calls(), 2);
+            assert_eq!(echo_stats.in_flight(), 0);
+            assert_eq!(
+                echo_stats.errors(),
+                [(TransactionError::Status(StatusCode::NOT_ENOUGH_DATA), 1)]
+            );
+            assert_eq!(echo_stats.latency().count(), 2);
+
+            let mut fds = [0; 2];
+            assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+            let mut read_end = unsafe { File::from_raw_fd(fds[0]) };
+            let write_end = unsafe { File::from_raw_fd(fds[1]) };
+            binder.dump(&write_end, &["-a", "--binder-stats"]).expect("Dump failed");
+            drop(write_end);
+            let mut output = String::new();
+            read_end.read_to_string(&mut output).unwrap();
+            assert!(output.starts_with("echo [\"-a\"]\nBinder transaction stats:\n"), "{}", output);
+            assert!(output.contains("  code 1 (echo): calls=2 errors=1 in_flight=0"), "{}", output);
+
+            metrics.reset();
+            assert!(metrics.stats().is_empty());
+        }
+
+        #[test]
+        fn test_aidl_exceptions() {
+            let metrics = Arc::new(TransactionMetrics::for_aidl());
+            let mut thrower = Binder::new(Thrower);
+            thrower.add_interceptor(metrics.clone());
+            let binder = thrower.as_binder();
+
+            let reply =
+                binder.transact(FIRST_CALL_TRANSACTION, 0, |_| Ok(())).expect("Transaction failed");
+            // The reply is still read from the start.
+            assert_eq!(reply.get_data_position(), 0);
+            let status: Status = reply.read().expect("Could not read the status");
+            assert_eq!(status.exception_code(), ExceptionCode::ILLEGAL_STATE);
+
+            let stats = metrics.stats();
+            assert_eq!(
+                stats[&FIRST_CALL_TRANSACTION].errors(),
+                [(TransactionError::Exception(ExceptionCode::ILLEGAL_STATE), 1)]
+            );
+
+            // Without reading the status header, the transaction succeeded.
+            let metrics = Arc::new(TransactionMetrics::new());
+            let mut thrower = Binder::new(Thrower);
+            thrower.add_interceptor(metrics.clone());
+            thrower.as_binder().transact(FIRST_CALL_TRANSACTION, 0, |_| Ok(())).unwrap();
+            assert_eq!(metrics.stats()[&FIRST_CALL_TRANSACTION].error_count(), 0);
+        }
+
+        #[test]
+        fn test_untracked_codes() {
+            let metrics = Arc::new(TransactionMetrics::new());
+            let mut echo = Binder::new(Echo);
+            echo.add_interceptor(metrics.clone());
+            let binder = echo.as_binder();
+
+            let last_tracked = FIRST_CALL_TRANSACTION + MAX_TRACKED_CODES - 1;
+            for code in [last_tracked, last_tracked + 1, last_tracked + 2] {
+                binder.transact(code, 0, |mut data| data.write(&1i32)).unwrap();
+            }
+
+            let stats = metrics.stats();
+            assert_eq!(stats.keys().copied().collect::<Vec<_>>(), [last_tracked]);
+            let other = metrics.other_stats();
+            assert_eq!(other.name(), None);
+            assert_eq!(other.calls(), 2);
+
+            let mut output = Vec::new();
+            metrics.write_stats(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("  other codes: calls=2 errors=0"), "{}", output);
+
+            metrics.reset();
+            assert_eq!(metrics.other_stats().calls(), 0);
+        }
+    }
+}
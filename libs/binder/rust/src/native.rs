@@ -15,11 +15,14 @@
  */
 
 use crate::binder::{
-    AsNative, Interface, InterfaceClassMethods, Remotable, Stability, TransactionCode,
+    AsNative, BinderFeatures, Interface, InterfaceClassMethods, Remotable, SchedulerPolicy,
+    Stability, TransactionCode,
 };
 use crate::error::{status_result, status_t, Result, StatusCode};
+use crate::interceptor::{TransactionInterceptor, BINDER_STATS_DUMP_ARG};
 use crate::parcel::{BorrowedParcel, Serialize};
 use crate::proxy::SpIBinder;
+use crate::shell::IShellCallback;
 use crate::sys;
 
 use std::convert::TryFrom;
@@ -29,7 +32,9 @@ use std::mem::ManuallyDrop;
 use std::ops::Deref;
 use std::os::raw::c_char;
 use std::os::unix::io::FromRawFd;
+use std::ptr;
 use std::slice;
+use std::sync::{Arc, Mutex, RwLock};
 
 /// Rust wrapper around Binder remotable objects.
 ///
@@ -38,14 +43,21 @@ use std::slice;
 #[repr(C)]
 pub struct Binder<T: Remotable> {
     ibinder: *mut sys::AIBinder,
-    rust_object: *mut T,
+    user_data: *mut BinderUserData<T>,
+}
+
+/// The user data of the `AIBinder` owned by a [`Binder`].
+struct BinderUserData<T> {
+    rust_object: T,
+    interceptors: RwLock<Arc<[Arc<dyn TransactionInterceptor>]>>,
 }
 
 /// # Safety
 ///
 /// A `Binder<T>` is a pair of unique owning pointers to two values:
 ///   * a C++ ABBinder which the C++ API guarantees can be passed between threads
-///   * a Rust object which implements `Remotable`; this trait requires `Send + Sync`
+///   * a Rust object which implements `Remotable`; this trait requires `Send + Sync`,
+///     together with its interceptors, which are also `Send + Sync`
 ///
 /// Both pointers are unique (never escape the `Binder<T>` object and are not copied)
 /// so we can essentially treat `Binder<T>` as a box-like containing the two objects;
@@ -57,10 +69,12 @@ unsafe impl<T: Remotable> Send for Binder<T> {}
 ///
 /// A `Binder<T>` is a pair of unique owning pointers to two values:
 ///   * a C++ ABBinder which is thread-safe, i.e. `Send + Sync`
-///   * a Rust object which implements `Remotable`; this trait requires `Send + Sync`
+///   * a Rust object which implements `Remotable`; this trait requires `Send + Sync`,
+///     together with its interceptors, which are also `Send + Sync`
 ///
 /// `ABBinder` contains an immutable `mUserData` pointer, which is actually a
-/// pointer to a boxed `T: Remotable`, which is `Sync`. `ABBinder` also contains
+/// pointer to a boxed `BinderUserData<T>` containing a `T: Remotable`, which is
+/// `Sync`. The interceptors are behind a lock, so they are also `Sync`. `ABBinder` also contains
 /// a mutable pointer to its class, but mutation of this field is controlled by
 /// a mutex and it is only allowed to be set once, therefore we can concurrently
 /// access this field safely. `ABBinder` inherits from `BBinder`, which is also
@@ -86,8 +100,25 @@ impl<T: Remotable> Binder<T> {
     /// This moves the `rust_object` into an owned [`Box`] and Binder will
     /// manage its lifetime.
     pub fn new_with_stability(rust_object: T, stability: Stability) -> Binder<T> {
+        Self::new_with_features(rust_object, stability, BinderFeatures::default())
+    }
+
+    /// Create a new Binder remotable object with the given stability and
+    /// features.
+    ///
+    /// The features are applied before the object is returned, so the
+    /// interceptors in `features` see every transaction on the object, unlike
+    /// those added later with [`add_interceptor`](Self::add_interceptor).
+    pub fn new_with_features(
+        rust_object: T,
+        stability: Stability,
+        features: BinderFeatures,
+    ) -> Binder<T> {
         let class = T::get_class();
-        let rust_object = Box::into_raw(Box::new(rust_object));
+        let user_data = Box::into_raw(Box::new(BinderUserData {
+            rust_object,
+            interceptors: RwLock::new(features.interceptors.into()),
+        }));
         let ibinder = unsafe {
             // Safety: `AIBinder_new` expects a valid class pointer (which we
             // initialize via `get_class`), and an arbitrary pointer
@@ -95,13 +126,27 @@ impl<T: Remotable> Binder<T> {
             // is a strong reference to a `BBinder`. This reference should be
             // decremented via `AIBinder_decStrong` when the reference lifetime
             // ends.
-            sys::AIBinder_new(class.into(), rust_object as *mut c_void)
+            sys::AIBinder_new(class.into(), user_data as *mut c_void)
         };
         let mut binder = Binder {
             ibinder,
-            rust_object,
+            user_data,
         };
         binder.mark_stability(stability);
+        #[cfg(not(android_vndk))]
+        crate::binder::IBinderInternal::set_requesting_sid(
+            &mut binder,
+            features.set_requesting_sid,
+        );
+        if let Some(mut extension) = features.extension {
+            // This can only fail if one of the binders is null, which is
+            // impossible.
+            binder.set_extension(&mut extension).expect("Failed to set extension");
+        }
+        if let Some(policy) = features.min_scheduler_policy {
+            binder.set_min_scheduler_policy(policy);
+        }
+        binder.set_inherit_rt(features.inherit_rt);
         binder
     }
 
@@ -190,6 +235,57 @@ impl<T: Remotable> Binder<T> {
         status_result(status)
     }
 
+    /// Set the minimum scheduler policy that incoming transactions are handled
+    /// with. This should be called immediately when the object is created
+    /// before it is passed to another process.
+    pub fn set_min_scheduler_policy(&mut self, policy: SchedulerPolicy) {
+        unsafe {
+            // Safety: `AIBinder_setMinSchedulerPolicy` expects a valid, local
+            // `AIBinder` pointer, which `self` always contains. It aborts on
+            // invalid values, but the `SchedulerPolicy` constructors have
+            // already checked them.
+            sys::AIBinder_setMinSchedulerPolicy(
+                self.as_native_mut(),
+                policy.policy(),
+                policy.priority(),
+            );
+        }
+    }
+
+    /// Set whether incoming transactions inherit the realtime scheduling
+    /// policy of the caller. This should be called immediately when the
+    /// object is created before it is passed to another process.
+    pub fn set_inherit_rt(&mut self, inherit_rt: bool) {
+        unsafe {
+            // Safety: `AIBinder_setInheritRt` expects a valid, local `AIBinder`
+            // pointer, which `self` always contains.
+            sys::AIBinder_setInheritRt(self.as_native_mut(), inherit_rt);
+        }
+    }
+
+    /// Add an interceptor which runs around every transaction handled by this
+    /// object, see [`TransactionInterceptor`].
+    ///
+    /// Interceptors see transactions in the order that they were added, so
+    /// the first interceptor runs before and after all others. The
+    /// interceptors apply to all references to the underlying binder object,
+    /// including those created before this call, but transactions which are
+    /// already being handled aren't intercepted. Interceptors which must see
+    /// every transaction, such as permission checks, should be passed in
+    /// [`BinderFeatures::interceptors`] instead.
+    pub fn add_interceptor(&mut self, interceptor: Arc<dyn TransactionInterceptor>) {
+        let user_data = unsafe {
+            // Safety: While `self` is alive, the reference count of the
+            // underlying object is > 0 and therefore `on_destroy` cannot be
+            // called, so `user_data` is a valid pointer.
+            &*self.user_data
+        };
+        let mut interceptors = user_data.interceptors.write().unwrap();
+        let mut new_interceptors = interceptors.to_vec();
+        new_interceptors.push(interceptor);
+        *interceptors = new_interceptors.into();
+    }
+
     /// Retrieve the interface descriptor string for this object's Binder
     /// interface.
     pub fn get_descriptor() -> &'static str {
@@ -259,29 +355,80 @@ impl<T: Remotable> InterfaceClassMethods for Binder<T> {
         <T as Remotable>::get_descriptor()
     }
 
+    fn get_transaction_names() -> &'static [&'static str] {
+        <T as Remotable>::get_transaction_names()
+    }
+
+    /// Called whenever a transaction needs to be processed by a local
+    /// implementation, if the NDK doesn't pass on the flags of the
+    /// transaction.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`on_transact_with_flags`](Self::on_transact_with_flags).
+    unsafe extern "C" fn on_transact(
+        binder: *mut sys::AIBinder,
+        code: u32,
+        data: *const sys::AParcel,
+        reply: *mut sys::AParcel,
+    ) -> status_t {
+        Self::on_transact_with_flags(binder, code, data, reply, 0)
+    }
+
     /// Called whenever a transaction needs to be processed by a local
     /// implementation.
     ///
     /// # Safety
     ///
     /// Must be called with a non-null, valid pointer to a local `AIBinder` that
-    /// contains a `T` pointer in its user data. The `data` and `reply` parcel
-    /// parameters must be valid pointers to `AParcel` objects. This method does
-    /// not take ownership of any of its parameters.
+    /// contains a `BinderUserData<T>` pointer in its user data. The `data` and
+    /// `reply` parcel parameters must be valid pointers to `AParcel` objects.
+    /// This method does not take ownership of any of its parameters.
     ///
     /// These conditions hold when invoked by `ABBinder::onTransact`.
-    unsafe extern "C" fn on_transact(
+    unsafe extern "C" fn on_transact_with_flags(
         binder: *mut sys::AIBinder,
         code: u32,
         data: *const sys::AParcel,
         reply: *mut sys::AParcel,
+        flags: sys::binder_flags_t,
     ) -> status_t {
         let res = {
             let mut reply = BorrowedParcel::from_raw(reply).unwrap();
             let data = BorrowedParcel::from_raw(data as *mut sys::AParcel).unwrap();
             let object = sys::AIBinder_getUserData(binder);
-            let binder: &T = &*(object as *const T);
-            binder.on_transact(code, &data, &mut reply)
+            let user_data = &*(object as *const BinderUserData<T>);
+            // Take a snapshot so that the lock isn't held during the
+            // transaction, in case the object adds interceptors to itself.
+            let interceptors = user_data.interceptors.read().unwrap().clone();
+            // Vendor binders can't request the security context of callers.
+            #[cfg(not(android_vndk))]
+            let requesting_sid = sys::AIBinder_isRequestingSid(binder);
+            #[cfg(android_vndk)]
+            let requesting_sid = false;
+            let context = crate::state::TransactionContext::new(flags, requesting_sid);
+            let _current = context.enter();
+            let span = crate::trace::TransactionSpan::incoming(
+                T::get_descriptor(),
+                code,
+                T::get_transaction_name(code),
+                data.get_data_size(),
+                &context,
+            );
+            let res = span.in_scope(|| {
+                crate::interceptor::intercept_transact::<T, _>(
+                    &interceptors,
+                    code,
+                    &data,
+                    &mut reply,
+                    &context,
+                    |data, reply| {
+                        user_data.rust_object.on_transact_with_context(code, data, reply, &context)
+                    },
+                )
+            });
+            span.record_result(Some(reply.get_data_size()), &res);
+            res
         };
         match res {
             Ok(()) => 0i32,
@@ -294,21 +441,23 @@ impl<T: Remotable> InterfaceClassMethods for Binder<T> {
     ///
     /// # Safety
     ///
-    /// Must be called with a valid pointer to a `T` object. After this call,
-    /// the pointer will be invalid and should not be dereferenced.
+    /// Must be called with a valid pointer to a `BinderUserData<T>` object.
+    /// After this call, the pointer will be invalid and should not be
+    /// dereferenced.
     unsafe extern "C" fn on_destroy(object: *mut c_void) {
-        Box::from_raw(object as *mut T);
+        Box::from_raw(object as *mut BinderUserData<T>);
     }
 
     /// Called whenever a new, local `AIBinder` object is needed of a specific
     /// class.
     ///
     /// Constructs the user data pointer that will be stored in the object,
-    /// which will be a heap-allocated `T` object.
+    /// which will be a heap-allocated `BinderUserData<T>` object.
     ///
     /// # Safety
     ///
-    /// Must be called with a valid pointer to a `T` object allocated via `Box`.
+    /// Must be called with a valid pointer to a `BinderUserData<T>` object
+    /// allocated via `Box`.
     unsafe extern "C" fn on_create(args: *mut c_void) -> *mut c_void {
         // We just return the argument, as it is already a pointer to the rust
         // object created by Box.
@@ -320,9 +469,9 @@ impl<T: Remotable> InterfaceClassMethods for Binder<T> {
     /// # Safety
     ///
     /// Must be called with a non-null, valid pointer to a local `AIBinder` that
-    /// contains a `T` pointer in its user data. fd should be a non-owned file
-    /// descriptor, and args must be an array of null-terminated string
-    /// poiinters with length num_args.
+    /// contains a `BinderUserData<T>` pointer in its user data. fd should be a
+    /// non-owned file descriptor, and args must be an array of null-terminated
+    /// string poiinters with length num_args.
     unsafe extern "C" fn on_dump(
         binder: *mut sys::AIBinder,
         fd: i32,
@@ -342,8 +491,101 @@ impl<T: Remotable> InterfaceClassMethods for Binder<T> {
         let args: Vec<_> = args.iter().map(|s| CStr::from_ptr(*s)).collect();
 
         let object = sys::AIBinder_getUserData(binder);
-        let binder: &T = &*(object as *const T);
-        let res = binder.on_dump(&file, &args);
+        let user_data = &*(object as *const BinderUserData<T>);
+        let is_stats_arg = |arg: &&CStr| arg.to_bytes() == BINDER_STATS_DUMP_ARG.as_bytes();
+        let res = if args.iter().any(is_stats_arg) {
+            let args: Vec<_> = args.into_iter().filter(|arg| !is_stats_arg(arg)).collect();
+            user_data.rust_object.on_dump(&file, &args).and_then(|()| {
+                let interceptors = user_data.interceptors.read().unwrap().clone();
+                interceptors.iter().try_for_each(|interceptor| interceptor.on_dump(&file, &args))
+            })
+        } else {
+            user_data.rust_object.on_dump(&file, &args)
+        };
+
+        match res {
+            Ok(()) => 0,
+            Err(e) => e as status_t,
+        }
+    }
+
+    /// Called to handle the shell command transaction, if the NDK doesn't
+    /// pass on the shell callback.
+    ///
+    /// # Safety
+    ///
+    /// Same as
+    /// [`on_shell_command_with_callback`](Self::on_shell_command_with_callback).
+    unsafe extern "C" fn on_shell_command(
+        binder: *mut sys::AIBinder,
+        in_fd: i32,
+        out_fd: i32,
+        err_fd: i32,
+        argv: *mut *const c_char,
+        argc: u32,
+    ) -> status_t {
+        Self::on_shell_command_with_callback(
+            binder,
+            in_fd,
+            out_fd,
+            err_fd,
+            argv,
+            argc,
+            ptr::null_mut(),
+        )
+    }
+
+    /// Called to handle the shell command transaction.
+    ///
+    /// # Safety
+    ///
+    /// Must be called with a non-null, valid pointer to a local `AIBinder` that
+    /// contains a `BinderUserData<T>` pointer in its user data. The file
+    /// descriptors should be non-owned, and argv must be an array of
+    /// null-terminated string pointers with length argc, or null if argc is 0.
+    /// shell_callback must be null or a valid, non-owned pointer to an
+    /// `AIBinder`.
+    unsafe extern "C" fn on_shell_command_with_callback(
+        binder: *mut sys::AIBinder,
+        in_fd: i32,
+        out_fd: i32,
+        err_fd: i32,
+        argv: *mut *const c_char,
+        argc: u32,
+        shell_callback: *mut sys::AIBinder,
+    ) -> status_t {
+        if in_fd < 0 || out_fd < 0 || err_fd < 0 {
+            return StatusCode::UNEXPECTED_NULL as status_t;
+        }
+        // We don't own these files, so we need to be careful not to drop them.
+        let input = ManuallyDrop::new(File::from_raw_fd(in_fd));
+        let output = ManuallyDrop::new(File::from_raw_fd(out_fd));
+        let error = ManuallyDrop::new(File::from_raw_fd(err_fd));
+
+        let args: Vec<_> = if argc == 0 {
+            vec![]
+        } else if argv.is_null() {
+            return StatusCode::UNEXPECTED_NULL as status_t;
+        } else {
+            let argv = slice::from_raw_parts(argv, argc as usize);
+            argv.iter().map(|s| CStr::from_ptr(*s)).collect()
+        };
+
+        let shell_callback = if shell_callback.is_null() {
+            None
+        } else {
+            // We don't own this reference, so take a new one for the
+            // `SpIBinder` to release.
+            sys::AIBinder_incStrong(shell_callback);
+            SpIBinder::from_raw(shell_callback)
+        };
+        // A callback of the wrong type is ignored, like in C++.
+        let shell_callback = shell_callback
+            .and_then(|callback| callback.into_interface::<dyn IShellCallback>().ok());
+
+        let object = sys::AIBinder_getUserData(binder);
+        let binder: &T = &(*(object as *const BinderUserData<T>)).rust_object;
+        let res = binder.on_shell_command(&input, &output, &error, &args, shell_callback.as_ref());
 
         match res {
             Ok(()) => 0,
@@ -376,9 +618,9 @@ impl<T: Remotable> Deref for Binder<T> {
             // Safety: While `self` is alive, the reference count of the
             // underlying object is > 0 and therefore `on_destroy` cannot be
             // called. Therefore while `self` is alive, we know that
-            // `rust_object` is still a valid pointer to a heap allocated object
-            // of type `T`.
-            &*self.rust_object
+            // `user_data` is still a valid pointer to a heap allocated object
+            // of type `BinderUserData<T>`.
+            &(*self.user_data).rust_object
         }
     }
 }
@@ -415,7 +657,7 @@ impl<B: Remotable> TryFrom<SpIBinder> for Binder<B> {
         let mut ibinder = ManuallyDrop::new(ibinder);
         Ok(Binder {
             ibinder: ibinder.as_native_mut(),
-            rust_object: userdata as *mut B,
+            user_data: userdata as *mut BinderUserData<B>,
         })
     }
 }
@@ -493,6 +735,76 @@ pub fn force_lazy_services_persist(persist: bool) {
     }
 }
 
+/// The callback registered with [`set_active_services_callback`].
+type ActiveServicesCallback = Arc<dyn Fn(bool) -> bool + Send + Sync>;
+
+/// The only active services callback of the process. libbinder_ndk keeps a
+/// single callback too, so registering a new one replaces and drops the old
+/// one.
+static ACTIVE_SERVICES_CALLBACK: Mutex<Option<ActiveServicesCallback>> = Mutex::new(None);
+
+/// Set a callback that is invoked when the number of lazy services with
+/// clients registered by this process drops to zero, or becomes nonzero.
+///
+/// The callback's argument is true if at least one service has clients. When
+/// there are no clients, the process is shut down after the callback returns
+/// false. The callback may instead call [`try_unregister`], perform any cleanup
+/// and exit the process itself, or return true to keep the process running.
+///
+/// Calling this again replaces the previous callback, which is dropped once it
+/// is no longer running. It should be called before [`register_lazy_service`].
+pub fn set_active_services_callback<F>(callback: F)
+where
+    F: Fn(bool) -> bool + Send + Sync + 'static,
+{
+    unsafe extern "C" fn active_services_callback(
+        has_clients: bool,
+        _context: *mut c_void,
+    ) -> bool {
+        // Clone the callback so that it can replace itself while it runs.
+        let callback = ACTIVE_SERVICES_CALLBACK.lock().unwrap().clone();
+        match callback {
+            Some(callback) => callback(has_clients),
+            // Like without a callback, let the process shut down.
+            None => false,
+        }
+    }
+
+    *ACTIVE_SERVICES_CALLBACK.lock().unwrap() = Some(Arc::new(callback));
+    unsafe {
+        // Safety: The callback doesn't use its context, and only accesses the
+        // global callback, which is `Send + Sync` so it can be called from any
+        // binder thread.
+        sys::AServiceManager_setActiveServicesCallback(
+            Some(active_services_callback),
+            std::ptr::null_mut(),
+        )
+    }
+}
+
+/// Try to unregister all lazy services registered by this process, so that it
+/// can shut down.
+///
+/// Returns false if any of the services has clients, in which case they are
+/// still registered, and [`re_register`] should be called to undo any partial
+/// unregistration.
+pub fn try_unregister() -> bool {
+    unsafe {
+        // Safety: No borrowing or transfer of ownership occurs here.
+        sys::AServiceManager_tryUnregister()
+    }
+}
+
+/// Re-register the lazy services which were unregistered by [`try_unregister`].
+///
+/// This should be called if `try_unregister` fails, on the same thread.
+pub fn re_register() {
+    unsafe {
+        // Safety: No borrowing or transfer of ownership occurs here.
+        sys::AServiceManager_reRegister()
+    }
+}
+
 /// Tests often create a base BBinder instance; so allowing the unit
 /// type to be remotable translates nicely to Binder::new(()).
 impl Remotable for () {
@@ -526,3 +838,149 @@ pub fn is_handling_transaction() -> bool {
         sys::AIBinder_isHandlingTransaction()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Binder;
+    use crate::binder::{
+        AsNative, BinderFeatures, Interface, InterfaceClassMethods, Remotable, Strong,
+        TransactionCode,
+    };
+    use crate::error::{Result, StatusCode};
+    use crate::parcel::{BorrowedParcel, ParcelFileDescriptor};
+    use crate::shell::{BnShellCallback, IShellCallback};
+
+    use std::ffi::{CStr, CString};
+    use std::fs::{self, File};
+    use std::io::{self, Read, Write};
+    use std::os::raw::c_char;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::ptr;
+
+    struct ShellService;
+
+    impl Remotable for ShellService {
+        fn get_descriptor() -> &'static str {
+            "android.os.ShellService"
+        }
+
+        fn on_transact(
+            &self,
+            _code: TransactionCode,
+            _data: &BorrowedParcel<'_>,
+            _reply: &mut BorrowedParcel<'_>,
+        ) -> Result<()> {
+            Err(StatusCode::UNKNOWN_TRANSACTION)
+        }
+
+        fn on_dump(&self, _file: &File, _args: &[&CStr]) -> Result<()> {
+            Ok(())
+        }
+
+        fn on_shell_command(
+            &self,
+            _input: &File,
+            mut output: &File,
+            _error: &File,
+            args: &[&CStr],
+            shell_callback: Option<&Strong<dyn IShellCallback>>,
+        ) -> Result<()> {
+            let args: Vec<_> = args.iter().map(|arg| arg.to_str().unwrap()).collect();
+            match (args.as_slice(), shell_callback) {
+                ([], _) => Err(StatusCode::BAD_VALUE),
+                (["cat", path], Some(shell_callback)) => {
+                    let file = shell_callback
+                        .open_file(path, "", "r")
+                        .map_err(|_| StatusCode::PERMISSION_DENIED)?
+                        .ok_or(StatusCode::PERMISSION_DENIED)?;
+                    io::copy(&mut File::from(file), &mut output)
+                        .map_err(|_| StatusCode::UNKNOWN_ERROR)?;
+                    Ok(())
+                }
+                (["cat", _], None) => Err(StatusCode::UNEXPECTED_NULL),
+                _ => writeln!(output, "{}", args.join(" ")).map_err(|_| StatusCode::UNKNOWN_ERROR),
+            }
+        }
+
+        binder_fn_get_class!(Binder::<Self>);
+    }
+
+    /// Opens files for reading on behalf of the shell service.
+    struct ShellCallback;
+
+    impl Interface for ShellCallback {}
+
+    impl IShellCallback for ShellCallback {
+        fn open_file(
+            &self,
+            path: &str,
+            _selinux_context: &str,
+            mode: &str,
+        ) -> crate::Result<Option<ParcelFileDescriptor>> {
+            assert_eq!(mode, "r");
+            Ok(File::open(path).ok().map(ParcelFileDescriptor::new))
+        }
+    }
+
+    fn shell_command(
+        binder: &mut Binder<ShellService>,
+        args: &[&str],
+        shell_callback: Option<&Strong<dyn IShellCallback>>,
+    ) -> (i32, String) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut read_end = unsafe { File::from_raw_fd(fds[0]) };
+        let write_end = unsafe { File::from_raw_fd(fds[1]) };
+        let null = File::open("/dev/null").unwrap();
+
+        let args: Vec<_> = args.iter().map(|arg| CString::new(*arg).unwrap()).collect();
+        let mut argv: Vec<*const c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+        let mut shell_callback = shell_callback.map(|callback| callback.as_binder());
+        let status = unsafe {
+            <Binder<ShellService> as InterfaceClassMethods>::on_shell_command_with_callback(
+                binder.as_native_mut(),
+                null.as_raw_fd(),
+                write_end.as_raw_fd(),
+                null.as_raw_fd(),
+                argv.as_mut_ptr(),
+                argv.len() as u32,
+                shell_callback
+                    .as_mut()
+                    .map_or(ptr::null_mut(), |callback| callback.as_native_mut()),
+            )
+        };
+        drop(write_end);
+
+        let mut output = String::new();
+        read_end.read_to_string(&mut output).unwrap();
+        (status, output)
+    }
+
+    #[test]
+    fn test_on_shell_command() {
+        let mut binder = Binder::new(ShellService);
+        assert_eq!(
+            shell_command(&mut binder, &["hello", "world"], None),
+            (0, "hello world\n".into())
+        );
+        assert_eq!(
+            shell_command(&mut binder, &[], None),
+            (StatusCode::BAD_VALUE as i32, String::new())
+        );
+    }
+
+    #[test]
+    fn test_on_shell_command_with_callback() {
+        let mut binder = Binder::new(ShellService);
+        let shell_callback = BnShellCallback::new_binder(ShellCallback, BinderFeatures::default());
+        let version = fs::read_to_string("/proc/version").unwrap();
+        assert_eq!(
+            shell_command(&mut binder, &["cat", "/proc/version"], Some(&shell_callback)),
+            (0, version)
+        );
+        assert_eq!(
+            shell_command(&mut binder, &["cat", "/proc/version"], None),
+            (StatusCode::UNEXPECTED_NULL as i32, String::new())
+        );
+    }
+}
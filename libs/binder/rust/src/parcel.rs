@@ -18,13 +18,13 @@
 
 use crate::binder::AsNative;
 use crate::error::{status_result, Result, StatusCode};
-use crate::proxy::SpIBinder;
 use crate::sys;
 
 use std::convert::TryInto;
 use std::marker::PhantomData;
 use std::mem::ManuallyDrop;
-use std::ptr::{self, NonNull};
+use std::os::raw::c_ulong;
+use std::ptr::NonNull;
 use std::fmt;
 
 mod file_descriptor;
@@ -37,6 +37,11 @@ pub use self::parcelable::{
     Parcelable, NON_NULL_PARCELABLE_FLAG, NULL_PARCELABLE_FLAG,
 };
 pub use self::parcelable_holder::{ParcelableHolder, ParcelableMetadata};
+// Used by `SpIBinder::get_remote_descriptor`, which isn't part of the parcel
+// codec crate.
+#[cfg(not(android_vndk))]
+#[allow(unused_imports)]
+pub(crate) use self::parcelable::allocate_vec_with_buffer;
 
 /// Container for a message (data and object references) that can be sent
 /// through Binder.
@@ -131,6 +136,37 @@ impl Parcel {
             &*(self as *const Parcel as *const BorrowedParcel<'_>)
         }
     }
+
+    /// Copy the raw contents of this `Parcel` into a byte vector.
+    ///
+    /// The returned bytes can be turned back into a `Parcel` with
+    /// [`Parcel::unmarshal`]. Only parcels that contain plain data can be
+    /// marshalled; if the parcel holds any binder objects or file descriptors,
+    /// `StatusCode::INVALID_OPERATION` is returned.
+    pub fn marshal(&self) -> Result<Vec<u8>> {
+        self.borrowed_ref().marshal()
+    }
+
+    /// Create a new `Parcel` from raw bytes previously produced by
+    /// [`Parcel::marshal`].
+    ///
+    /// The data position of the returned parcel is set to the start of the
+    /// data, so it is ready to be read from.
+    pub fn unmarshal(data: &[u8]) -> Result<Parcel> {
+        let mut parcel = Parcel::new();
+        let status = unsafe {
+            // Safety: `Parcel` always contains a valid pointer to an `AParcel`.
+            // `data` is a valid slice of `data.len()` bytes which is only
+            // read (and copied) for the duration of the call.
+            sys::AParcel_unmarshal(parcel.as_native_mut(), data.as_ptr(), data.len() as c_ulong)
+        };
+        status_result(status)?;
+        unsafe {
+            // Safety: 0 is always a valid data position.
+            parcel.set_data_position(0)?;
+        }
+        Ok(parcel)
+    }
 }
 
 impl Default for Parcel {
@@ -182,6 +218,26 @@ impl<'a> BorrowedParcel<'a> {
             _lifetime: PhantomData,
         }
     }
+
+    /// Copy the raw contents of this parcel into a byte vector.
+    ///
+    /// The returned bytes can be turned back into a `Parcel` with
+    /// [`Parcel::unmarshal`]. Only parcels that contain plain data can be
+    /// marshalled; if the parcel holds any binder objects or file descriptors,
+    /// `StatusCode::INVALID_OPERATION` is returned.
+    pub fn marshal(&self) -> Result<Vec<u8>> {
+        let len: usize = self.get_data_size().try_into().or(Err(StatusCode::BAD_VALUE))?;
+        let mut buffer = vec![0u8; len];
+        let status = unsafe {
+            // Safety: `BorrowedParcel` always contains a valid pointer to an
+            // `AParcel`. `buffer` is a valid, writable allocation of exactly
+            // `len` bytes, and `AParcel_marshal` checks that `start` and `len`
+            // are in bounds of the parcel data before copying into it.
+            sys::AParcel_marshal(self.as_native(), buffer.as_mut_ptr(), 0, len as c_ulong)
+        };
+        status_result(status)?;
+        Ok(buffer)
+    }
 }
 
 /// # Safety
@@ -661,47 +717,6 @@ impl Parcel {
     }
 }
 
-// Internal APIs
-impl<'a> BorrowedParcel<'a> {
-    pub(crate) fn write_binder(&mut self, binder: Option<&SpIBinder>) -> Result<()> {
-        unsafe {
-            // Safety: `BorrowedParcel` always contains a valid pointer to an
-            // `AParcel`. `AsNative` for `Option<SpIBinder`> will either return
-            // null or a valid pointer to an `AIBinder`, both of which are
-            // valid, safe inputs to `AParcel_writeStrongBinder`.
-            //
-            // This call does not take ownership of the binder. However, it does
-            // require a mutable pointer, which we cannot extract from an
-            // immutable reference, so we clone the binder, incrementing the
-            // refcount before the call. The refcount will be immediately
-            // decremented when this temporary is dropped.
-            status_result(sys::AParcel_writeStrongBinder(
-                self.as_native_mut(),
-                binder.cloned().as_native_mut(),
-            ))
-        }
-    }
-
-    pub(crate) fn read_binder(&self) -> Result<Option<SpIBinder>> {
-        let mut binder = ptr::null_mut();
-        let status = unsafe {
-            // Safety: `BorrowedParcel` always contains a valid pointer to an
-            // `AParcel`. We pass a valid, mutable out pointer to the `binder`
-            // parameter. After this call, `binder` will be either null or a
-            // valid pointer to an `AIBinder` owned by the caller.
-            sys::AParcel_readStrongBinder(self.as_native(), &mut binder)
-        };
-
-        status_result(status)?;
-
-        Ok(unsafe {
-            // Safety: `binder` is either null or a valid, owned pointer at this
-            // point, so can be safely passed to `SpIBinder::from_raw`.
-            SpIBinder::from_raw(binder)
-        })
-    }
-}
-
 impl Drop for Parcel {
     fn drop(&mut self) {
         // Run the C++ Parcel complete object destructor
@@ -745,8 +760,6 @@ fn test_read_write() {
     assert_eq!(parcel.read::<Option<String>>(), Ok(None));
     assert_eq!(parcel.read::<String>(), Err(StatusCode::UNEXPECTED_NULL));
 
-    assert_eq!(parcel.borrowed_ref().read_binder().err(), Some(StatusCode::BAD_TYPE));
-
     parcel.write(&1i32).unwrap();
 
     unsafe {
@@ -963,3 +976,24 @@ fn test_append_from() {
     assert_eq!(Err(StatusCode::BAD_VALUE), parcel2.append_from(&parcel1, -1, 4));
     assert_eq!(Err(StatusCode::BAD_VALUE), parcel2.append_from(&parcel1, 2, -1));
 }
+
+#[test]
+fn test_marshal_unmarshal() {
+    let mut parcel = Parcel::new();
+    parcel.write(&42i32).unwrap();
+    parcel.write("Hello, Binder!").unwrap();
+    parcel.write(&[1u8, 2u8, 3u8][..]).unwrap();
+
+    let bytes = parcel.marshal().expect("Could not marshal parcel");
+    assert_eq!(bytes.len() as i32, parcel.get_data_size());
+
+    let unmarshalled = Parcel::unmarshal(&bytes).expect("Could not unmarshal parcel");
+    assert_eq!(unmarshalled.get_data_size(), parcel.get_data_size());
+    assert_eq!(unmarshalled.get_data_position(), 0);
+    assert_eq!(Ok(42), unmarshalled.read::<i32>());
+    assert_eq!(Ok("Hello, Binder!".to_string()), unmarshalled.read::<String>());
+    assert_eq!(Ok(vec![1u8, 2u8, 3u8]), unmarshalled.read::<Vec<u8>>());
+
+    assert_eq!(Ok(vec![]), Parcel::new().marshal());
+    assert_eq!(0, Parcel::unmarshal(&[]).unwrap().get_data_size());
+}
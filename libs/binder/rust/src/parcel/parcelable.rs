@@ -14,10 +14,9 @@
  * limitations under the License.
  */
 
-use crate::binder::{AsNative, FromIBinder, Stability, Strong};
+use crate::binder::{AsNative, Stability};
 use crate::error::{status_result, status_t, Result, Status, StatusCode};
 use crate::parcel::BorrowedParcel;
-use crate::proxy::SpIBinder;
 use crate::sys;
 
 use std::convert::{TryFrom, TryInto};
@@ -246,7 +245,7 @@ pub trait DeserializeOption: Deserialize {
 /// The opaque data pointer passed to the array read function must be a mutable
 /// pointer to an `Option<Vec<MaybeUninit<T>>>`. `buffer` will be assigned a mutable pointer
 /// to the allocated vector data if this function returns true.
-unsafe extern "C" fn allocate_vec_with_buffer<T>(
+pub(crate) unsafe extern "C" fn allocate_vec_with_buffer<T>(
     data: *mut c_void,
     len: i32,
     buffer: *mut *mut T,
@@ -733,36 +732,6 @@ impl Deserialize for Status {
     }
 }
 
-impl<T: Serialize + FromIBinder + ?Sized> Serialize for Strong<T> {
-    fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
-        Serialize::serialize(&**self, parcel)
-    }
-}
-
-impl<T: SerializeOption + FromIBinder + ?Sized> SerializeOption for Strong<T> {
-    fn serialize_option(this: Option<&Self>, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
-        SerializeOption::serialize_option(this.map(|b| &**b), parcel)
-    }
-}
-
-impl<T: Serialize + FromIBinder + ?Sized> SerializeArray for Strong<T> {}
-
-impl<T: FromIBinder + ?Sized> Deserialize for Strong<T> {
-    fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<Self> {
-        let ibinder: SpIBinder = parcel.read()?;
-        FromIBinder::try_from(ibinder)
-    }
-}
-
-impl<T: FromIBinder + ?Sized> DeserializeOption for Strong<T> {
-    fn deserialize_option(parcel: &BorrowedParcel<'_>) -> Result<Option<Self>> {
-        let ibinder: Option<SpIBinder> = parcel.read()?;
-        ibinder.map(FromIBinder::try_from).transpose()
-    }
-}
-
-impl<T: FromIBinder + ?Sized> DeserializeArray for Strong<T> {}
-
 // We need these to support Option<&T> for all T
 impl<T: Serialize + ?Sized> Serialize for &T {
     fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
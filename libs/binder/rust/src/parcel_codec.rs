@@ -0,0 +1,72 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+
+//! The parcel codec of the binder crate, built without `libbinder_ndk`.
+//!
+//! This crate shares the `parcel` and `error` modules with `libbinder_rs`, but
+//! builds them against a pure Rust implementation of the `AParcel` and
+//! `AStatus` APIs instead of `binder_ndk_sys`. It encodes and decodes the same
+//! wire format as the NDK, so it can be used where `libbinder_ndk` is not
+//! available. Binder objects are implemented in Rust and can only be sent over
+//! RPC sessions, such as those of `binder_rpc`. Everything that needs the
+//! binder driver is not part of this crate.
+
+mod binder {
+    mod common;
+
+    pub use self::common::{
+        AsNative, Stability, TransactionCode, TransactionFlags, DEBUG_PID_TRANSACTION,
+        DUMP_TRANSACTION, EXTENSION_TRANSACTION, FIRST_CALL_TRANSACTION, FLAG_CLEAR_BUF,
+        FLAG_ONEWAY, INTERFACE_TRANSACTION, LAST_CALL_TRANSACTION, LIKE_TRANSACTION,
+        PING_TRANSACTION, SET_RPC_CLIENT_TRANSACTION, SHELL_COMMAND_TRANSACTION,
+        SYSPROPS_TRANSACTION, TWEET_TRANSACTION,
+    };
+}
+mod error;
+// `Parcel::into_raw` is only used to hand parcels to libbinder_ndk.
+#[allow(dead_code)]
+mod parcel;
+#[path = "parcel_codec/proxy.rs"]
+mod proxy;
+#[path = "parcel_codec/sys.rs"]
+mod sys;
+
+pub use binder_macros::{Deserialize, Parcelable, Serialize};
+pub use error::{ExceptionCode, Status, StatusCode};
+pub use parcel::{ParcelFileDescriptor, Parcelable, ParcelableHolder};
+pub use proxy::{SpIBinder, WpIBinder};
+
+/// Binder result containing a [`Status`] on error.
+pub type Result<T> = std::result::Result<T, Status>;
+
+/// Advanced parcel APIs needed internally by AIDL.
+pub mod binder_impl {
+    pub use crate::binder::{
+        Stability, TransactionCode, TransactionFlags, DEBUG_PID_TRANSACTION, DUMP_TRANSACTION,
+        EXTENSION_TRANSACTION, FIRST_CALL_TRANSACTION, FLAG_CLEAR_BUF, FLAG_ONEWAY,
+        INTERFACE_TRANSACTION, LAST_CALL_TRANSACTION, LIKE_TRANSACTION, PING_TRANSACTION,
+        SET_RPC_CLIENT_TRANSACTION, SHELL_COMMAND_TRANSACTION, SYSPROPS_TRANSACTION,
+        TWEET_TRANSACTION,
+    };
+    pub use crate::error::{status_result, status_t};
+    pub use crate::parcel::{
+        BorrowedParcel, Deserialize, DeserializeArray, DeserializeOption, Parcel,
+        ParcelableMetadata, Serialize, SerializeArray, SerializeOption, NON_NULL_PARCELABLE_FLAG,
+        NULL_PARCELABLE_FLAG,
+    };
+    pub use crate::proxy::{BinderObject, RpcParcelSession};
+}
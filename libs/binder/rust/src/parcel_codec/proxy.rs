@@ -0,0 +1,347 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Binder objects of the pure Rust parcel codec.
+//!
+//! Without `libbinder_ndk`, an [`SpIBinder`] is a reference counted handle to
+//! a [`BinderObject`] implemented in Rust: either an object hosted by this
+//! process, or a proxy provided by an RPC implementation such as
+//! `binder_rpc`. Binders can only be written to and read from parcels which
+//! were marked for an RPC session with [`Parcel::mark_for_rpc`], since only
+//! the session knows how to encode them.
+
+use crate::binder::{AsNative, TransactionCode, TransactionFlags};
+use crate::error::{Result, StatusCode};
+use crate::parcel::{
+    BorrowedParcel, Deserialize, DeserializeArray, DeserializeOption, Parcel, Serialize,
+    SerializeArray, SerializeOption,
+};
+
+use downcast_rs::{impl_downcast, DowncastSync};
+use std::fmt;
+use std::sync::{Arc, Weak};
+
+/// The implementation of a binder object behind an [`SpIBinder`].
+pub trait BinderObject: DowncastSync {
+    /// Create a parcel that can be used with [`SpIBinder::submit_transact`].
+    ///
+    /// Proxies return a parcel marked for their session, so that binders can
+    /// be written to it.
+    fn prepare_transact(&self) -> Result<Parcel> {
+        Ok(Parcel::new())
+    }
+
+    /// Handle a transaction, or send it to the remote object for a proxy.
+    ///
+    /// `reply` is ignored for oneway transactions.
+    fn transact(
+        &self,
+        code: TransactionCode,
+        data: &BorrowedParcel<'_>,
+        reply: &mut BorrowedParcel<'_>,
+        flags: TransactionFlags,
+    ) -> Result<()>;
+}
+
+impl_downcast!(sync BinderObject);
+
+/// Encodes the binders in the parcels of an RPC session, like `RpcState` does
+/// for the C++ parcels marked for RPC.
+pub trait RpcParcelSession: Send + Sync {
+    /// Write `binder` to `parcel`, to be sent over the session.
+    fn write_binder(
+        &self,
+        parcel: &mut BorrowedParcel<'_>,
+        binder: Option<&SpIBinder>,
+    ) -> Result<()>;
+
+    /// Read a binder received over the session from `parcel`.
+    fn read_binder(&self, parcel: &BorrowedParcel<'_>) -> Result<Option<SpIBinder>>;
+}
+
+/// A strong reference to a binder object.
+///
+/// This is the pure Rust equivalent of the `SpIBinder` of `libbinder_rs`.
+#[derive(Clone)]
+pub struct SpIBinder(Arc<dyn BinderObject>);
+
+impl SpIBinder {
+    /// Create a binder for `object`.
+    pub fn new(object: impl BinderObject) -> Self {
+        Self(Arc::new(object))
+    }
+
+    /// Return the object behind this binder if it is a `T`.
+    pub fn downcast_ref<T: BinderObject>(&self) -> Option<&T> {
+        self.0.as_ref().downcast_ref()
+    }
+
+    /// Creates a new weak reference to this binder object.
+    pub fn downgrade(&self) -> WpIBinder {
+        WpIBinder(Arc::downgrade(&self.0))
+    }
+
+    /// Create a Parcel that can be used with `submit_transact`.
+    pub fn prepare_transact(&self) -> Result<Parcel> {
+        self.0.prepare_transact()
+    }
+
+    /// Perform a generic operation with the object, and return its reply.
+    pub fn submit_transact(
+        &self,
+        code: TransactionCode,
+        data: Parcel,
+        flags: TransactionFlags,
+    ) -> Result<Parcel> {
+        // Like with the kernel driver, both parcels are read from the start.
+        let mut reply = Parcel::new();
+        unsafe {
+            // Safety: The start of the parcel is always a valid position.
+            data.set_data_position(0)?;
+        }
+        self.0.transact(code, data.borrowed_ref(), &mut reply.borrowed(), flags)?;
+        unsafe {
+            // Safety: The start of the parcel is always a valid position.
+            reply.set_data_position(0)?;
+        }
+        Ok(reply)
+    }
+
+    /// Deliver a transaction to the object with the given parcels, e.g. one
+    /// that an RPC session received for a local object.
+    pub fn transact(
+        &self,
+        code: TransactionCode,
+        data: &BorrowedParcel<'_>,
+        reply: &mut BorrowedParcel<'_>,
+        flags: TransactionFlags,
+    ) -> Result<()> {
+        self.0.transact(code, data, reply, flags)
+    }
+}
+
+impl fmt::Debug for SpIBinder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("SpIBinder")
+    }
+}
+
+impl PartialEq for SpIBinder {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SpIBinder {}
+
+/// A weak reference to a binder object.
+#[derive(Clone)]
+pub struct WpIBinder(Weak<dyn BinderObject>);
+
+impl WpIBinder {
+    /// Promote this weak reference to a strong reference to the binder object.
+    pub fn promote(&self) -> Option<SpIBinder> {
+        self.0.upgrade().map(SpIBinder)
+    }
+
+    /// Whether the binder object was destroyed, i.e. `promote` can't succeed
+    /// anymore.
+    pub fn is_dead(&self) -> bool {
+        self.0.strong_count() == 0
+    }
+}
+
+impl fmt::Debug for WpIBinder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("WpIBinder")
+    }
+}
+
+impl PartialEq for WpIBinder {
+    fn eq(&self, other: &Self) -> bool {
+        Weak::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for WpIBinder {}
+
+impl Parcel {
+    /// Mark this parcel for an RPC session, which encodes the binders written
+    /// to and read from it, like `Parcel::markForRpc` in C++.
+    pub fn mark_for_rpc(&mut self, session: Arc<dyn RpcParcelSession>) {
+        self.borrowed().mark_for_rpc(session)
+    }
+}
+
+impl<'a> BorrowedParcel<'a> {
+    /// Mark this parcel for an RPC session, which encodes the binders written
+    /// to and read from it, like `Parcel::markForRpc` in C++.
+    pub fn mark_for_rpc(&mut self, session: Arc<dyn RpcParcelSession>) {
+        unsafe {
+            // Safety: `BorrowedParcel` always contains a valid pointer to an
+            // `AParcel`, which we have exclusive access to.
+            (*self.as_native_mut()).mark_for_rpc(session)
+        }
+    }
+
+    fn rpc_session(&self) -> Option<Arc<dyn RpcParcelSession>> {
+        unsafe {
+            // Safety: `BorrowedParcel` always contains a valid pointer to an
+            // `AParcel`.
+            (*self.as_native()).rpc_session()
+        }
+    }
+
+    /// Binders can only be written to parcels marked for an RPC session, so
+    /// this returns `INVALID_OPERATION` for other parcels.
+    pub(crate) fn write_binder(&mut self, binder: Option<&SpIBinder>) -> Result<()> {
+        match self.rpc_session() {
+            Some(session) => session.write_binder(self, binder),
+            None => Err(StatusCode::INVALID_OPERATION),
+        }
+    }
+
+    /// Binders can only be read from parcels marked for an RPC session, so
+    /// this returns `BAD_TYPE` for other parcels.
+    pub(crate) fn read_binder(&self) -> Result<Option<SpIBinder>> {
+        match self.rpc_session() {
+            Some(session) => session.read_binder(self),
+            None => Err(StatusCode::BAD_TYPE),
+        }
+    }
+}
+
+impl Serialize for SpIBinder {
+    fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        parcel.write_binder(Some(self))
+    }
+}
+
+impl SerializeOption for SpIBinder {
+    fn serialize_option(this: Option<&Self>, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
+        parcel.write_binder(this)
+    }
+}
+
+impl SerializeArray for SpIBinder {}
+
+impl Deserialize for SpIBinder {
+    fn deserialize(parcel: &BorrowedParcel<'_>) -> Result<SpIBinder> {
+        parcel.read_binder().transpose().unwrap_or(Err(StatusCode::UNEXPECTED_NULL))
+    }
+}
+
+impl DeserializeOption for SpIBinder {
+    fn deserialize_option(parcel: &BorrowedParcel<'_>) -> Result<Option<SpIBinder>> {
+        parcel.read_binder()
+    }
+}
+
+impl DeserializeArray for SpIBinder {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A local object which echoes strings.
+    struct Echo;
+
+    impl BinderObject for Echo {
+        fn transact(
+            &self,
+            _code: TransactionCode,
+            data: &BorrowedParcel<'_>,
+            reply: &mut BorrowedParcel<'_>,
+            _flags: TransactionFlags,
+        ) -> Result<()> {
+            reply.write(&data.read::<String>()?)
+        }
+    }
+
+    /// A session which encodes binders as indices into a table, like a
+    /// minimal `RpcState`.
+    #[derive(Default)]
+    struct TableSession(std::sync::Mutex<Vec<SpIBinder>>);
+
+    impl RpcParcelSession for TableSession {
+        fn write_binder(
+            &self,
+            parcel: &mut BorrowedParcel<'_>,
+            binder: Option<&SpIBinder>,
+        ) -> Result<()> {
+            match binder {
+                Some(binder) => {
+                    let mut table = self.0.lock().unwrap();
+                    table.push(binder.clone());
+                    parcel.write(&(table.len() as i32 - 1))
+                }
+                None => parcel.write(&-1i32),
+            }
+        }
+
+        fn read_binder(&self, parcel: &BorrowedParcel<'_>) -> Result<Option<SpIBinder>> {
+            let index: i32 = parcel.read()?;
+            if index < 0 {
+                return Ok(None);
+            }
+            self.0
+                .lock()
+                .unwrap()
+                .get(index as usize)
+                .cloned()
+                .map(Some)
+                .ok_or(StatusCode::BAD_VALUE)
+        }
+    }
+
+    #[test]
+    fn test_transact_local_object() {
+        let binder = SpIBinder::new(Echo);
+        let mut data = binder.prepare_transact().unwrap();
+        data.write("hello").unwrap();
+        let reply = binder.submit_transact(1, data, 0).unwrap();
+        assert_eq!(reply.read::<String>().as_deref(), Ok("hello"));
+        assert!(binder.downcast_ref::<Echo>().is_some());
+    }
+
+    #[test]
+    fn test_binder_needs_rpc_session() {
+        let binder = SpIBinder::new(Echo);
+        let mut parcel = Parcel::new();
+        assert_eq!(parcel.write(&binder), Err(StatusCode::INVALID_OPERATION));
+        assert_eq!(parcel.read::<Option<SpIBinder>>(), Err(StatusCode::BAD_TYPE));
+
+        parcel.mark_for_rpc(Arc::new(TableSession::default()));
+        parcel.write(&binder).unwrap();
+        parcel.write(&None::<SpIBinder>).unwrap();
+        unsafe {
+            parcel.set_data_position(0).unwrap();
+        }
+        assert_eq!(parcel.read::<SpIBinder>(), Ok(binder.clone()));
+        assert_eq!(parcel.read::<SpIBinder>(), Err(StatusCode::UNEXPECTED_NULL));
+    }
+
+    #[test]
+    fn test_weak_binder() {
+        let binder = SpIBinder::new(Echo);
+        let weak = binder.downgrade();
+        assert_eq!(weak, binder.downgrade());
+        assert_eq!(weak.promote(), Some(binder.clone()));
+        drop(binder);
+        assert!(weak.is_dead());
+        assert_eq!(weak.promote(), None);
+    }
+}
@@ -0,0 +1,1173 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Pure Rust implementation of the `AParcel` and `AStatus` APIs.
+//!
+//! This module takes the place of `binder_ndk_sys` as `crate::sys` in the
+//! `libbinder_rs_parcel` crate, so that the parcel codec can be built and
+//! tested without `libbinder_ndk`. It produces the same wire format as
+//! `android::Parcel` for plain data: every value is padded to 4 bytes, strings
+//! are written as a UTF-16 length followed by null-terminated UTF-16 data, and
+//! null arrays and strings have a length of -1.
+//!
+//! Binder objects are encoded by the RPC session that a parcel is marked for,
+//! see `crate::proxy`. Non-null file descriptors are rejected with
+//! `StatusCode::INVALID_OPERATION` when written and `StatusCode::BAD_TYPE`
+//! when read.
+//!
+//! The encoding logic lives in safe methods on [`AParcel`] and [`AStatus`].
+//! The `AParcel_*` and `AStatus_*` functions are thin wrappers with the same
+//! signatures as their NDK counterparts so that the `parcel` and `error`
+//! modules do not need to know which backend they are built against.
+
+#![allow(non_camel_case_types, non_snake_case)]
+
+use crate::error::{
+    parse_exception_code, parse_status_code, status_result, ExceptionCode, Result, StatusCode,
+};
+use crate::proxy::RpcParcelSession;
+
+use std::cell::Cell;
+use std::convert::TryInto;
+use std::ffi::{c_void, CStr, CString};
+use std::fmt;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_ulong};
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+
+/// Equivalent of `binder_status_t`.
+pub type binder_status_t = i32;
+
+/// Equivalent of `FIRST_CALL_TRANSACTION` in `android/binder_ibinder.h`.
+pub const FIRST_CALL_TRANSACTION: u32 = 0x00000001;
+/// Equivalent of `LAST_CALL_TRANSACTION` in `android/binder_ibinder.h`.
+pub const LAST_CALL_TRANSACTION: u32 = 0x00ffffff;
+
+/// Equivalent of `FLAG_ONEWAY` in `android/binder_ibinder.h`.
+pub const FLAG_ONEWAY: u32 = 0x01;
+/// Equivalent of `FLAG_CLEAR_BUF` in `android/binder_ibinder_platform.h`.
+pub const FLAG_CLEAR_BUF: u32 = 0x20;
+
+/// Equivalent of the `STATUS_*` values in `android/binder_status.h`.
+#[repr(i32)]
+#[non_exhaustive]
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub enum android_c_interface_StatusCode {
+    OK = 0,
+    UNKNOWN_ERROR = i32::MIN,
+    NO_MEMORY = -libc::ENOMEM,
+    INVALID_OPERATION = -libc::ENOSYS,
+    BAD_VALUE = -libc::EINVAL,
+    BAD_TYPE = i32::MIN + 1,
+    NAME_NOT_FOUND = -libc::ENOENT,
+    PERMISSION_DENIED = -libc::EPERM,
+    NO_INIT = -libc::ENODEV,
+    ALREADY_EXISTS = -libc::EEXIST,
+    DEAD_OBJECT = -libc::EPIPE,
+    FAILED_TRANSACTION = i32::MIN + 2,
+    BAD_INDEX = -libc::EOVERFLOW,
+    NOT_ENOUGH_DATA = -libc::ENODATA,
+    WOULD_BLOCK = -libc::EWOULDBLOCK,
+    TIMED_OUT = -libc::ETIMEDOUT,
+    UNKNOWN_TRANSACTION = -libc::EBADMSG,
+    FDS_NOT_ALLOWED = i32::MIN + 7,
+    UNEXPECTED_NULL = i32::MIN + 8,
+}
+
+impl std::error::Error for android_c_interface_StatusCode {}
+
+impl fmt::Display for android_c_interface_StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StatusCode::{:?}", self)
+    }
+}
+
+/// Equivalent of the `EX_*` values in `android/binder_status.h`.
+#[repr(i32)]
+#[non_exhaustive]
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub enum android_c_interface_ExceptionCode {
+    NONE = 0,
+    SECURITY = -1,
+    BAD_PARCELABLE = -2,
+    ILLEGAL_ARGUMENT = -3,
+    NULL_POINTER = -4,
+    ILLEGAL_STATE = -5,
+    NETWORK_MAIN_THREAD = -6,
+    UNSUPPORTED_OPERATION = -7,
+    SERVICE_SPECIFIC = -8,
+    PARCELABLE = -9,
+    TRANSACTION_FAILED = -129,
+}
+
+/// Allocator callback for contiguous arrays and strings.
+pub type AParcel_contiguousArrayAllocator<T> = Option<
+    unsafe extern "C" fn(arrayData: *mut c_void, length: i32, outBuffer: *mut *mut T) -> bool,
+>;
+
+/// Allocator callback for arrays of parcelables.
+pub type AParcel_parcelableArrayAllocator =
+    Option<unsafe extern "C" fn(arrayData: *mut c_void, length: i32) -> bool>;
+
+/// Callback which writes a single element of a parcelable array.
+pub type AParcel_writeParcelableElement = Option<
+    unsafe extern "C" fn(
+        parcel: *mut AParcel,
+        arrayData: *const c_void,
+        index: c_ulong,
+    ) -> binder_status_t,
+>;
+
+/// Callback which reads a single element of a parcelable array.
+pub type AParcel_readParcelableElement = Option<
+    unsafe extern "C" fn(
+        parcel: *const AParcel,
+        arrayData: *mut c_void,
+        index: c_ulong,
+    ) -> binder_status_t,
+>;
+
+/// Rounds `len` up to the parcel alignment of 4 bytes.
+fn pad_size(len: usize) -> Option<usize> {
+    len.checked_add(3).map(|len| len & !3)
+}
+
+/// In-memory parcel data.
+///
+/// Mirrors the data handling of `android::Parcel`: the parcel has a data
+/// buffer and a single position which is shared by reads and writes. Reads
+/// take `&self`, like the `const` readers in C++, so the position is stored in
+/// a `Cell`.
+#[derive(Default)]
+pub struct AParcel {
+    data: Vec<u8>,
+    pos: Cell<usize>,
+    sensitive: Cell<bool>,
+    /// The session which encodes binders, like `Parcel::mSession`.
+    rpc_session: Option<Arc<dyn RpcParcelSession>>,
+}
+
+impl AParcel {
+    /// Equivalent of `Parcel::markForRpc`.
+    pub(crate) fn mark_for_rpc(&mut self, session: Arc<dyn RpcParcelSession>) {
+        self.rpc_session = Some(session);
+    }
+
+    pub(crate) fn rpc_session(&self) -> Option<Arc<dyn RpcParcelSession>> {
+        self.rpc_session.clone()
+    }
+
+    /// Equivalent of `Parcel::dataSize`.
+    fn data_size(&self) -> usize {
+        self.data.len().max(self.pos.get())
+    }
+
+    /// Equivalent of `Parcel::dataAvail`.
+    fn data_avail(&self) -> usize {
+        self.data.len().saturating_sub(self.pos.get())
+    }
+
+    fn set_data_position(&self, pos: usize) {
+        self.pos.set(pos);
+    }
+
+    fn mark_sensitive(&self) {
+        self.sensitive.set(true);
+    }
+
+    /// Makes sure the data buffer is at least `len` bytes long.
+    fn grow_to(&mut self, len: usize) -> Result<()> {
+        if len > i32::MAX as usize {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        if len <= self.data.len() {
+            return Ok(());
+        }
+        if self.sensitive.get() && len > self.data.capacity() {
+            // Don't leave a copy of sensitive data behind in the old
+            // allocation.
+            let mut data = Vec::with_capacity(len.max(self.data.capacity() * 2));
+            data.extend_from_slice(&self.data);
+            zero(&mut self.data);
+            self.data = data;
+        }
+        self.data.resize(len, 0);
+        Ok(())
+    }
+
+    /// Writes `bytes` at the current position without any padding.
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        let start = self.pos.get();
+        let end = start.checked_add(bytes.len()).ok_or(StatusCode::BAD_VALUE)?;
+        self.grow_to(end)?;
+        self.data[start..end].copy_from_slice(bytes);
+        self.pos.set(end);
+        Ok(())
+    }
+
+    /// Equivalent of `Parcel::writeInplace` followed by a copy of `bytes`. The
+    /// data is zero-padded to a multiple of 4 bytes.
+    fn write_padded(&mut self, bytes: &[u8]) -> Result<()> {
+        let padded = pad_size(bytes.len()).ok_or(StatusCode::NO_MEMORY)?;
+        let start = self.pos.get();
+        let end = start.checked_add(padded).ok_or(StatusCode::NO_MEMORY)?;
+        self.grow_to(end)?;
+        self.data[start..start + bytes.len()].copy_from_slice(bytes);
+        zero(&mut self.data[start + bytes.len()..end]);
+        self.pos.set(end);
+        Ok(())
+    }
+
+    /// Reads exactly `len` bytes at the current position.
+    fn read_bytes(&self, len: usize) -> Result<&[u8]> {
+        let start = self.pos.get();
+        match start.checked_add(len) {
+            Some(end) if end <= self.data.len() => {
+                self.pos.set(end);
+                Ok(&self.data[start..end])
+            }
+            _ => Err(StatusCode::NOT_ENOUGH_DATA),
+        }
+    }
+
+    /// Equivalent of `Parcel::readInplace`: reads `len` bytes and skips over
+    /// the padding that follows them.
+    fn read_padded(&self, len: usize) -> Option<&[u8]> {
+        let start = self.pos.get();
+        let end = start.checked_add(pad_size(len)?)?;
+        if end > self.data.len() {
+            return None;
+        }
+        self.pos.set(end);
+        Some(&self.data[start..start + len])
+    }
+
+    fn write_i32(&mut self, value: i32) -> Result<()> {
+        self.write_bytes(&value.to_ne_bytes())
+    }
+
+    fn read_i32(&self) -> Result<i32> {
+        Ok(i32::from_ne_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    fn write_array_size(&mut self, is_null: bool, length: i32) -> Result<()> {
+        // Only -1 can be used to represent a null array.
+        if length < -1 || (!is_null && length < 0) || (is_null && length > 0) {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        self.write_i32(length)
+    }
+
+    fn read_array_size(&self) -> Result<i32> {
+        let length = self.read_i32()?;
+        if length < -1 {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        if length > 0 && length as usize > self.data_avail() {
+            return Err(StatusCode::NO_MEMORY);
+        }
+        Ok(length)
+    }
+
+    fn write_array(&mut self, array: Option<&[u8]>, length: i32) -> Result<()> {
+        self.write_array_size(array.is_none(), length)?;
+        match array {
+            Some(bytes) if length > 0 => {
+                if bytes.len() > i32::MAX as usize {
+                    return Err(StatusCode::NO_MEMORY);
+                }
+                self.write_padded(bytes)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes `string` as UTF-16, or a null string if `string` is `None`.
+    fn write_string(&mut self, string: Option<&[u8]>) -> Result<()> {
+        let string = match string {
+            None => return self.write_i32(-1),
+            Some(s) => std::str::from_utf8(s).or(Err(StatusCode::BAD_VALUE))?,
+        };
+        let mut utf16: Vec<u16> = string.encode_utf16().collect();
+        let len16: i32 = utf16.len().try_into().or(Err(StatusCode::BAD_VALUE))?;
+        if len16 == i32::MAX {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        utf16.push(0);
+        self.write_i32(len16)?;
+        let bytes: Vec<u8> = utf16.iter().flat_map(|c| c.to_ne_bytes()).collect();
+        self.write_padded(&bytes)
+    }
+
+    /// Equivalent of `Parcel::readString16Inplace`. Returns `None` for a null
+    /// string as well as for malformed string data.
+    fn read_string(&self) -> Option<Vec<u16>> {
+        // Like `Parcel::readInt32()`, a failed read is treated as 0 here.
+        let len16 = self.read_i32().unwrap_or(0);
+        if len16 < 0 || len16 == i32::MAX {
+            return None;
+        }
+        let len16 = len16 as usize;
+        let bytes = self.read_padded((len16 + 1).checked_mul(mem::size_of::<u16>())?)?;
+        let mut utf16: Vec<u16> =
+            bytes.chunks_exact(2).map(|c| u16::from_ne_bytes(c.try_into().unwrap())).collect();
+        if utf16.pop() != Some(0) {
+            return None;
+        }
+        Some(utf16)
+    }
+
+    /// Skips over a header which starts with its own size, like the remote
+    /// stack trace header of a status. A size of 0 means there is no header.
+    fn skip_header(&self) -> Result<()> {
+        let start = self.pos.get();
+        let size = self.read_i32()?;
+        if size < 0 || size as usize > self.data_avail() {
+            return Err(StatusCode::UNKNOWN_ERROR);
+        }
+        if size != 0 {
+            self.pos.set(start + size as usize);
+        }
+        Ok(())
+    }
+
+    fn append_from(&mut self, other: &AParcel, start: usize, len: usize) -> Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        if len > i32::MAX as usize {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        match start.checked_add(len) {
+            Some(end) if end <= other.data.len() => self.write_bytes(&other.data[start..end]),
+            _ => Err(StatusCode::BAD_VALUE),
+        }
+    }
+
+    fn marshal(&self, buffer: &mut [u8], start: usize) -> Result<()> {
+        let data_size = self.data_size();
+        if buffer.len() > data_size || start > data_size - buffer.len() {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        // The data size may extend past the end of the buffer if the position
+        // was moved beyond it, in which case the remainder reads as zeros.
+        let end = (start + buffer.len()).min(self.data.len());
+        let copied = end.saturating_sub(start);
+        if copied > 0 {
+            buffer[..copied].copy_from_slice(&self.data[start..end]);
+        }
+        zero(&mut buffer[copied..]);
+        Ok(())
+    }
+
+    fn unmarshal(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() > i32::MAX as usize {
+            return Err(StatusCode::BAD_VALUE);
+        }
+        zero(&mut self.data);
+        self.data.clear();
+        self.pos.set(0);
+        self.write_padded(data)
+    }
+}
+
+impl Drop for AParcel {
+    fn drop(&mut self) {
+        if self.sensitive.get() {
+            zero(&mut self.data);
+        }
+    }
+}
+
+fn zero(bytes: &mut [u8]) {
+    for b in bytes.iter_mut() {
+        // Volatile so the compiler can't elide zeroing of sensitive data that
+        // is about to be freed.
+        unsafe {
+            // Safety: `b` is a valid, exclusive reference to a byte.
+            ptr::write_volatile(b, 0);
+        }
+    }
+}
+
+/// Converts UTF-16 to UTF-8 the same way as `utf16_to_utf8` from libutils.
+///
+/// Unpaired surrogates are encoded as 3-byte sequences, so they are rejected
+/// by `String::from_utf8` just like in the NDK backend.
+fn utf16_to_utf8(utf16: &[u16]) -> Vec<u8> {
+    let mut utf8 = Vec::with_capacity(utf16.len());
+    let mut i = 0;
+    while i < utf16.len() {
+        let unit = utf16[i] as u32;
+        let codepoint = match utf16.get(i + 1) {
+            Some(&next) if (0xD800..0xDC00).contains(&unit) && (0xDC00..0xE000).contains(&next) => {
+                i += 1;
+                0x10000 + ((unit - 0xD800) << 10) + (next as u32 - 0xDC00)
+            }
+            _ => unit,
+        };
+        i += 1;
+        match codepoint {
+            0..=0x7F => utf8.push(codepoint as u8),
+            0x80..=0x7FF => {
+                utf8.push(0xC0 | (codepoint >> 6) as u8);
+                utf8.push(0x80 | (codepoint & 0x3F) as u8);
+            }
+            0x800..=0xFFFF => {
+                utf8.push(0xE0 | (codepoint >> 12) as u8);
+                utf8.push(0x80 | ((codepoint >> 6) & 0x3F) as u8);
+                utf8.push(0x80 | (codepoint & 0x3F) as u8);
+            }
+            _ => {
+                utf8.push(0xF0 | (codepoint >> 18) as u8);
+                utf8.push(0x80 | ((codepoint >> 12) & 0x3F) as u8);
+                utf8.push(0x80 | ((codepoint >> 6) & 0x3F) as u8);
+                utf8.push(0x80 | (codepoint & 0x3F) as u8);
+            }
+        }
+    }
+    utf8
+}
+
+/// Exception code which precedes a reply header instead of a status, see
+/// `EX_HAS_REPLY_HEADER` in `binder/Status.h`.
+const EX_HAS_REPLY_HEADER: i32 = -128;
+
+/// In-memory status, equivalent of `android::binder::Status`.
+#[derive(Debug, Default)]
+pub struct AStatus {
+    exception: i32,
+    /// The `status_t` of a transaction failure, or the service specific error.
+    error_code: i32,
+    message: CString,
+}
+
+impl AStatus {
+    /// Equivalent of `Status::fromExceptionCode`. Unknown exception codes are
+    /// coerced into `EX_TRANSACTION_FAILED` like in the NDK.
+    fn from_exception(exception: i32, message: Option<&CStr>) -> AStatus {
+        let exception = parse_exception_code(exception);
+        let error_code = match exception {
+            ExceptionCode::TRANSACTION_FAILED => StatusCode::FAILED_TRANSACTION,
+            _ => StatusCode::OK,
+        };
+        AStatus {
+            exception: exception as i32,
+            error_code: error_code as i32,
+            message: message.map(CStr::to_owned).unwrap_or_default(),
+        }
+    }
+
+    /// Equivalent of `Status::fromServiceSpecificError`.
+    fn from_service_specific_error(error: i32, message: Option<&CStr>) -> AStatus {
+        AStatus {
+            exception: ExceptionCode::SERVICE_SPECIFIC as i32,
+            error_code: error,
+            message: message.map(CStr::to_owned).unwrap_or_default(),
+        }
+    }
+
+    /// Equivalent of `Status::fromStatusT`. Unknown status codes are coerced
+    /// into `STATUS_UNKNOWN_ERROR` like in the NDK.
+    fn from_status(status: binder_status_t) -> AStatus {
+        let status = parse_status_code(status);
+        let exception = match status {
+            StatusCode::OK => ExceptionCode::NONE,
+            _ => ExceptionCode::TRANSACTION_FAILED,
+        };
+        AStatus {
+            exception: exception as i32,
+            error_code: status as i32,
+            message: CString::default(),
+        }
+    }
+
+    fn is_ok(&self) -> bool {
+        self.exception == ExceptionCode::NONE as i32
+    }
+
+    fn transaction_error(&self) -> binder_status_t {
+        if self.exception == ExceptionCode::TRANSACTION_FAILED as i32 {
+            self.error_code
+        } else {
+            StatusCode::OK as binder_status_t
+        }
+    }
+
+    fn service_specific_error(&self) -> i32 {
+        if self.exception == ExceptionCode::SERVICE_SPECIFIC as i32 {
+            self.error_code
+        } else {
+            0
+        }
+    }
+
+    /// Equivalent of `Status::toString8`.
+    fn description(&self) -> String {
+        if self.is_ok() {
+            return "No error".to_string();
+        }
+        let mut description =
+            format!("Status({}, EX_{:?}): '", self.exception, parse_exception_code(self.exception));
+        if self.exception == ExceptionCode::SERVICE_SPECIFIC as i32 {
+            description += &format!("{}: ", self.error_code);
+        } else if self.exception == ExceptionCode::TRANSACTION_FAILED as i32 {
+            description += &format!("{:?}: ", parse_status_code(self.error_code));
+        }
+        description += &self.message.to_string_lossy();
+        description.push('\'');
+        description
+    }
+
+    /// Equivalent of `Status::writeToParcel`.
+    fn write_to_parcel(&self, parcel: &mut AParcel) -> Result<()> {
+        if self.exception == ExceptionCode::TRANSACTION_FAILED as i32 {
+            // Transaction failures are returned instead of being written.
+            return status_result(self.error_code);
+        }
+        parcel.write_i32(self.exception)?;
+        if self.is_ok() {
+            return Ok(());
+        }
+        let message = String::from_utf8_lossy(self.message.to_bytes());
+        parcel.write_string(Some(message.as_bytes()))?;
+        // Empty remote stack trace header.
+        parcel.write_i32(0)?;
+        if self.exception == ExceptionCode::SERVICE_SPECIFIC as i32 {
+            parcel.write_i32(self.error_code)?;
+        } else if self.exception == ExceptionCode::PARCELABLE as i32 {
+            // Empty parcelable header.
+            parcel.write_i32(0)?;
+        }
+        Ok(())
+    }
+
+    /// Equivalent of `Status::readFromParcel`.
+    fn read_from_parcel(parcel: &AParcel) -> Result<AStatus> {
+        let mut exception = parcel.read_i32()?;
+        if exception == EX_HAS_REPLY_HEADER {
+            // Reply headers are only sent when there is no exception.
+            parcel.skip_header()?;
+            exception = ExceptionCode::NONE as i32;
+        }
+        if exception == ExceptionCode::NONE as i32 {
+            return Ok(AStatus::default());
+        }
+        let message = parcel.read_string().ok_or(StatusCode::UNEXPECTED_NULL)?;
+        let mut message = utf16_to_utf8(&message);
+        // `String8` stops at the first null character.
+        message.truncate(message.iter().position(|&b| b == 0).unwrap_or(message.len()));
+        // Skip over the remote stack trace.
+        parcel.skip_header()?;
+        let mut error_code = StatusCode::OK as i32;
+        if exception == ExceptionCode::SERVICE_SPECIFIC as i32 {
+            error_code = parcel.read_i32()?;
+        } else if exception == ExceptionCode::PARCELABLE as i32 {
+            parcel.skip_header()?;
+        }
+        Ok(AStatus { exception, error_code, message: CString::new(message).unwrap() })
+    }
+}
+
+fn to_status(result: Result<()>) -> binder_status_t {
+    match result {
+        Ok(()) => StatusCode::OK as binder_status_t,
+        Err(e) => e as binder_status_t,
+    }
+}
+
+// The functions below mirror the `AParcel_*` functions from the NDK.
+//
+// Safety: unless documented otherwise, all of them must be called with a valid
+// pointer to an `AParcel` created by `AParcel_create`, which is what `Parcel`
+// and `BorrowedParcel` guarantee.
+
+pub unsafe fn AParcel_create() -> *mut AParcel {
+    Box::into_raw(Box::new(AParcel::default()))
+}
+
+pub unsafe fn AParcel_delete(parcel: *mut AParcel) {
+    drop(Box::from_raw(parcel));
+}
+
+pub unsafe fn AParcel_markSensitive(parcel: *const AParcel) {
+    (*parcel).mark_sensitive()
+}
+
+pub unsafe fn AParcel_getDataPosition(parcel: *const AParcel) -> i32 {
+    (*parcel).pos.get() as i32
+}
+
+pub unsafe fn AParcel_getDataSize(parcel: *const AParcel) -> i32 {
+    (*parcel).data_size() as i32
+}
+
+pub unsafe fn AParcel_setDataPosition(parcel: *const AParcel, position: i32) -> binder_status_t {
+    if position < 0 {
+        return StatusCode::BAD_VALUE as binder_status_t;
+    }
+    (*parcel).set_data_position(position as usize);
+    StatusCode::OK as binder_status_t
+}
+
+pub unsafe fn AParcel_appendFrom(
+    from: *const AParcel,
+    to: *mut AParcel,
+    start: i32,
+    size: i32,
+) -> binder_status_t {
+    // Negative values are deliberately sign-extended, so they are rejected as
+    // out of bounds like in the NDK.
+    to_status((*to).append_from(&*from, start as usize, size as usize))
+}
+
+/// # Safety
+///
+/// `buffer` must be valid for writes of `len` bytes.
+pub unsafe fn AParcel_marshal(
+    parcel: *const AParcel,
+    buffer: *mut u8,
+    start: c_ulong,
+    len: c_ulong,
+) -> binder_status_t {
+    let buffer =
+        if len == 0 { &mut [][..] } else { slice::from_raw_parts_mut(buffer, len as usize) };
+    to_status((*parcel).marshal(buffer, start as usize))
+}
+
+/// # Safety
+///
+/// `buffer` must be valid for reads of `len` bytes.
+pub unsafe fn AParcel_unmarshal(
+    parcel: *mut AParcel,
+    buffer: *const u8,
+    len: c_ulong,
+) -> binder_status_t {
+    let buffer = if len == 0 { &[][..] } else { slice::from_raw_parts(buffer, len as usize) };
+    to_status((*parcel).unmarshal(buffer))
+}
+
+pub unsafe fn AParcel_writeParcelFileDescriptor(
+    parcel: *mut AParcel,
+    fd: c_int,
+) -> binder_status_t {
+    match fd {
+        // A null file descriptor is plain data, so it can be written.
+        -1 => to_status((*parcel).write_i32(0)),
+        fd if fd < 0 => StatusCode::UNKNOWN_ERROR as binder_status_t,
+        _ => StatusCode::INVALID_OPERATION as binder_status_t,
+    }
+}
+
+pub unsafe fn AParcel_readParcelFileDescriptor(
+    parcel: *const AParcel,
+    fd: *mut c_int,
+) -> binder_status_t {
+    match (*parcel).read_i32() {
+        Ok(0) => {
+            *fd = -1;
+            StatusCode::OK as binder_status_t
+        }
+        Ok(_) => StatusCode::BAD_TYPE as binder_status_t,
+        Err(e) => e as binder_status_t,
+    }
+}
+
+/// # Safety
+///
+/// `string` must either be null with a `length` of -1, or point to `length`
+/// bytes of UTF-8.
+pub unsafe fn AParcel_writeString(
+    parcel: *mut AParcel,
+    string: *const c_char,
+    length: i32,
+) -> binder_status_t {
+    if string.is_null() {
+        if length != -1 {
+            return StatusCode::BAD_VALUE as binder_status_t;
+        }
+        return to_status((*parcel).write_string(None));
+    }
+    if length < 0 {
+        return StatusCode::BAD_VALUE as binder_status_t;
+    }
+    let bytes = slice::from_raw_parts(string as *const u8, length as usize);
+    to_status((*parcel).write_string(Some(bytes)))
+}
+
+/// # Safety
+///
+/// `allocator` must follow the contract of `AParcel_stringAllocator` for
+/// `string_data`.
+pub unsafe fn AParcel_readString(
+    parcel: *const AParcel,
+    string_data: *mut c_void,
+    allocator: AParcel_contiguousArrayAllocator<c_char>,
+) -> binder_status_t {
+    let allocator = match allocator {
+        Some(a) => a,
+        None => return StatusCode::UNEXPECTED_NULL as binder_status_t,
+    };
+    let utf16 = match (*parcel).read_string() {
+        Some(s) => s,
+        None if allocator(string_data, -1, ptr::null_mut()) => {
+            return StatusCode::OK as binder_status_t
+        }
+        None => return StatusCode::UNEXPECTED_NULL as binder_status_t,
+    };
+    let mut utf8 = utf16_to_utf8(&utf16);
+    utf8.push(0);
+    let len8: i32 = match utf8.len().try_into() {
+        Ok(len) => len,
+        Err(_) => return StatusCode::BAD_VALUE as binder_status_t,
+    };
+    let mut buffer = ptr::null_mut();
+    if !allocator(string_data, len8, &mut buffer) || buffer.is_null() {
+        return StatusCode::NO_MEMORY as binder_status_t;
+    }
+    ptr::copy_nonoverlapping(utf8.as_ptr(), buffer as *mut u8, utf8.len());
+    StatusCode::OK as binder_status_t
+}
+
+/// Writes a contiguous array of `length` elements of `T`.
+///
+/// # Safety
+///
+/// `array` must either be null or point to `length` elements of `T`.
+unsafe fn write_array<T>(parcel: *mut AParcel, array: *const T, length: i32) -> binder_status_t {
+    let bytes = if array.is_null() {
+        None
+    } else if length <= 0 {
+        Some(&[][..])
+    } else {
+        let size = match (length as usize).checked_mul(mem::size_of::<T>()) {
+            Some(size) => size,
+            None => return StatusCode::NO_MEMORY as binder_status_t,
+        };
+        Some(slice::from_raw_parts(array as *const u8, size))
+    };
+    to_status((*parcel).write_array(bytes, length))
+}
+
+/// Reads a contiguous array of `T` into the buffer provided by `allocator`.
+///
+/// # Safety
+///
+/// `allocator` must follow the contract of the NDK array allocators for
+/// `array_data`, and `T` must be valid for any bit pattern.
+unsafe fn read_array<T>(
+    parcel: *const AParcel,
+    array_data: *mut c_void,
+    allocator: AParcel_contiguousArrayAllocator<T>,
+) -> binder_status_t {
+    let parcel = &*parcel;
+    let length = match parcel.read_array_size() {
+        Ok(length) => length,
+        Err(e) => return e as binder_status_t,
+    };
+    let mut array = ptr::null_mut();
+    match allocator {
+        Some(allocator) if allocator(array_data, length, &mut array) => {}
+        _ => return StatusCode::NO_MEMORY as binder_status_t,
+    }
+    if length <= 0 {
+        return StatusCode::OK as binder_status_t;
+    }
+    if array.is_null() {
+        return StatusCode::NO_MEMORY as binder_status_t;
+    }
+    let size = match (length as usize).checked_mul(mem::size_of::<T>()) {
+        Some(size) if size <= i32::MAX as usize => size,
+        _ => return StatusCode::NO_MEMORY as binder_status_t,
+    };
+    match parcel.read_padded(size) {
+        Some(bytes) => {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), array as *mut u8, size);
+            StatusCode::OK as binder_status_t
+        }
+        None => StatusCode::NO_MEMORY as binder_status_t,
+    }
+}
+
+macro_rules! primitive_functions {
+    {
+        $(
+            $ty:ty: $write_fn:ident, $read_fn:ident, $write_array_fn:ident, $read_array_fn:ident;
+        )*
+    } => {
+        $(
+            pub unsafe fn $write_fn(parcel: *mut AParcel, value: $ty) -> binder_status_t {
+                to_status((*parcel).write_bytes(&value.to_ne_bytes()))
+            }
+
+            pub unsafe fn $read_fn(parcel: *const AParcel, value: *mut $ty) -> binder_status_t {
+                match (*parcel).read_bytes(mem::size_of::<$ty>()) {
+                    Ok(bytes) => {
+                        *value = <$ty>::from_ne_bytes(bytes.try_into().unwrap());
+                        StatusCode::OK as binder_status_t
+                    }
+                    Err(e) => e as binder_status_t,
+                }
+            }
+
+            pub unsafe fn $write_array_fn(parcel: *mut AParcel, array: *const $ty, length: i32) -> binder_status_t {
+                write_array(parcel, array, length)
+            }
+
+            pub unsafe fn $read_array_fn(
+                parcel: *const AParcel,
+                array_data: *mut c_void,
+                allocator: AParcel_contiguousArrayAllocator<$ty>,
+            ) -> binder_status_t {
+                read_array(parcel, array_data, allocator)
+            }
+        )*
+    };
+}
+
+primitive_functions! {
+    i32: AParcel_writeInt32, AParcel_readInt32, AParcel_writeInt32Array, AParcel_readInt32Array;
+    u32: AParcel_writeUint32, AParcel_readUint32, AParcel_writeUint32Array, AParcel_readUint32Array;
+    i64: AParcel_writeInt64, AParcel_readInt64, AParcel_writeInt64Array, AParcel_readInt64Array;
+    u64: AParcel_writeUint64, AParcel_readUint64, AParcel_writeUint64Array, AParcel_readUint64Array;
+    f32: AParcel_writeFloat, AParcel_readFloat, AParcel_writeFloatArray, AParcel_readFloatArray;
+    f64: AParcel_writeDouble, AParcel_readDouble, AParcel_writeDoubleArray, AParcel_readDoubleArray;
+}
+
+// Booleans, chars and bytes are each written as a full int32.
+
+pub unsafe fn AParcel_writeBool(parcel: *mut AParcel, value: bool) -> binder_status_t {
+    to_status((*parcel).write_i32(value as i32))
+}
+
+pub unsafe fn AParcel_readBool(parcel: *const AParcel, value: *mut bool) -> binder_status_t {
+    match (*parcel).read_i32() {
+        Ok(v) => {
+            *value = v != 0;
+            StatusCode::OK as binder_status_t
+        }
+        Err(e) => e as binder_status_t,
+    }
+}
+
+pub unsafe fn AParcel_writeChar(parcel: *mut AParcel, value: u16) -> binder_status_t {
+    to_status((*parcel).write_i32(value as i32))
+}
+
+pub unsafe fn AParcel_readChar(parcel: *const AParcel, value: *mut u16) -> binder_status_t {
+    match (*parcel).read_i32() {
+        Ok(v) => {
+            *value = v as u16;
+            StatusCode::OK as binder_status_t
+        }
+        Err(e) => e as binder_status_t,
+    }
+}
+
+pub unsafe fn AParcel_writeByte(parcel: *mut AParcel, value: i8) -> binder_status_t {
+    to_status((*parcel).write_i32(value as i32))
+}
+
+pub unsafe fn AParcel_readByte(parcel: *const AParcel, value: *mut i8) -> binder_status_t {
+    match (*parcel).read_i32() {
+        Ok(v) => {
+            *value = v as i8;
+            StatusCode::OK as binder_status_t
+        }
+        Err(e) => e as binder_status_t,
+    }
+}
+
+pub unsafe fn AParcel_writeByteArray(
+    parcel: *mut AParcel,
+    array: *const i8,
+    length: i32,
+) -> binder_status_t {
+    write_array(parcel, array, length)
+}
+
+pub unsafe fn AParcel_readByteArray(
+    parcel: *const AParcel,
+    array_data: *mut c_void,
+    allocator: AParcel_contiguousArrayAllocator<i8>,
+) -> binder_status_t {
+    read_array(parcel, array_data, allocator)
+}
+
+/// Each element of a char array is written as an int32 rather than packed.
+pub unsafe fn AParcel_writeCharArray(
+    parcel: *mut AParcel,
+    array: *const u16,
+    length: i32,
+) -> binder_status_t {
+    let parcel = &mut *parcel;
+    if let Err(e) = parcel.write_array_size(array.is_null(), length) {
+        return e as binder_status_t;
+    }
+    if length <= 0 {
+        return StatusCode::OK as binder_status_t;
+    }
+    for &c in slice::from_raw_parts(array, length as usize) {
+        if let Err(e) = parcel.write_i32(c as i32) {
+            return e as binder_status_t;
+        }
+    }
+    StatusCode::OK as binder_status_t
+}
+
+pub unsafe fn AParcel_readCharArray(
+    parcel: *const AParcel,
+    array_data: *mut c_void,
+    allocator: AParcel_contiguousArrayAllocator<u16>,
+) -> binder_status_t {
+    let parcel = &*parcel;
+    let length = match parcel.read_array_size() {
+        Ok(length) => length,
+        Err(e) => return e as binder_status_t,
+    };
+    let mut array = ptr::null_mut();
+    match allocator {
+        Some(allocator) if allocator(array_data, length, &mut array) => {}
+        _ => return StatusCode::NO_MEMORY as binder_status_t,
+    }
+    if length <= 0 {
+        return StatusCode::OK as binder_status_t;
+    }
+    if array.is_null() {
+        return StatusCode::NO_MEMORY as binder_status_t;
+    }
+    for i in 0..length as usize {
+        match parcel.read_i32() {
+            Ok(c) => *array.add(i) = c as u16,
+            Err(e) => return e as binder_status_t,
+        }
+    }
+    StatusCode::OK as binder_status_t
+}
+
+pub unsafe fn AParcel_writeParcelableArray(
+    parcel: *mut AParcel,
+    array_data: *const c_void,
+    length: i32,
+    element_writer: AParcel_writeParcelableElement,
+) -> binder_status_t {
+    // Whether the array is null can only be inferred from the length.
+    if let Err(e) = (*parcel).write_array_size(length < 0, length) {
+        return e as binder_status_t;
+    }
+    let element_writer = match element_writer {
+        Some(w) => w,
+        None if length <= 0 => return StatusCode::OK as binder_status_t,
+        None => return StatusCode::UNEXPECTED_NULL as binder_status_t,
+    };
+    for i in 0..length.max(0) {
+        let status = element_writer(parcel, array_data, i as c_ulong);
+        if status != StatusCode::OK as binder_status_t {
+            return status;
+        }
+    }
+    StatusCode::OK as binder_status_t
+}
+
+pub unsafe fn AParcel_readParcelableArray(
+    parcel: *const AParcel,
+    array_data: *mut c_void,
+    allocator: AParcel_parcelableArrayAllocator,
+    element_reader: AParcel_readParcelableElement,
+) -> binder_status_t {
+    let length = match (*parcel).read_array_size() {
+        Ok(length) => length,
+        Err(e) => return e as binder_status_t,
+    };
+    match allocator {
+        Some(allocator) if allocator(array_data, length) => {}
+        _ => return StatusCode::NO_MEMORY as binder_status_t,
+    }
+    if length <= 0 {
+        return StatusCode::OK as binder_status_t;
+    }
+    let element_reader = match element_reader {
+        Some(r) => r,
+        None => return StatusCode::UNEXPECTED_NULL as binder_status_t,
+    };
+    for i in 0..length {
+        let status = element_reader(parcel, array_data, i as c_ulong);
+        if status != StatusCode::OK as binder_status_t {
+            return status;
+        }
+    }
+    StatusCode::OK as binder_status_t
+}
+
+pub unsafe fn AParcel_writeStatusHeader(
+    parcel: *mut AParcel,
+    status: *const AStatus,
+) -> binder_status_t {
+    to_status((*status).write_to_parcel(&mut *parcel))
+}
+
+/// # Safety
+///
+/// `status` must be valid for writes. If the status header was read
+/// successfully, the caller owns the `AStatus` written to it.
+pub unsafe fn AParcel_readStatusHeader(
+    parcel: *const AParcel,
+    status: *mut *mut AStatus,
+) -> binder_status_t {
+    match AStatus::read_from_parcel(&*parcel) {
+        Ok(s) => {
+            *status = Box::into_raw(Box::new(s));
+            StatusCode::OK as binder_status_t
+        }
+        Err(e) => e as binder_status_t,
+    }
+}
+
+// The functions below mirror the `AStatus_*` functions from the NDK. All of
+// the constructors return an owned pointer which must be freed with
+// `AStatus_delete`.
+//
+// Safety: unless documented otherwise, the functions which take an `AStatus`
+// must be called with a valid pointer returned by one of the constructors,
+// which is what `Status` guarantees.
+
+pub unsafe fn AStatus_newOk() -> *mut AStatus {
+    Box::into_raw(Box::default())
+}
+
+pub unsafe fn AStatus_fromExceptionCode(exception: i32) -> *mut AStatus {
+    Box::into_raw(Box::new(AStatus::from_exception(exception, None)))
+}
+
+/// # Safety
+///
+/// `message` must be a valid, null-terminated C string.
+pub unsafe fn AStatus_fromExceptionCodeWithMessage(
+    exception: i32,
+    message: *const c_char,
+) -> *mut AStatus {
+    Box::into_raw(Box::new(AStatus::from_exception(exception, Some(CStr::from_ptr(message)))))
+}
+
+pub unsafe fn AStatus_fromServiceSpecificError(error: i32) -> *mut AStatus {
+    Box::into_raw(Box::new(AStatus::from_service_specific_error(error, None)))
+}
+
+/// # Safety
+///
+/// `message` must be a valid, null-terminated C string.
+pub unsafe fn AStatus_fromServiceSpecificErrorWithMessage(
+    error: i32,
+    message: *const c_char,
+) -> *mut AStatus {
+    Box::into_raw(Box::new(AStatus::from_service_specific_error(
+        error,
+        Some(CStr::from_ptr(message)),
+    )))
+}
+
+pub unsafe fn AStatus_fromStatus(status: binder_status_t) -> *mut AStatus {
+    Box::into_raw(Box::new(AStatus::from_status(status)))
+}
+
+pub unsafe fn AStatus_isOk(status: *const AStatus) -> bool {
+    (*status).is_ok()
+}
+
+pub unsafe fn AStatus_getExceptionCode(status: *const AStatus) -> i32 {
+    (*status).exception
+}
+
+pub unsafe fn AStatus_getServiceSpecificError(status: *const AStatus) -> i32 {
+    (*status).service_specific_error()
+}
+
+pub unsafe fn AStatus_getStatus(status: *const AStatus) -> binder_status_t {
+    (*status).transaction_error()
+}
+
+/// The returned string must be freed with `AStatus_deleteDescription`.
+pub unsafe fn AStatus_getDescription(status: *const AStatus) -> *const c_char {
+    CString::new((*status).description()).unwrap().into_raw()
+}
+
+/// # Safety
+///
+/// `description` must have been returned by `AStatus_getDescription`.
+pub unsafe fn AStatus_deleteDescription(description: *const c_char) {
+    drop(CString::from_raw(description as *mut c_char));
+}
+
+pub unsafe fn AStatus_delete(status: *mut AStatus) {
+    drop(Box::from_raw(status));
+}
+
+#[test]
+fn test_wire_format() {
+    let mut parcel = AParcel::default();
+    parcel.write_i32(7).unwrap();
+    parcel.write_string(Some(b"ab")).unwrap();
+    parcel.write_string(None).unwrap();
+    parcel.write_array(Some(&[1, 2, 3]), 3).unwrap();
+    parcel.write_array(None, -1).unwrap();
+
+    let mut expected = vec![];
+    expected.extend_from_slice(&7i32.to_ne_bytes());
+    // UTF-16 length, then "ab\0" padded to 8 bytes.
+    expected.extend_from_slice(&2i32.to_ne_bytes());
+    for c in &[b'a' as u16, b'b' as u16, 0, 0] {
+        expected.extend_from_slice(&c.to_ne_bytes());
+    }
+    expected.extend_from_slice(&(-1i32).to_ne_bytes());
+    expected.extend_from_slice(&3i32.to_ne_bytes());
+    expected.extend_from_slice(&[1, 2, 3, 0]);
+    expected.extend_from_slice(&(-1i32).to_ne_bytes());
+    assert_eq!(parcel.data, expected);
+
+    parcel.set_data_position(4);
+    assert_eq!(parcel.read_string(), Some(vec![b'a' as u16, b'b' as u16]));
+    assert_eq!(parcel.read_string(), None);
+    assert_eq!(parcel.read_array_size(), Ok(3));
+}
+
+#[test]
+fn test_status_header() {
+    let message = CString::new("message").unwrap();
+    let mut parcel = AParcel::default();
+    AStatus::from_service_specific_error(42, Some(&message)).write_to_parcel(&mut parcel).unwrap();
+    AStatus::default().write_to_parcel(&mut parcel).unwrap();
+    assert_eq!(
+        AStatus::from_status(StatusCode::DEAD_OBJECT as i32).write_to_parcel(&mut parcel),
+        Err(StatusCode::DEAD_OBJECT)
+    );
+
+    parcel.set_data_position(0);
+    let status = AStatus::read_from_parcel(&parcel).unwrap();
+    assert_eq!(status.service_specific_error(), 42);
+    assert_eq!(status.description(), "Status(-8, EX_SERVICE_SPECIFIC): '42: message'");
+    assert!(AStatus::read_from_parcel(&parcel).unwrap().is_ok());
+    assert_eq!(parcel.data_avail(), 0);
+}
+
+#[test]
+fn test_utf16_to_utf8() {
+    let s = "a\u{e9}\u{20ac}\u{1f600}";
+    let utf16: Vec<u16> = s.encode_utf16().collect();
+    assert_eq!(utf16_to_utf8(&utf16), s.as_bytes());
+    assert!(String::from_utf8(utf16_to_utf8(&[0xD800])).is_err());
+}
@@ -0,0 +1,471 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Declarative permission checks for the transactions of native services.
+//!
+//! A [`PermissionPolicy`] maps transaction codes to the callers which are
+//! allowed to make them, and rejects all other transactions with
+//! [`ExceptionCode::SECURITY`] before they reach the service. It is passed to
+//! the service in [`BinderFeatures::interceptors`](crate::BinderFeatures), so
+//! that it applies from the first transaction:
+//!
+//! ```ignore
+//! let policy = PermissionPolicy::new()
+//!     .allow(transactions::getState, Callers::Any)
+//!     .allow(transactions::setState, Callers::Uids(vec![AID_SYSTEM]))
+//!     .allow(transactions::setState, Callers::Permission("android.permission.DUMP".into()));
+//! let features = BinderFeatures { interceptors: vec![Arc::new(policy)], ..Default::default() };
+//! let service = BnFoo::new_binder(Foo, features);
+//! ```
+
+use crate::binder::{IBinderInternal, Remotable, TransactionCode, FIRST_CALL_TRANSACTION};
+use crate::error::{ExceptionCode, Status, StatusCode};
+use crate::interceptor::{TransactionInfo, TransactionInterceptor};
+use crate::native::Binder;
+use crate::parcel::BorrowedParcel;
+use crate::proxy::{get_service, AssociateClass, SpIBinder};
+use crate::state::TransactionContext;
+
+use libc::{pid_t, uid_t};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+
+/// The range of UIDs reserved for each Android user, see
+/// `AID_USER_OFFSET` in `android_filesystem_config.h`.
+pub const AID_USER_OFFSET: uid_t = 100000;
+
+/// Transaction code of `IPermissionController.checkPermission`.
+const CHECK_PERMISSION: TransactionCode = FIRST_CALL_TRANSACTION;
+
+/// Returns the app ID of a UID, i.e. the UID without its Android user.
+pub fn app_id(uid: uid_t) -> uid_t {
+    uid % AID_USER_OFFSET
+}
+
+/// Returns the type of an SELinux context, e.g. `system_server` for
+/// `u:r:system_server:s0`.
+pub fn selinux_type(context: &str) -> Option<&str> {
+    context.split(':').nth(2)
+}
+
+/// A set of callers which are allowed to make a transaction.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Callers {
+    /// Any caller.
+    Any,
+    /// Callers with one of the given UIDs.
+    Uids(Vec<uid_t>),
+    /// Callers whose [`app_id`] is in the given range, for any Android user.
+    AppIds(RangeInclusive<uid_t>),
+    /// Callers whose SELinux context has the given type, e.g.
+    /// `system_server`.
+    ///
+    /// The calling context is only available if the service was created with
+    /// [`BinderFeatures::set_requesting_sid`](crate::BinderFeatures), otherwise
+    /// this never matches.
+    SelinuxType(String),
+    /// Callers which hold the given permission, according to the
+    /// [`PermissionChecker`] of the policy. Calls from the service's own
+    /// process always hold all permissions.
+    Permission(String),
+    /// Callers which match any of the given sets.
+    AnyOf(Vec<Callers>),
+}
+
+impl Callers {
+    fn matches(&self, context: &TransactionContext, checker: &dyn PermissionChecker) -> bool {
+        match self {
+            Callers::Any => true,
+            Callers::Uids(uids) => uids.contains(&context.calling_uid()),
+            Callers::AppIds(app_ids) => app_ids.contains(&app_id(context.calling_uid())),
+            Callers::SelinuxType(expected) => context
+                .calling_sid()
+                .and_then(|sid| sid.to_str().ok())
+                .and_then(selinux_type)
+                .is_some_and(|actual| actual == expected),
+            Callers::Permission(permission) => {
+                let own_pid = unsafe {
+                    // Safety: `getpid` has no preconditions and always
+                    // succeeds.
+                    libc::getpid()
+                };
+                context.calling_pid() == own_pid
+                    || checker.check_permission(
+                        permission,
+                        context.calling_pid(),
+                        context.calling_uid(),
+                    )
+            }
+            Callers::AnyOf(callers) => callers.iter().any(|c| c.matches(context, checker)),
+        }
+    }
+}
+
+/// A source of truth for the permissions held by callers, used by
+/// [`Callers::Permission`].
+pub trait PermissionChecker: Send + Sync {
+    /// Returns whether the process with the given PID and UID holds
+    /// `permission`.
+    fn check_permission(&self, permission: &str, pid: pid_t, uid: uid_t) -> bool;
+}
+
+/// Maps transaction codes to the callers that are allowed to make them.
+///
+/// As a [`TransactionInterceptor`], it rejects transactions from any other
+/// caller with [`ExceptionCode::SECURITY`] before the service handles them.
+/// Transactions with codes that have no rule are rejected unless
+/// [`allow_others`](Self::allow_others) was used.
+pub struct PermissionPolicy {
+    rules: HashMap<TransactionCode, Callers>,
+    others: Option<Callers>,
+    checker: Arc<dyn PermissionChecker>,
+}
+
+impl Default for PermissionPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PermissionPolicy {
+    /// Create a policy which rejects all transactions, and checks permissions
+    /// with a [`PermissionController`].
+    pub fn new() -> Self {
+        Self { rules: HashMap::new(), others: None, checker: Arc::new(PermissionController::new()) }
+    }
+
+    /// Allow `callers` to make transactions with the given code, in addition
+    /// to any callers that were already allowed.
+    pub fn allow(mut self, code: TransactionCode, callers: Callers) -> Self {
+        let rule = match self.rules.remove(&code) {
+            Some(Callers::AnyOf(mut existing)) => {
+                existing.push(callers);
+                Callers::AnyOf(existing)
+            }
+            Some(existing) => Callers::AnyOf(vec![existing, callers]),
+            None => callers,
+        };
+        self.rules.insert(code, rule);
+        self
+    }
+
+    /// Allow `callers` to make transactions with any code that doesn't have
+    /// its own rule.
+    pub fn allow_others(mut self, callers: Callers) -> Self {
+        self.others = Some(callers);
+        self
+    }
+
+    /// Use `checker` instead of the `permission` service to check the
+    /// permissions of [`Callers::Permission`] rules.
+    pub fn with_permission_checker(mut self, checker: Arc<dyn PermissionChecker>) -> Self {
+        self.checker = checker;
+        self
+    }
+
+    /// Returns whether the caller described by `context` is allowed to make a
+    /// transaction with the given code.
+    pub fn is_allowed(&self, code: TransactionCode, context: &TransactionContext) -> bool {
+        self.rules
+            .get(&code)
+            .or(self.others.as_ref())
+            .is_some_and(|callers| callers.matches(context, self.checker.as_ref()))
+    }
+}
+
+impl TransactionInterceptor for PermissionPolicy {
+    fn before_transact(&self, info: &TransactionInfo<'_>) -> crate::Result<()> {
+        if self.is_allowed(info.code(), info.context()) {
+            return Ok(());
+        }
+        let message = format!(
+            "UID {} is not allowed to make transaction {} on {}",
+            info.context().calling_uid(),
+            info.code(),
+            info.descriptor()
+        );
+        let message = CString::new(message).ok();
+        Err(Status::new_exception(ExceptionCode::SECURITY, message.as_deref()))
+    }
+}
+
+/// The `IPermissionController` interface. Its class is used to make
+/// transactions to the permission controller, and it serves a
+/// [`PermissionChecker`] to test the client side.
+struct BnPermissionController(Arc<dyn PermissionChecker>);
+
+impl Remotable for BnPermissionController {
+    fn get_descriptor() -> &'static str {
+        "android.os.IPermissionController"
+    }
+
+    fn on_transact(
+        &self,
+        code: TransactionCode,
+        data: &BorrowedParcel<'_>,
+        reply: &mut BorrowedParcel<'_>,
+    ) -> crate::error::Result<()> {
+        match code {
+            CHECK_PERMISSION => {
+                let permission: String = data.read()?;
+                let pid: pid_t = data.read()?;
+                let uid: i32 = data.read()?;
+                reply.write(&Status::ok())?;
+                reply.write(&self.0.check_permission(&permission, pid, uid as uid_t))
+            }
+            _ => Err(StatusCode::UNKNOWN_TRANSACTION),
+        }
+    }
+
+    fn on_dump(&self, _file: &File, _args: &[&CStr]) -> crate::error::Result<()> {
+        Ok(())
+    }
+
+    binder_fn_get_class!(Binder::<Self>);
+}
+
+/// A [`PermissionChecker`] which asks the `permission` service, like
+/// `PermissionCache` in C++.
+///
+/// Granted permissions are cached for each UID, because they are rarely
+/// revoked while a process is running. Denials are not cached.
+#[derive(Default)]
+pub struct PermissionController {
+    service: Mutex<Option<SpIBinder>>,
+    granted: Mutex<HashSet<(String, uid_t)>>,
+}
+
+impl PermissionController {
+    /// Create a permission checker with an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a permission checker which asks the given service instead of
+    /// the `permission` service.
+    #[cfg(test)]
+    fn with_service(service: SpIBinder) -> Self {
+        Self { service: Mutex::new(Some(service)), granted: Mutex::default() }
+    }
+
+    /// Forget all cached permissions, e.g. after permissions were revoked.
+    pub fn purge(&self) {
+        self.granted.lock().unwrap().clear();
+    }
+
+    fn service(&self) -> Option<SpIBinder> {
+        let mut service = self.service.lock().unwrap();
+        if service.is_none() {
+            let mut binder = get_service("permission")?;
+            if !binder.associate_class(BnPermissionController::get_class()) {
+                return None;
+            }
+            *service = Some(binder);
+        }
+        service.clone()
+    }
+
+    fn check_remote(&self, permission: &str, pid: pid_t, uid: uid_t) -> crate::Result<bool> {
+        let binder = self.service().ok_or(StatusCode::NAME_NOT_FOUND)?;
+        let result = binder.transact(CHECK_PERMISSION, 0, |mut data| {
+            data.write(permission)?;
+            data.write(&pid)?;
+            data.write(&(uid as i32))
+        });
+        if let Err(StatusCode::DEAD_OBJECT) = result {
+            *self.service.lock().unwrap() = None;
+        }
+        let reply = result?;
+        let status: Status = reply.read()?;
+        if !status.is_ok() {
+            return Err(status);
+        }
+        Ok(reply.read()?)
+    }
+}
+
+impl PermissionChecker for PermissionController {
+    fn check_permission(&self, permission: &str, pid: pid_t, uid: uid_t) -> bool {
+        let key = (permission.to_owned(), uid);
+        if self.granted.lock().unwrap().contains(&key) {
+            return true;
+        }
+        let granted = self.check_remote(permission, pid, uid).unwrap_or(false);
+        if granted {
+            self.granted.lock().unwrap().insert(key);
+        }
+        granted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        app_id, selinux_type, BnPermissionController, Callers, PermissionChecker,
+        PermissionController, PermissionPolicy, CHECK_PERMISSION,
+    };
+    use crate::binder::{
+        BinderFeatures, IBinderInternal, Interface, Stability, FIRST_CALL_TRANSACTION,
+    };
+    use crate::error::{ExceptionCode, Status};
+    use crate::native::Binder;
+    use crate::state::TransactionContext;
+
+    use libc::{pid_t, uid_t};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const DUMP: &str = "android.permission.DUMP";
+    const OTHER_PID: pid_t = 1234;
+
+    #[derive(Default)]
+    struct DenyAll(AtomicUsize);
+
+    impl PermissionChecker for DenyAll {
+        fn check_permission(&self, _permission: &str, _pid: pid_t, _uid: uid_t) -> bool {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            false
+        }
+    }
+
+    /// Grants only the `DUMP` permission, to callers with `OTHER_PID`.
+    #[derive(Default)]
+    struct GrantDump(AtomicUsize);
+
+    impl PermissionChecker for GrantDump {
+        fn check_permission(&self, permission: &str, pid: pid_t, _uid: uid_t) -> bool {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            permission == DUMP && pid == OTHER_PID
+        }
+    }
+
+    #[test]
+    fn test_permission_policy() {
+        let context = TransactionContext::current();
+        let uid = context.calling_uid();
+        let checker = Arc::new(DenyAll::default());
+        let policy = PermissionPolicy::new()
+            .with_permission_checker(checker.clone())
+            .allow(FIRST_CALL_TRANSACTION, Callers::Any)
+            .allow(FIRST_CALL_TRANSACTION + 1, Callers::Uids(vec![uid]))
+            .allow(FIRST_CALL_TRANSACTION + 2, Callers::Uids(vec![uid + 1]))
+            .allow(FIRST_CALL_TRANSACTION + 2, Callers::AppIds(app_id(uid)..=app_id(uid)))
+            .allow(FIRST_CALL_TRANSACTION + 3, Callers::AppIds(app_id(uid) + 1..=app_id(uid) + 1))
+            .allow(
+                FIRST_CALL_TRANSACTION + 4,
+                Callers::Permission("android.permission.DUMP".into()),
+            );
+
+        assert!(policy.is_allowed(FIRST_CALL_TRANSACTION, &context));
+        assert!(policy.is_allowed(FIRST_CALL_TRANSACTION + 1, &context));
+        assert!(policy.is_allowed(FIRST_CALL_TRANSACTION + 2, &context));
+        assert!(!policy.is_allowed(FIRST_CALL_TRANSACTION + 3, &context));
+        // Calls from the same process hold all permissions.
+        assert!(policy.is_allowed(FIRST_CALL_TRANSACTION + 4, &context));
+        assert_eq!(checker.0.load(Ordering::SeqCst), 0);
+        // Codes without a rule are rejected, unless other codes are allowed.
+        assert!(!policy.is_allowed(FIRST_CALL_TRANSACTION + 5, &context));
+        let policy = policy.allow_others(Callers::Uids(vec![uid]));
+        assert!(policy.is_allowed(FIRST_CALL_TRANSACTION + 5, &context));
+    }
+
+    #[test]
+    fn test_permission_checker() {
+        let uid = TransactionContext::current().calling_uid();
+        let policy = |checker| {
+            PermissionPolicy::new()
+                .with_permission_checker(checker)
+                .allow(FIRST_CALL_TRANSACTION, Callers::Permission(DUMP.into()))
+        };
+        let other_process = TransactionContext::from_caller(uid, OTHER_PID);
+
+        let deny_all = Arc::new(DenyAll::default());
+        assert!(!policy(deny_all.clone()).is_allowed(FIRST_CALL_TRANSACTION, &other_process));
+        assert_eq!(deny_all.0.load(Ordering::SeqCst), 1);
+
+        let grant_dump = Arc::new(GrantDump::default());
+        assert!(policy(grant_dump.clone()).is_allowed(FIRST_CALL_TRANSACTION, &other_process));
+        assert_eq!(grant_dump.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_permission_controller() {
+        let service = Arc::new(GrantDump::default());
+        let controller = PermissionController::with_service(
+            Binder::new(BnPermissionController(service.clone())).as_binder(),
+        );
+        let calls = || service.0.load(Ordering::SeqCst);
+
+        // Grants are cached for each UID.
+        assert!(controller.check_permission(DUMP, OTHER_PID, 10057));
+        assert!(controller.check_permission(DUMP, OTHER_PID, 10057));
+        assert_eq!(calls(), 1);
+        assert!(controller.check_permission(DUMP, OTHER_PID, 10058));
+        assert_eq!(calls(), 2);
+
+        // Denials are not cached.
+        assert!(!controller.check_permission(DUMP, OTHER_PID + 1, 10059));
+        assert!(!controller.check_permission(DUMP, OTHER_PID + 1, 10059));
+        assert_eq!(calls(), 4);
+
+        controller.purge();
+        assert!(controller.check_permission(DUMP, OTHER_PID, 10057));
+        assert_eq!(calls(), 5);
+    }
+
+    #[test]
+    fn test_policy_rejects_transactions() {
+        let uid = TransactionContext::current().calling_uid();
+        let policy = PermissionPolicy::new().allow(CHECK_PERMISSION, Callers::Uids(vec![uid + 1]));
+        let features =
+            BinderFeatures { interceptors: vec![Arc::new(policy)], ..BinderFeatures::default() };
+        let service = Arc::new(GrantDump::default());
+        let binder = Binder::new_with_features(
+            BnPermissionController(service.clone()),
+            Stability::default(),
+            features,
+        );
+
+        let reply = binder
+            .as_binder()
+            .transact(CHECK_PERMISSION, 0, |mut data| {
+                data.write(DUMP)?;
+                data.write(&OTHER_PID)?;
+                data.write(&(uid as i32))
+            })
+            .expect("Transaction failed");
+        let status: Status = reply.read().expect("Reply has no status header");
+        assert_eq!(status.exception_code(), ExceptionCode::SECURITY);
+        assert_eq!(service.0.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_app_id() {
+        assert_eq!(app_id(1000), 1000);
+        assert_eq!(app_id(10057), 10057);
+        assert_eq!(app_id(1010057), 10057);
+    }
+
+    #[test]
+    fn test_selinux_type() {
+        assert_eq!(selinux_type("u:r:system_server:s0"), Some("system_server"));
+        assert_eq!(selinux_type("u:r:untrusted_app:s0:c512,c768"), Some("untrusted_app"));
+        assert_eq!(selinux_type("kernel"), None);
+    }
+}
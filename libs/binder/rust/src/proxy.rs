@@ -24,6 +24,8 @@ use crate::error::{status_result, Result, StatusCode};
 use crate::parcel::{
     Parcel, BorrowedParcel, Deserialize, DeserializeArray, DeserializeOption, Serialize, SerializeArray, SerializeOption,
 };
+#[cfg(not(android_vndk))]
+use crate::parcel::allocate_vec_with_buffer;
 use crate::sys;
 
 use std::cmp::Ordering;
@@ -130,6 +132,168 @@ impl SpIBinder {
     pub fn downgrade(&mut self) -> WpIBinder {
         WpIBinder::new(self)
     }
+
+    /// Call `callback` when the process hosting this binder object dies.
+    ///
+    /// The notification lasts until the returned [`DeathLink`] is dropped,
+    /// which unlinks it. The link holds a strong reference to this binder
+    /// object. The callback is called on a binder thread, so it should not
+    /// block.
+    ///
+    /// Local binder objects can't be linked, so this fails with
+    /// `INVALID_OPERATION` for them. If the process has already died, this
+    /// fails with `DEAD_OBJECT`.
+    pub fn watch_death<F>(&self, callback: F) -> Result<DeathLink>
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let mut binder = self.clone();
+        let mut recipient = DeathRecipient::new(callback);
+        binder.link_to_death(&mut recipient)?;
+        Ok(DeathLink { binder, recipient: Some(recipient) })
+    }
+
+    /// Mark this binder object with the stability of the current compilation
+    /// unit, which is vendor if we are building for the VNDK and system
+    /// otherwise. This is the [`Stability::Local`] that [`Binder`] objects are
+    /// marked with when they are created.
+    ///
+    /// Stability can only be set on a local binder object, before it is
+    /// passed to another process. Marking a binder object which already has a
+    /// different stability aborts, see
+    /// [`force_downgrade_to_local_stability`](Self::force_downgrade_to_local_stability)
+    /// instead.
+    ///
+    /// [`Binder`]: crate::binder_impl::Binder
+    /// [`Stability::Local`]: crate::binder_impl::Stability::Local
+    pub fn mark_compilation_unit_stability(&mut self) {
+        #[cfg(any(vendor_ndk, android_vndk))]
+        self.mark_vendor_stability();
+        #[cfg(not(any(vendor_ndk, android_vndk)))]
+        self.mark_system_stability();
+    }
+
+    /// Mark this binder object with vendor stability, see
+    /// [`mark_compilation_unit_stability`](Self::mark_compilation_unit_stability).
+    #[cfg(any(vendor_ndk, android_vndk))]
+    pub fn mark_vendor_stability(&mut self) {
+        unsafe {
+            // Safety: `SpIBinder` guarantees that it always contains a valid
+            // `AIBinder` pointer.
+            sys::AIBinder_markVendorStability(self.as_native_mut());
+        }
+    }
+
+    /// Mark this binder object with system stability, see
+    /// [`mark_compilation_unit_stability`](Self::mark_compilation_unit_stability).
+    #[cfg(not(any(vendor_ndk, android_vndk)))]
+    pub fn mark_system_stability(&mut self) {
+        unsafe {
+            // Safety: `SpIBinder` guarantees that it always contains a valid
+            // `AIBinder` pointer.
+            sys::AIBinder_markSystemStability(self.as_native_mut());
+        }
+    }
+
+    /// Downgrade the stability of this binder object to the local stability,
+    /// which is vendor if we are building for the VNDK and system otherwise.
+    ///
+    /// A binder with a higher stability, e.g. [`Stability::Vintf`], must meet
+    /// requirements such as being declared in the VINTF manifest. Downgrading
+    /// it allows the binder to be passed on to other processes in the same
+    /// partition without meeting them. Transactions on a downgraded binder
+    /// from a process in another partition fail with
+    /// [`StatusCode::BAD_TYPE`](crate::StatusCode::BAD_TYPE).
+    ///
+    /// [`Stability::Vintf`]: crate::binder_impl::Stability::Vintf
+    pub fn force_downgrade_to_local_stability(&mut self) {
+        #[cfg(any(vendor_ndk, android_vndk))]
+        self.force_downgrade_to_vendor_stability();
+        #[cfg(not(any(vendor_ndk, android_vndk)))]
+        self.force_downgrade_to_system_stability();
+    }
+
+    /// Get the interface descriptor of this binder object.
+    ///
+    /// Unlike [`get_class`](IBinderInternal::get_class), this asks the remote
+    /// process with [`INTERFACE_TRANSACTION`](crate::binder_impl::INTERFACE_TRANSACTION),
+    /// so it also works for binders which haven't been associated with a
+    /// class, e.g. binders received by debugging tools.
+    #[cfg(not(android_vndk))]
+    pub fn get_remote_descriptor(&mut self) -> Result<String> {
+        let mut vec: Option<Vec<u8>> = None;
+        let status = unsafe {
+            // Safety: `SpIBinder` guarantees that it always contains a valid
+            // `AIBinder` pointer. `Option<Vec<u8>>` is equivalent to the
+            // expected `Option<Vec<i8>>` for `allocate_vec_with_buffer`, so
+            // `vec` is safe to pass as the opaque data pointer on platforms
+            // where char is signed.
+            sys::AIBinder_getRemoteDescriptor(
+                self.as_native_mut(),
+                &mut vec as *mut _ as *mut c_void,
+                Some(allocate_vec_with_buffer),
+            )
+        };
+        status_result(status)?;
+        let mut descriptor = vec.ok_or(StatusCode::UNEXPECTED_NULL)?;
+        // Drop the null-terminator.
+        descriptor.pop();
+        String::from_utf8(descriptor).or(Err(StatusCode::BAD_VALUE))
+    }
+
+    /// Get the PID of the process which hosts this binder object, with
+    /// [`DEBUG_PID_TRANSACTION`](crate::binder_impl::DEBUG_PID_TRANSACTION).
+    ///
+    /// This is only meant for debugging, since the PID may be reused once the
+    /// process dies.
+    #[cfg(not(android_vndk))]
+    pub fn get_debug_pid(&mut self) -> Result<libc::pid_t> {
+        let mut pid = 0;
+        let status = unsafe {
+            // Safety: `SpIBinder` guarantees that it always contains a valid
+            // `AIBinder` pointer, and `pid` is a valid out pointer for the
+            // duration of the call.
+            sys::AIBinder_getDebugPid(self.as_native_mut(), &mut pid)
+        };
+        status_result(status)?;
+        Ok(pid)
+    }
+
+    /// Notify the process which hosts this binder object that system
+    /// properties changed, with
+    /// [`SYSPROPS_TRANSACTION`](crate::binder_impl::SYSPROPS_TRANSACTION), so
+    /// that it reloads them.
+    #[cfg(not(android_vndk))]
+    pub fn sysprops_changed(&mut self) -> Result<()> {
+        let status = unsafe {
+            // Safety: `SpIBinder` guarantees that it always contains a valid
+            // `AIBinder` pointer.
+            sys::AIBinder_notifySyspropsChanged(self.as_native_mut())
+        };
+        status_result(status)
+    }
+
+    /// Downgrade the stability of this binder object to vendor, see
+    /// [`force_downgrade_to_local_stability`](Self::force_downgrade_to_local_stability).
+    #[cfg(any(vendor_ndk, android_vndk))]
+    pub fn force_downgrade_to_vendor_stability(&mut self) {
+        unsafe {
+            // Safety: `SpIBinder` guarantees that it always contains a valid
+            // `AIBinder` pointer.
+            sys::AIBinder_forceDowngradeToVendorStability(self.as_native_mut());
+        }
+    }
+
+    /// Downgrade the stability of this binder object to system, see
+    /// [`force_downgrade_to_local_stability`](Self::force_downgrade_to_local_stability).
+    #[cfg(not(any(vendor_ndk, android_vndk)))]
+    pub fn force_downgrade_to_system_stability(&mut self) {
+        unsafe {
+            // Safety: `SpIBinder` guarantees that it always contains a valid
+            // `AIBinder` pointer.
+            sys::AIBinder_forceDowngradeToSystemStability(self.as_native_mut());
+        }
+    }
 }
 
 pub mod unstable_api {
@@ -265,42 +429,60 @@ impl<T: AsNative<sys::AIBinder>> IBinderInternal for T {
         data: Parcel,
         flags: TransactionFlags,
     ) -> Result<Parcel> {
-        let mut reply = ptr::null_mut();
-        let status = unsafe {
-            // Safety: `SpIBinder` guarantees that `self` always contains a
-            // valid pointer to an `AIBinder`. Although `IBinder::transact` is
-            // not a const method, it is still safe to cast our immutable
-            // pointer to mutable for the call. First, `IBinder::transact` is
-            // thread-safe, so concurrency is not an issue. The only way that
-            // `transact` can affect any visible, mutable state in the current
-            // process is by calling `onTransact` for a local service. However,
-            // in order for transactions to be thread-safe, this method must
-            // dynamically lock its data before modifying it. We enforce this
-            // property in Rust by requiring `Sync` for remotable objects and
-            // only providing `on_transact` with an immutable reference to
-            // `self`.
-            //
-            // This call takes ownership of the `data` parcel pointer, and
-            // passes ownership of the `reply` out parameter to its caller. It
-            // does not affect ownership of the `binder` parameter.
-            sys::AIBinder_transact(
-                self.as_native() as *mut sys::AIBinder,
-                code,
-                &mut data.into_raw(),
-                &mut reply,
-                flags,
-            )
-        };
-        status_result(status)?;
-
-        unsafe {
-            // Safety: `reply` is either a valid `AParcel` pointer or null
-            // after the call to `AIBinder_transact` above, so we can
-            // construct a `Parcel` out of it. `AIBinder_transact` passes
-            // ownership of the `reply` parcel to Rust, so we need to
-            // construct an owned variant.
-            Parcel::from_raw(reply).ok_or(StatusCode::UNEXPECTED_NULL)
-        }
+        let span = crate::trace::TransactionSpan::outgoing(
+            || unsafe {
+                // Safety: `SpIBinder` guarantees that `self` always contains a
+                // valid pointer to an `AIBinder`. `AIBinder_getClass` returns
+                // either a null pointer or a valid pointer to an
+                // `AIBinder_Class`, and does not modify the binder.
+                sys::AIBinder_getClass(self.as_native() as *mut sys::AIBinder)
+                    .as_ref()
+                    .map(|class| InterfaceClass::from_ptr(class))
+            },
+            code,
+            flags,
+            data.get_data_size(),
+        );
+        let reply = span.in_scope(|| {
+            let mut reply = ptr::null_mut();
+            let status = unsafe {
+                // Safety: `SpIBinder` guarantees that `self` always contains a
+                // valid pointer to an `AIBinder`. Although `IBinder::transact` is
+                // not a const method, it is still safe to cast our immutable
+                // pointer to mutable for the call. First, `IBinder::transact` is
+                // thread-safe, so concurrency is not an issue. The only way that
+                // `transact` can affect any visible, mutable state in the current
+                // process is by calling `onTransact` for a local service. However,
+                // in order for transactions to be thread-safe, this method must
+                // dynamically lock its data before modifying it. We enforce this
+                // property in Rust by requiring `Sync` for remotable objects and
+                // only providing `on_transact` with an immutable reference to
+                // `self`.
+                //
+                // This call takes ownership of the `data` parcel pointer, and
+                // passes ownership of the `reply` out parameter to its caller. It
+                // does not affect ownership of the `binder` parameter.
+                sys::AIBinder_transact(
+                    self.as_native() as *mut sys::AIBinder,
+                    code,
+                    &mut data.into_raw(),
+                    &mut reply,
+                    flags,
+                )
+            };
+            status_result(status)?;
+
+            unsafe {
+                // Safety: `reply` is either a valid `AParcel` pointer or null
+                // after the call to `AIBinder_transact` above, so we can
+                // construct a `Parcel` out of it. `AIBinder_transact` passes
+                // ownership of the `reply` parcel to Rust, so we need to
+                // construct an owned variant.
+                Parcel::from_raw(reply).ok_or(StatusCode::UNEXPECTED_NULL)
+            }
+        });
+        span.record_result(reply.as_ref().ok().map(Parcel::get_data_size), &reply);
+        reply
     }
 
     fn is_binder_alive(&self) -> bool {
@@ -415,6 +597,47 @@ impl<T: AsNative<sys::AIBinder>> IBinder for T {
     }
 }
 
+// Internal parcel APIs for binder objects
+impl<'a> BorrowedParcel<'a> {
+    pub(crate) fn write_binder(&mut self, binder: Option<&SpIBinder>) -> Result<()> {
+        unsafe {
+            // Safety: `BorrowedParcel` always contains a valid pointer to an
+            // `AParcel`. `AsNative` for `Option<SpIBinder`> will either return
+            // null or a valid pointer to an `AIBinder`, both of which are
+            // valid, safe inputs to `AParcel_writeStrongBinder`.
+            //
+            // This call does not take ownership of the binder. However, it does
+            // require a mutable pointer, which we cannot extract from an
+            // immutable reference, so we clone the binder, incrementing the
+            // refcount before the call. The refcount will be immediately
+            // decremented when this temporary is dropped.
+            status_result(sys::AParcel_writeStrongBinder(
+                self.as_native_mut(),
+                binder.cloned().as_native_mut(),
+            ))
+        }
+    }
+
+    pub(crate) fn read_binder(&self) -> Result<Option<SpIBinder>> {
+        let mut binder = ptr::null_mut();
+        let status = unsafe {
+            // Safety: `BorrowedParcel` always contains a valid pointer to an
+            // `AParcel`. We pass a valid, mutable out pointer to the `binder`
+            // parameter. After this call, `binder` will be either null or a
+            // valid pointer to an `AIBinder` owned by the caller.
+            sys::AParcel_readStrongBinder(self.as_native(), &mut binder)
+        };
+
+        status_result(status)?;
+
+        Ok(unsafe {
+            // Safety: `binder` is either null or a valid, owned pointer at this
+            // point, so can be safely passed to `SpIBinder::from_raw`.
+            SpIBinder::from_raw(binder)
+        })
+    }
+}
+
 impl Serialize for SpIBinder {
     fn serialize(&self, parcel: &mut BorrowedParcel<'_>) -> Result<()> {
         parcel.write_binder(Some(self))
@@ -701,6 +924,51 @@ unsafe impl AsNative<sys::AIBinder_DeathRecipient> for DeathRecipient {
     }
 }
 
+/// A death notification for a binder object, created by
+/// [`SpIBinder::watch_death`]. The notification is unlinked when this is
+/// dropped.
+#[must_use]
+pub struct DeathLink {
+    binder: SpIBinder,
+    recipient: Option<DeathRecipient>,
+}
+
+impl DeathLink {
+    /// The binder object that this notification is linked to.
+    pub fn binder(&self) -> &SpIBinder {
+        &self.binder
+    }
+
+    /// Unlink the death notification, reporting any error.
+    ///
+    /// This fails with `DEAD_OBJECT` if the notification was already
+    /// delivered.
+    pub fn unlink(mut self) -> Result<()> {
+        self.unlink_internal()
+    }
+
+    fn unlink_internal(&mut self) -> Result<()> {
+        match self.recipient.take() {
+            Some(mut recipient) => self.binder.unlink_to_death(&mut recipient),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for DeathLink {
+    fn drop(&mut self) {
+        // Unlinking fails if the process already died, in which case there is
+        // nothing to clean up.
+        let _ = self.unlink_internal();
+    }
+}
+
+impl fmt::Debug for DeathLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeathLink").field("binder", &self.binder).finish()
+    }
+}
+
 impl Drop for DeathRecipient {
     fn drop(&mut self) {
         unsafe {
@@ -761,6 +1029,18 @@ pub fn get_service(name: &str) -> Option<SpIBinder> {
     }
 }
 
+/// Retrieve an existing service without waiting for it, returning `None` if it
+/// isn't registered.
+pub fn check_service(name: &str) -> Option<SpIBinder> {
+    let name = CString::new(name).ok()?;
+    unsafe {
+        // Safety: `AServiceManager_checkService` returns either a null pointer
+        // or a valid pointer to an owned `AIBinder`. Either of these values is
+        // safe to pass to `SpIBinder::from_raw`.
+        SpIBinder::from_raw(sys::AServiceManager_checkService(name.as_ptr()))
+    }
+}
+
 /// Retrieve an existing service, or start it if it is configured as a dynamic
 /// service and isn't yet started.
 pub fn wait_for_service(name: &str) -> Option<SpIBinder> {
@@ -805,6 +1085,18 @@ pub fn is_declared(interface: &str) -> Result<bool> {
     }
 }
 
+/// Check if a service is updatable via an APEX module
+pub fn is_updatable_via_apex(instance: &str) -> Result<bool> {
+    let instance = CString::new(instance).or(Err(StatusCode::UNEXPECTED_NULL))?;
+
+    unsafe {
+        // Safety: `instance` is a valid null-terminated C-style string and is
+        // only borrowed for the lifetime of the call. The `instance` local
+        // outlives this call as it lives for the function scope.
+        Ok(sys::AServiceManager_isUpdatableViaApex(instance.as_ptr()))
+    }
+}
+
 /// Retrieve all declared instances for a particular interface
 ///
 /// For instance, if 'android.foo.IFoo/foo' is declared, and 'android.foo.IFoo'
@@ -861,3 +1153,22 @@ unsafe impl AsNative<sys::AIBinder> for SpIBinder {
         self.0.as_ptr()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::Binder;
+
+    #[test]
+    fn test_read_binder_from_empty_parcel() {
+        let parcel = Parcel::new();
+        assert_eq!(parcel.borrowed_ref().read_binder().err(), Some(StatusCode::BAD_TYPE));
+    }
+
+    #[test]
+    fn test_marshal_rejects_objects() {
+        let mut parcel = Parcel::new();
+        parcel.write(&Binder::new(()).as_binder()).unwrap();
+        assert_eq!(Err(StatusCode::INVALID_OPERATION), parcel.marshal());
+    }
+}
@@ -0,0 +1,225 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A handle to a named service which reconnects after the service dies.
+
+use crate::binder::{FromIBinder, Strong};
+use crate::error::StatusCode;
+use crate::proxy::{get_interface, DeathLink};
+
+use std::cmp;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How [`ServiceHandle::call_idempotent`] retries calls that fail because the
+/// service died or isn't registered.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RetryPolicy {
+    /// The maximum number of times to retry a call.
+    pub max_retries: u32,
+    /// The time to wait before the first retry. This doubles after every
+    /// retry.
+    pub initial_backoff: Duration,
+    /// The maximum time to wait between retries.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+/// A live connection to the service.
+struct Connection<I: FromIBinder + ?Sized> {
+    service: Strong<I>,
+    dead: Arc<AtomicBool>,
+    // Local services can't die, so they have no death link.
+    _link: Option<DeathLink>,
+}
+
+/// A handle to a named service which reconnects after the service dies.
+///
+/// The service is retrieved from the service manager on first use, and again
+/// on the next use after the hosting process dies, so callers never see a
+/// proxy which is permanently dead. Calls which were in flight when the
+/// service died still fail with `DEAD_OBJECT`, unless they are made with
+/// [`call_idempotent`](Self::call_idempotent).
+///
+/// Death notifications are delivered on a binder thread, so the binder thread
+/// pool should be started with `ProcessState::start_thread_pool`. Without it,
+/// deaths are only noticed when a call fails with `DEAD_OBJECT`.
+///
+/// # Examples
+///
+/// ```ignore
+/// let foo = ServiceHandle::<dyn IFoo>::new("foo");
+/// let name = foo.call_idempotent(|foo| foo.get_name())?;
+/// ```
+pub struct ServiceHandle<I: FromIBinder + ?Sized> {
+    name: String,
+    retry_policy: RetryPolicy,
+    connection: Mutex<Option<Connection<I>>>,
+}
+
+impl<I: FromIBinder + ?Sized> ServiceHandle<I> {
+    /// Create a handle for the service with the given name, which uses the
+    /// default [`RetryPolicy`]. The service is not retrieved until it is used.
+    pub fn new(name: &str) -> Self {
+        Self::with_retry_policy(name, RetryPolicy::default())
+    }
+
+    /// Create a handle for the service with the given name and retry policy.
+    pub fn with_retry_policy(name: &str, retry_policy: RetryPolicy) -> Self {
+        Self { name: name.to_owned(), retry_policy, connection: Mutex::new(None) }
+    }
+
+    /// The name of the service.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the service, retrieving it from the service manager if there is no
+    /// live connection to it.
+    ///
+    /// The lock is not held while the service is retrieved, so other threads
+    /// can keep using a live connection that one of them installs meanwhile.
+    pub fn get(&self) -> Result<Strong<I>, StatusCode> {
+        {
+            let mut connection = self.connection.lock().unwrap();
+            if let Some(service) = live_service(&connection) {
+                return Ok(service);
+            }
+            // Drop the stale proxy before retrieving the new one.
+            *connection = None;
+        }
+
+        let service: Strong<I> = get_interface(&self.name)?;
+        let dead = Arc::new(AtomicBool::new(false));
+        let link = {
+            let dead = dead.clone();
+            match service.as_binder().watch_death(move || dead.store(true, Ordering::Relaxed)) {
+                Ok(link) => Some(link),
+                Err(StatusCode::INVALID_OPERATION) => None,
+                Err(e) => return Err(e),
+            }
+        };
+
+        let mut connection = self.connection.lock().unwrap();
+        // Another thread may have reconnected while the lock wasn't held.
+        if let Some(service) = live_service(&connection) {
+            return Ok(service);
+        }
+        *connection = Some(Connection { service: service.clone(), dead, _link: link });
+        Ok(service)
+    }
+
+    /// Call `f` with the service once.
+    ///
+    /// If the call fails with `DEAD_OBJECT`, the connection is dropped so the
+    /// next use reconnects.
+    pub fn call<T, F>(&self, f: F) -> crate::Result<T>
+    where
+        F: FnOnce(&Strong<I>) -> crate::Result<T>,
+    {
+        let service = self.get()?;
+        let result = f(&service);
+        if is_status_code(&result, StatusCode::DEAD_OBJECT) {
+            self.invalidate(&service);
+        }
+        result
+    }
+
+    /// Call `f` with the service, retrying according to the retry policy if
+    /// the service died or isn't registered.
+    ///
+    /// This must only be used for calls which are safe to repeat, because the
+    /// service may have handled the call before it died.
+    pub fn call_idempotent<T, F>(&self, mut f: F) -> crate::Result<T>
+    where
+        F: FnMut(&Strong<I>) -> crate::Result<T>,
+    {
+        let mut backoff = self.retry_policy.initial_backoff;
+        let mut retries = 0;
+        loop {
+            let result = self.call(&mut f);
+            let retryable = is_status_code(&result, StatusCode::DEAD_OBJECT)
+                || is_status_code(&result, StatusCode::NAME_NOT_FOUND);
+            if !retryable || retries >= self.retry_policy.max_retries {
+                return result;
+            }
+            thread::sleep(backoff);
+            backoff = cmp::min(backoff * 2, self.retry_policy.max_backoff);
+            retries += 1;
+        }
+    }
+
+    /// Drop the connection to `service` if it is still the current one.
+    fn invalidate(&self, service: &Strong<I>) {
+        let mut connection = self.connection.lock().unwrap();
+        if let Some(current) = &*connection {
+            if current.service.as_binder() == service.as_binder() {
+                *connection = None;
+            }
+        }
+    }
+}
+
+impl<I: FromIBinder + ?Sized> fmt::Debug for ServiceHandle<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceHandle")
+            .field("name", &self.name)
+            .field("retry_policy", &self.retry_policy)
+            .finish()
+    }
+}
+
+/// The service of `connection`, if it is still alive.
+fn live_service<I: FromIBinder + ?Sized>(connection: &Option<Connection<I>>) -> Option<Strong<I>> {
+    connection
+        .as_ref()
+        .filter(|connection| !connection.dead.load(Ordering::Relaxed))
+        .map(|connection| connection.service.clone())
+}
+
+fn is_status_code<T>(result: &crate::Result<T>, code: StatusCode) -> bool {
+    matches!(result, Err(status) if status.transaction_error() == code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_status_code;
+    use crate::error::{ExceptionCode, Status, StatusCode};
+
+    #[test]
+    fn test_is_status_code() {
+        let dead: crate::Result<()> = Err(StatusCode::DEAD_OBJECT.into());
+        assert!(is_status_code(&dead, StatusCode::DEAD_OBJECT));
+        assert!(!is_status_code(&dead, StatusCode::NAME_NOT_FOUND));
+
+        let exception: crate::Result<()> =
+            Err(Status::new_exception(ExceptionCode::ILLEGAL_STATE, None));
+        assert!(!is_status_code(&exception, StatusCode::DEAD_OBJECT));
+        assert!(!is_status_code(&Ok(()), StatusCode::DEAD_OBJECT));
+    }
+}
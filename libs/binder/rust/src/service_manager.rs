@@ -0,0 +1,374 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Rust API for the service manager.
+//!
+//! Lookups and registration which the binder NDK supports go through
+//! `libbinder_ndk`. The rest of `android.os.IServiceManager` is not exposed by
+//! the NDK, so it is called directly on the `manager` service. These calls
+//! require the caller to be allowed to use the service manager by SELinux, and
+//! are not available to vendor processes, which use `vndservicemanager`.
+
+use crate::binder::{
+    IBinderInternal, Interface, Remotable, TransactionCode, FIRST_CALL_TRANSACTION,
+    FLAG_PRIVATE_LOCAL,
+};
+use crate::error::{Status, StatusCode};
+use crate::native::Binder;
+use crate::parcel::{BorrowedParcel, Parcel, Parcelable};
+use crate::proxy::{AssociateClass, SpIBinder};
+
+use std::ffi::CStr;
+use std::fs::File;
+
+pub use crate::native::{
+    add_service, force_lazy_services_persist, re_register, register_lazy_service,
+    set_active_services_callback, try_unregister,
+};
+pub use crate::proxy::{
+    check_service, get_declared_instances, get_interface, get_service, is_declared,
+    is_updatable_via_apex, wait_for_interface, wait_for_service,
+};
+pub use crate::service_handle::{RetryPolicy, ServiceHandle};
+
+/// Dump priority of services which must be dumped first, e.g. in a bug report.
+pub const DUMP_FLAG_PRIORITY_CRITICAL: i32 = 1 << 0;
+/// Dump priority of services which should be dumped with high priority.
+pub const DUMP_FLAG_PRIORITY_HIGH: i32 = 1 << 1;
+/// Dump priority of services with normal priority.
+pub const DUMP_FLAG_PRIORITY_NORMAL: i32 = 1 << 2;
+/// Dump priority of services which don't specify one.
+pub const DUMP_FLAG_PRIORITY_DEFAULT: i32 = 1 << 3;
+/// All dump priorities, used to list every service.
+pub const DUMP_FLAG_PRIORITY_ALL: i32 = DUMP_FLAG_PRIORITY_CRITICAL
+    | DUMP_FLAG_PRIORITY_HIGH
+    | DUMP_FLAG_PRIORITY_NORMAL
+    | DUMP_FLAG_PRIORITY_DEFAULT;
+/// Indicates that the service supports dumping in protobuf format.
+pub const DUMP_FLAG_PROTO: i32 = 1 << 4;
+
+// Transaction codes of `android.os.IServiceManager`, in declaration order.
+const ADD_SERVICE: TransactionCode = FIRST_CALL_TRANSACTION + 2;
+const LIST_SERVICES: TransactionCode = FIRST_CALL_TRANSACTION + 3;
+const REGISTER_FOR_NOTIFICATIONS: TransactionCode = FIRST_CALL_TRANSACTION + 4;
+const UNREGISTER_FOR_NOTIFICATIONS: TransactionCode = FIRST_CALL_TRANSACTION + 5;
+const GET_CONNECTION_INFO: TransactionCode = FIRST_CALL_TRANSACTION + 9;
+const GET_SERVICE_DEBUG_INFO: TransactionCode = FIRST_CALL_TRANSACTION + 12;
+
+/// Options for registering a service with [`add_service_with_options`].
+///
+/// This should always be initialised with a default value, e.g.:
+/// ```
+/// # use binder::service_manager::{AddServiceOptions, DUMP_FLAG_PRIORITY_HIGH};
+/// AddServiceOptions {
+///   dump_priority: DUMP_FLAG_PRIORITY_HIGH,
+///   ..AddServiceOptions::default()
+/// };
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AddServiceOptions {
+    /// Allow isolated processes to retrieve the service.
+    pub allow_isolated: bool,
+    /// Combination of `DUMP_FLAG_*` flags used by `dumpsys` to select services.
+    pub dump_priority: i32,
+    // Ensure that clients include a ..AddServiceOptions::default() to preserve
+    // backwards compatibility when new fields are added.
+    #[doc(hidden)]
+    pub _non_exhaustive: (),
+}
+
+impl Default for AddServiceOptions {
+    fn default() -> Self {
+        Self {
+            allow_isolated: false,
+            dump_priority: DUMP_FLAG_PRIORITY_DEFAULT,
+            _non_exhaustive: (),
+        }
+    }
+}
+
+/// Address of a service which is also served over RPC binder, as returned by
+/// [`get_connection_info`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ConnectionInfo {
+    /// IP address of the RPC server.
+    pub ip_address: String,
+    /// Port of the RPC server.
+    pub port: i32,
+}
+
+impl Parcelable for ConnectionInfo {
+    fn write_to_parcel(&self, parcel: &mut BorrowedParcel<'_>) -> crate::error::Result<()> {
+        parcel.sized_write(|subparcel| {
+            subparcel.write(&self.ip_address)?;
+            subparcel.write(&self.port)
+        })
+    }
+
+    fn read_from_parcel(&mut self, parcel: &BorrowedParcel<'_>) -> crate::error::Result<()> {
+        parcel.sized_read(|subparcel| {
+            if subparcel.has_more_data() {
+                self.ip_address = subparcel.read()?;
+            }
+            if subparcel.has_more_data() {
+                self.port = subparcel.read()?;
+            }
+            Ok(())
+        })
+    }
+}
+
+crate::impl_serialize_for_parcelable!(ConnectionInfo);
+crate::impl_deserialize_for_parcelable!(ConnectionInfo);
+
+/// Debugging information about a registered service, as returned by
+/// [`get_service_debug_info`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ServiceDebugInfo {
+    /// Name of the service.
+    pub name: String,
+    /// PID of the process which registered the service.
+    pub debug_pid: i32,
+}
+
+impl Parcelable for ServiceDebugInfo {
+    fn write_to_parcel(&self, parcel: &mut BorrowedParcel<'_>) -> crate::error::Result<()> {
+        parcel.sized_write(|subparcel| {
+            subparcel.write(&self.name)?;
+            subparcel.write(&self.debug_pid)
+        })
+    }
+
+    fn read_from_parcel(&mut self, parcel: &BorrowedParcel<'_>) -> crate::error::Result<()> {
+        parcel.sized_read(|subparcel| {
+            if subparcel.has_more_data() {
+                self.name = subparcel.read()?;
+            }
+            if subparcel.has_more_data() {
+                self.debug_pid = subparcel.read()?;
+            }
+            Ok(())
+        })
+    }
+}
+
+crate::impl_serialize_for_parcelable!(ServiceDebugInfo);
+crate::impl_deserialize_for_parcelable!(ServiceDebugInfo);
+
+/// Remotable which only provides the interface class used to make transactions
+/// to the service manager. It is never instantiated.
+struct ServiceManagerClass;
+
+impl Remotable for ServiceManagerClass {
+    fn get_descriptor() -> &'static str {
+        "android.os.IServiceManager"
+    }
+
+    fn on_transact(
+        &self,
+        _code: TransactionCode,
+        _data: &BorrowedParcel<'_>,
+        _reply: &mut BorrowedParcel<'_>,
+    ) -> crate::error::Result<()> {
+        Err(StatusCode::UNKNOWN_TRANSACTION)
+    }
+
+    fn on_dump(&self, _file: &File, _args: &[&CStr]) -> crate::error::Result<()> {
+        Ok(())
+    }
+
+    binder_fn_get_class!(Binder::<Self>);
+}
+
+/// Closure called with the name and binder of a newly registered service.
+type ServiceCallbackFn = dyn Fn(&str, SpIBinder) + Send + Sync;
+
+/// Native implementation of `android.os.IServiceCallback`, which forwards
+/// registrations to a Rust closure.
+struct ServiceCallback {
+    callback: Box<ServiceCallbackFn>,
+}
+
+impl Remotable for ServiceCallback {
+    fn get_descriptor() -> &'static str {
+        "android.os.IServiceCallback"
+    }
+
+    fn on_transact(
+        &self,
+        code: TransactionCode,
+        data: &BorrowedParcel<'_>,
+        _reply: &mut BorrowedParcel<'_>,
+    ) -> crate::error::Result<()> {
+        match code {
+            // oneway void onRegistration(@utf8InCpp String name, IBinder binder)
+            FIRST_CALL_TRANSACTION => {
+                let name: String = data.read()?;
+                let binder: SpIBinder = data.read()?;
+                (self.callback)(&name, binder);
+                Ok(())
+            }
+            _ => Err(StatusCode::UNKNOWN_TRANSACTION),
+        }
+    }
+
+    fn on_dump(&self, _file: &File, _args: &[&CStr]) -> crate::error::Result<()> {
+        Ok(())
+    }
+
+    binder_fn_get_class!(Binder::<Self>);
+}
+
+/// A registration for notifications about a service, created by
+/// [`register_for_notifications`].
+///
+/// Dropping the registration unregisters the callback.
+#[must_use = "the callback is unregistered when the registration is dropped"]
+pub struct ServiceNotificationRegistration {
+    name: String,
+    callback: Option<SpIBinder>,
+}
+
+impl ServiceNotificationRegistration {
+    /// Unregister the callback, returning any error from the service manager.
+    pub fn unregister(mut self) -> crate::Result<()> {
+        self.unregister_internal()
+    }
+
+    fn unregister_internal(&mut self) -> crate::Result<()> {
+        if let Some(callback) = self.callback.take() {
+            transact_service_manager(UNREGISTER_FOR_NOTIFICATIONS, |mut data| {
+                data.write(self.name.as_str())?;
+                data.write(&callback)
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ServiceNotificationRegistration {
+    fn drop(&mut self) {
+        // The service manager also drops the callback if this process dies, so
+        // there is nothing else to do if this fails.
+        let _ = self.unregister_internal();
+    }
+}
+
+/// Send a transaction to the service manager, and check the status header of
+/// the reply. On success, the reply is positioned at the return value.
+fn transact_service_manager<F>(code: TransactionCode, input_callback: F) -> crate::Result<Parcel>
+where
+    F: FnOnce(BorrowedParcel<'_>) -> crate::error::Result<()>,
+{
+    let mut binder = check_service("manager").ok_or(StatusCode::NAME_NOT_FOUND)?;
+    if !binder.associate_class(ServiceManagerClass::get_class()) {
+        return Err(StatusCode::BAD_TYPE.into());
+    }
+    let reply = binder.transact(code, FLAG_PRIVATE_LOCAL, input_callback)?;
+    let status: Status = reply.read()?;
+    if status.is_ok() {
+        Ok(reply)
+    } else {
+        Err(status)
+    }
+}
+
+/// Register a new service with the default service manager, with the given
+/// options.
+///
+/// Unlike [`add_service`], this can allow isolated processes to retrieve the
+/// service and set its dump priority.
+pub fn add_service_with_options(
+    identifier: &str,
+    binder: SpIBinder,
+    options: &AddServiceOptions,
+) -> crate::Result<()> {
+    transact_service_manager(ADD_SERVICE, |mut data| {
+        data.write(identifier)?;
+        data.write(&binder)?;
+        data.write(&options.allow_isolated)?;
+        data.write(&options.dump_priority)
+    })?;
+    Ok(())
+}
+
+/// Register a callback which is called with the name and binder of the given
+/// service whenever it is registered with the service manager.
+///
+/// If the service is already registered, the callback is called immediately
+/// with the current binder. The callback is called on a binder thread, so the
+/// binder thread pool must be started with
+/// [`ProcessState::start_thread_pool`](crate::ProcessState::start_thread_pool).
+pub fn register_for_notifications<F>(
+    name: &str,
+    callback: F,
+) -> crate::Result<ServiceNotificationRegistration>
+where
+    F: Fn(&str, SpIBinder) + Send + Sync + 'static,
+{
+    let callback = Binder::new(ServiceCallback { callback: Box::new(callback) }).as_binder();
+    transact_service_manager(REGISTER_FOR_NOTIFICATIONS, |mut data| {
+        data.write(name)?;
+        data.write(&callback)
+    })?;
+    Ok(ServiceNotificationRegistration { name: name.to_string(), callback: Some(callback) })
+}
+
+/// List the names of the registered services with any of the given dump
+/// priorities, e.g. [`DUMP_FLAG_PRIORITY_ALL`] for every service.
+pub fn list_services(dump_priority: i32) -> crate::Result<Vec<String>> {
+    let reply = transact_service_manager(LIST_SERVICES, |mut data| data.write(&dump_priority))?;
+    Ok(reply.read()?)
+}
+
+/// Get the address at which a service is also served over RPC binder, if any.
+pub fn get_connection_info(name: &str) -> crate::Result<Option<ConnectionInfo>> {
+    let reply = transact_service_manager(GET_CONNECTION_INFO, |mut data| data.write(name))?;
+    Ok(reply.read()?)
+}
+
+/// Get debugging information about every registered service.
+pub fn get_service_debug_info() -> crate::Result<Vec<ServiceDebugInfo>> {
+    let reply = transact_service_manager(GET_SERVICE_DEBUG_INFO, |_| Ok(()))?;
+    Ok(reply.read()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectionInfo, ServiceDebugInfo};
+    use crate::parcel::Parcel;
+
+    #[test]
+    fn test_service_manager_parcelables() {
+        let info = ConnectionInfo { ip_address: "127.0.0.1".to_string(), port: 5000 };
+        let debug_info = vec![
+            ServiceDebugInfo { name: "foo".to_string(), debug_pid: 1 },
+            ServiceDebugInfo { name: "bar".to_string(), debug_pid: 2 },
+        ];
+
+        let mut parcel = Parcel::new();
+        let start = parcel.get_data_position();
+        assert!(parcel.write(&Some(&info)).is_ok());
+        assert!(parcel.write(&debug_info).is_ok());
+
+        unsafe {
+            assert!(parcel.set_data_position(start).is_ok());
+        }
+
+        assert_eq!(parcel.read::<Option<ConnectionInfo>>(), Ok(Some(info)));
+        assert_eq!(parcel.read::<Vec<ServiceDebugInfo>>(), Ok(debug_info));
+    }
+}
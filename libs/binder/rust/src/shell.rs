@@ -0,0 +1,236 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Interfaces which `cmd` passes to the shell commands of a service, see
+//! [`Interface::handle_shell_command`](crate::Interface::handle_shell_command).
+//!
+//! These match the C++ `IShellCallback` and `IResultReceiver` classes in
+//! libbinder.
+
+use crate::binder::{
+    IBinderInternal, Interface, TransactionCode, FIRST_CALL_TRANSACTION, FLAG_ONEWAY,
+};
+use crate::error::{Status, StatusCode};
+use crate::native::Binder;
+use crate::parcel::{BorrowedParcel, ParcelFileDescriptor};
+
+/// Transaction code of `IShellCallback.openFile`.
+const OPEN_FILE: TransactionCode = FIRST_CALL_TRANSACTION;
+
+/// Transaction code of `IResultReceiver.send`.
+const SEND: TransactionCode = FIRST_CALL_TRANSACTION;
+
+/// Lets a shell command open files with the permissions of its caller, since
+/// services usually can't open files in the shell's directories themselves.
+pub trait IShellCallback: Interface {
+    /// Open the file at `path` in the given mode, e.g. `"r"` or `"w"`.
+    ///
+    /// `selinux_context` is the security context of the process which will use
+    /// the file, which the caller checks is allowed to access it. Returns
+    /// `None` if the caller refused to open the file.
+    fn open_file(
+        &self,
+        path: &str,
+        selinux_context: &str,
+        mode: &str,
+    ) -> crate::Result<Option<ParcelFileDescriptor>>;
+}
+
+declare_binder_interface! {
+    IShellCallback["com.android.internal.os.IShellCallback"] {
+        native: BnShellCallback(on_shell_callback_transact),
+        proxy: BpShellCallback,
+    }
+}
+
+fn on_shell_callback_transact(
+    service: &dyn IShellCallback,
+    code: TransactionCode,
+    data: &BorrowedParcel<'_>,
+    reply: &mut BorrowedParcel<'_>,
+) -> Result<(), StatusCode> {
+    match code {
+        OPEN_FILE => {
+            let path: String = data.read()?;
+            let selinux_context: String = data.read()?;
+            let mode: String = data.read()?;
+            match service.open_file(&path, &selinux_context, &mode) {
+                Ok(file) => {
+                    reply.write(&Status::ok())?;
+                    reply.write(&file)
+                }
+                Err(status) => reply.write(&status),
+            }
+        }
+        _ => Err(StatusCode::UNKNOWN_TRANSACTION),
+    }
+}
+
+impl IShellCallback for BpShellCallback {
+    fn open_file(
+        &self,
+        path: &str,
+        selinux_context: &str,
+        mode: &str,
+    ) -> crate::Result<Option<ParcelFileDescriptor>> {
+        let reply = self.binder.transact(OPEN_FILE, 0, |mut data| {
+            data.write(path)?;
+            data.write(selinux_context)?;
+            data.write(mode)
+        })?;
+        let status: Status = reply.read()?;
+        if !status.is_ok() {
+            return Err(status);
+        }
+        Ok(reply.read()?)
+    }
+}
+
+impl IShellCallback for Binder<BnShellCallback> {
+    fn open_file(
+        &self,
+        path: &str,
+        selinux_context: &str,
+        mode: &str,
+    ) -> crate::Result<Option<ParcelFileDescriptor>> {
+        self.0.open_file(path, selinux_context, mode)
+    }
+}
+
+/// Receives the result of a shell command.
+///
+/// libbinder_ndk sends the result of Rust shell commands itself, so services
+/// only need this to forward commands to other services.
+pub trait IResultReceiver: Interface {
+    /// Report the result of the command. This is a oneway call.
+    fn send(&self, result_code: i32) -> crate::Result<()>;
+}
+
+declare_binder_interface! {
+    IResultReceiver["com.android.internal.os.IResultReceiver"] {
+        native: BnResultReceiver(on_result_receiver_transact),
+        proxy: BpResultReceiver,
+    }
+}
+
+fn on_result_receiver_transact(
+    service: &dyn IResultReceiver,
+    code: TransactionCode,
+    data: &BorrowedParcel<'_>,
+    _reply: &mut BorrowedParcel<'_>,
+) -> Result<(), StatusCode> {
+    match code {
+        SEND => {
+            // Errors can't be reported to the sender of a oneway call.
+            let _ = service.send(data.read()?);
+            Ok(())
+        }
+        _ => Err(StatusCode::UNKNOWN_TRANSACTION),
+    }
+}
+
+impl IResultReceiver for BpResultReceiver {
+    fn send(&self, result_code: i32) -> crate::Result<()> {
+        self.binder.transact(SEND, FLAG_ONEWAY, |mut data| data.write(&result_code))?;
+        Ok(())
+    }
+}
+
+impl IResultReceiver for Binder<BnResultReceiver> {
+    fn send(&self, result_code: i32) -> crate::Result<()> {
+        self.0.send(result_code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BnResultReceiver, BnShellCallback, BpResultReceiver, BpShellCallback, IResultReceiver,
+        IShellCallback,
+    };
+    use crate::binder::{BinderFeatures, Interface};
+    use crate::error::{ExceptionCode, Status};
+    use crate::parcel::ParcelFileDescriptor;
+    use crate::proxy::Proxy;
+
+    use std::fs::File;
+    use std::io::Read;
+    use std::sync::mpsc::{channel, Sender};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    struct ShellCallback;
+
+    impl Interface for ShellCallback {}
+
+    impl IShellCallback for ShellCallback {
+        fn open_file(
+            &self,
+            path: &str,
+            _selinux_context: &str,
+            mode: &str,
+        ) -> crate::Result<Option<ParcelFileDescriptor>> {
+            match (path, mode) {
+                ("/proc/self/stat", "r") => {
+                    Ok(Some(ParcelFileDescriptor::new(File::open(path).unwrap())))
+                }
+                (_, "r") => Ok(None),
+                _ => Err(Status::new_exception(ExceptionCode::ILLEGAL_ARGUMENT, None)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_shell_callback_proxy() {
+        let service = BnShellCallback::new_binder(ShellCallback, BinderFeatures::default());
+        let proxy = BpShellCallback::from_binder(service.as_binder()).unwrap();
+
+        let file = proxy.open_file("/proc/self/stat", "", "r").unwrap();
+        let mut contents = String::new();
+        File::from(file.expect("File was not opened")).read_to_string(&mut contents).unwrap();
+        assert!(!contents.is_empty());
+
+        assert!(proxy.open_file("/proc/self/status", "", "r").unwrap().is_none());
+        assert_eq!(
+            proxy.open_file("/proc/self/stat", "", "w").unwrap_err().exception_code(),
+            ExceptionCode::ILLEGAL_ARGUMENT
+        );
+    }
+
+    struct ResultReceiver(Mutex<Sender<i32>>);
+
+    impl Interface for ResultReceiver {}
+
+    impl IResultReceiver for ResultReceiver {
+        fn send(&self, result_code: i32) -> crate::Result<()> {
+            self.0.lock().unwrap().send(result_code).unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_result_receiver_proxy() {
+        let (sender, receiver) = channel();
+        let service = BnResultReceiver::new_binder(
+            ResultReceiver(Mutex::new(sender)),
+            BinderFeatures::default(),
+        );
+        let proxy = BpResultReceiver::from_binder(service.as_binder()).unwrap();
+
+        proxy.send(42).unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(42));
+    }
+}
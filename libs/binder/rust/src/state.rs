@@ -14,9 +14,15 @@
  * limitations under the License.
  */
 
+use crate::binder::{TransactionFlags, FLAG_CLEAR_BUF, FLAG_ONEWAY};
+use crate::error::{status_result, Result};
 use crate::sys;
 
 use libc::{pid_t, uid_t};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::marker::PhantomData;
+use std::os::unix::io::{AsRawFd, RawFd};
 
 /// Static utility functions to manage Binder process state.
 pub struct ProcessState;
@@ -51,6 +57,57 @@ impl ProcessState {
     }
 }
 
+/// Handles incoming binder transactions on a thread which polls the binder
+/// driver, for processes that don't use the binder thread pool.
+///
+/// The file descriptor returned by [`as_raw_fd`](AsRawFd::as_raw_fd) becomes
+/// readable when there are commands to process, at which point
+/// [`handle_commands`](Self::handle_commands) should be called. This is
+/// expected to be used in a single threaded process which waits on events
+/// from multiple file descriptors, e.g. with `epoll`, and is not expected to be
+/// used together with [`ProcessState::start_thread_pool`] or
+/// [`ProcessState::join_thread_pool`].
+///
+/// Commands must be handled on the thread which created the poller, so it
+/// can't be sent to another thread.
+#[derive(Debug)]
+pub struct BinderPoller {
+    fd: RawFd,
+    // Polling is set up for the current thread only.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl BinderPoller {
+    /// Set up polling for binder commands on the current thread.
+    pub fn new() -> Result<Self> {
+        let mut fd = -1;
+        let status = unsafe {
+            // Safety: `fd` is a valid out pointer for the duration of the call.
+            sys::ABinderProcess_setupPolling(&mut fd)
+        };
+        status_result(status)?;
+        Ok(Self { fd, _not_send: PhantomData })
+    }
+
+    /// Handle all queued binder commands, including incoming transactions, and
+    /// return once there are none left.
+    pub fn handle_commands(&self) -> Result<()> {
+        let status = unsafe {
+            // Safety: Safe FFI. Polling was set up for this thread by `new`.
+            sys::ABinderProcess_handlePolledCommands()
+        };
+        status_result(status)
+    }
+}
+
+/// The file descriptor is owned by the process state, and stays open for the
+/// lifetime of the process.
+impl AsRawFd for BinderPoller {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
 /// Static utility functions to manage Binder thread state.
 pub struct ThreadState;
 
@@ -142,3 +199,212 @@ impl ThreadState {
         })
     }
 }
+
+/// The identity of the caller and the flags of an incoming transaction.
+///
+/// This is passed to
+/// [`Remotable::on_transact_with_context`](crate::binder_impl::Remotable::on_transact_with_context),
+/// and can also be captured with [`TransactionContext::current`]. Unlike the
+/// [`ThreadState`] functions, it stays valid after the transaction returns, so
+/// it can be moved into futures or other threads which handle the
+/// transaction.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TransactionContext {
+    calling_uid: uid_t,
+    calling_pid: pid_t,
+    calling_sid: Option<CString>,
+    flags: TransactionFlags,
+}
+
+thread_local! {
+    /// The context of the transaction which a local binder is handling on this
+    /// thread, if any.
+    static CURRENT_CONTEXT: RefCell<Option<TransactionContext>> = const { RefCell::new(None) };
+}
+
+impl TransactionContext {
+    /// Capture the context of a transaction which is being handled by the
+    /// current thread, which was sent with the given flags.
+    ///
+    /// The security context of the caller is only copied if the binder handling
+    /// the transaction requests it, since it isn't available otherwise.
+    pub(crate) fn new(flags: TransactionFlags, requesting_sid: bool) -> Self {
+        Self {
+            calling_uid: ThreadState::get_calling_uid(),
+            calling_pid: ThreadState::get_calling_pid(),
+            calling_sid: if requesting_sid {
+                ThreadState::with_calling_sid(|sid| sid.map(CStr::to_owned))
+            } else {
+                None
+            },
+            flags,
+        }
+    }
+
+    /// Capture the context of the transaction being handled by the current
+    /// thread.
+    ///
+    /// While a Rust service handles a transaction, this is the same context
+    /// that it is given, including the flags of the transaction. Otherwise
+    /// the flags aren't known and are reported as 0, and if the thread isn't
+    /// handling a transaction, the context describes the current process.
+    pub fn current() -> Self {
+        CURRENT_CONTEXT
+            .with(|current| current.borrow().clone())
+            .unwrap_or_else(|| Self::new(0, true))
+    }
+
+    /// Make this the context returned by [`TransactionContext::current`] on
+    /// this thread, until the returned guard is dropped.
+    pub(crate) fn enter(&self) -> ContextGuard {
+        let previous = CURRENT_CONTEXT.with(|current| current.replace(Some(self.clone())));
+        ContextGuard { previous }
+    }
+
+    /// Describe a transaction from the given caller, which need not be this
+    /// process.
+    #[cfg(test)]
+    pub(crate) fn from_caller(calling_uid: uid_t, calling_pid: pid_t) -> Self {
+        Self { calling_uid, calling_pid, calling_sid: None, flags: 0 }
+    }
+
+    /// The UID of the caller, see [`ThreadState::get_calling_uid`].
+    pub fn calling_uid(&self) -> uid_t {
+        self.calling_uid
+    }
+
+    /// The PID of the caller, see [`ThreadState::get_calling_pid`]. This is 0
+    /// for oneway transactions.
+    pub fn calling_pid(&self) -> pid_t {
+        self.calling_pid
+    }
+
+    /// The security context of the caller, see
+    /// [`ThreadState::with_calling_sid`].
+    ///
+    /// This is only available if the binder handling the transaction requests
+    /// it with [`BinderFeatures::set_requesting_sid`](crate::BinderFeatures).
+    pub fn calling_sid(&self) -> Option<&CStr> {
+        self.calling_sid.as_deref()
+    }
+
+    /// The flags the transaction was sent with.
+    pub fn flags(&self) -> TransactionFlags {
+        self.flags
+    }
+
+    /// Whether the transaction is oneway, i.e. the caller doesn't wait for it
+    /// to complete.
+    pub fn is_oneway(&self) -> bool {
+        self.flags & FLAG_ONEWAY != 0
+    }
+
+    /// Whether the caller asked for the transaction and its reply to be
+    /// cleared from the binder buffers once they are handled, because they
+    /// contain sensitive data.
+    pub fn is_clear_buf(&self) -> bool {
+        self.flags & FLAG_CLEAR_BUF != 0
+    }
+}
+
+/// Restores the previous current transaction context when dropped, so that
+/// nested transactions don't lose the context of the outer transaction.
+pub(crate) struct ContextGuard {
+    previous: Option<TransactionContext>,
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT_CONTEXT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TransactionContext;
+    use crate::binder::{
+        IBinderInternal, Interface, Remotable, TransactionCode, FIRST_CALL_TRANSACTION,
+        FLAG_CLEAR_BUF, FLAG_ONEWAY,
+    };
+    use crate::error::Result;
+    use crate::native::Binder;
+    use crate::parcel::BorrowedParcel;
+
+    use std::ffi::CStr;
+    use std::fs::File;
+    use std::sync::Mutex;
+
+    /// Records the context passed to the transaction, and the current context
+    /// while handling it.
+    struct ContextRecorder(Mutex<Option<(TransactionContext, TransactionContext)>>);
+
+    impl Remotable for ContextRecorder {
+        fn get_descriptor() -> &'static str {
+            "android.os.ContextRecorder"
+        }
+
+        fn on_transact(
+            &self,
+            _code: TransactionCode,
+            _data: &BorrowedParcel<'_>,
+            _reply: &mut BorrowedParcel<'_>,
+        ) -> Result<()> {
+            unreachable!("on_transact_with_context is overridden")
+        }
+
+        fn on_transact_with_context(
+            &self,
+            _code: TransactionCode,
+            _data: &BorrowedParcel<'_>,
+            _reply: &mut BorrowedParcel<'_>,
+            context: &TransactionContext,
+        ) -> Result<()> {
+            *self.0.lock().unwrap() = Some((context.clone(), TransactionContext::current()));
+            Ok(())
+        }
+
+        fn on_dump(&self, _file: &File, _args: &[&CStr]) -> Result<()> {
+            Ok(())
+        }
+
+        binder_fn_get_class!(Binder::<Self>);
+    }
+
+    #[test]
+    fn test_transaction_context() {
+        let recorder = Binder::new(ContextRecorder(Mutex::new(None)));
+        recorder
+            .as_binder()
+            .transact(FIRST_CALL_TRANSACTION, 0, |_| Ok(()))
+            .expect("Transaction failed");
+
+        let (context, current) =
+            recorder.0.lock().unwrap().take().expect("Context was not recorded");
+        assert_eq!(context, current);
+        assert_eq!(context, TransactionContext::current());
+        assert_eq!(context.calling_uid(), unsafe { libc::getuid() });
+        assert_eq!(context.calling_pid(), unsafe { libc::getpid() });
+        assert_eq!(context.calling_sid(), None);
+        assert!(!context.is_oneway());
+        assert!(!context.is_clear_buf());
+    }
+
+    #[test]
+    fn test_transaction_context_flags() {
+        let recorder = Binder::new(ContextRecorder(Mutex::new(None)));
+        recorder
+            .as_binder()
+            .transact(FIRST_CALL_TRANSACTION, FLAG_ONEWAY | FLAG_CLEAR_BUF, |_| Ok(()))
+            .expect("Transaction failed");
+
+        let (context, current) =
+            recorder.0.lock().unwrap().take().expect("Context was not recorded");
+        assert_eq!(context, current);
+        assert_eq!(context.flags(), FLAG_ONEWAY | FLAG_CLEAR_BUF);
+        assert!(context.is_oneway());
+        assert!(context.is_clear_buf());
+        // The context is only current while the transaction is handled.
+        assert_eq!(TransactionContext::current().flags(), 0);
+    }
+}
@@ -0,0 +1,56 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Fixtures shared by the unit tests.
+
+use crate::binder::{Remotable, TransactionCode};
+use crate::error::{Result, StatusCode};
+use crate::native::Binder;
+use crate::parcel::BorrowedParcel;
+
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::Write;
+
+/// A remotable object which replies with the `i32` it is sent, and writes its
+/// dump arguments when it is dumped. Transaction `FIRST_CALL_TRANSACTION` is
+/// named `echo`.
+pub(crate) struct Echo;
+
+impl Remotable for Echo {
+    fn get_descriptor() -> &'static str {
+        "android.os.EchoTest"
+    }
+
+    fn get_transaction_names() -> &'static [&'static str] {
+        &["echo"]
+    }
+
+    fn on_transact(
+        &self,
+        _code: TransactionCode,
+        data: &BorrowedParcel<'_>,
+        reply: &mut BorrowedParcel<'_>,
+    ) -> Result<()> {
+        reply.write(&data.read::<i32>()?)
+    }
+
+    fn on_dump(&self, mut file: &File, args: &[&CStr]) -> Result<()> {
+        writeln!(file, "echo {:?}", args).map_err(|_| StatusCode::UNKNOWN_ERROR)
+    }
+
+    binder_fn_get_class!(Binder::<Self>);
+}
@@ -0,0 +1,80 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The pool of helper threads which make calls with a timeout, see
+//! [`WithTimeout`](crate::WithTimeout).
+
+use crate::error::StatusCode;
+
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How long an idle helper thread waits for another call before it exits.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+struct PoolState {
+    /// Jobs waiting for an idle thread. There are never more of them than
+    /// idle threads.
+    queue: VecDeque<Job>,
+    /// Helper threads waiting for a job.
+    idle: usize,
+}
+
+static STATE: Mutex<PoolState> = Mutex::new(PoolState { queue: VecDeque::new(), idle: 0 });
+static JOB_QUEUED: Condvar = Condvar::new();
+
+/// Run `job` on a helper thread, starting a new one if none are idle.
+///
+/// This doesn't limit the number of threads itself; each
+/// [`WithTimeout`](crate::WithTimeout) limits the calls it has in progress.
+pub(crate) fn spawn(job: impl FnOnce() + Send + 'static) -> Result<(), StatusCode> {
+    let mut state = STATE.lock().unwrap();
+    if state.queue.len() < state.idle {
+        state.queue.push_back(Box::new(job));
+        JOB_QUEUED.notify_one();
+        return Ok(());
+    }
+    let job: Job = Box::new(job);
+    thread::Builder::new()
+        .name("binder_timeout".to_owned())
+        .spawn(move || run(job))
+        .map_err(|_| StatusCode::NO_MEMORY)?;
+    Ok(())
+}
+
+fn run(mut job: Job) {
+    loop {
+        // A panic only ends the job; the caller sees that its result was
+        // dropped.
+        let _ = panic::catch_unwind(AssertUnwindSafe(job));
+
+        let mut state = STATE.lock().unwrap();
+        state.idle += 1;
+        let (mut state, _) = JOB_QUEUED
+            .wait_timeout_while(state, IDLE_TIMEOUT, |state| state.queue.is_empty())
+            .unwrap();
+        state.idle -= 1;
+        match state.queue.pop_front() {
+            Some(next) => job = next,
+            None => return,
+        }
+    }
+}
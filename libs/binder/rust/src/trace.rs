@@ -0,0 +1,273 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Optional `tracing` spans for transactions.
+//!
+//! With the `tracing` feature enabled, every transaction sent by a proxy or
+//! handled by a local binder object runs inside a debug level
+//! `binder_transaction` span. Without it, these spans compile to nothing.
+//!
+//! On Android, the feature is enabled for `libbinder_rs` by the
+//! `libbinder_rs` soong config variable `tracing`, see `Android.bp`.
+
+use crate::binder::{InterfaceClass, TransactionCode, TransactionFlags};
+use crate::error::Result;
+use crate::state::TransactionContext;
+
+/// A span covering a single transaction.
+pub(crate) struct TransactionSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+#[cfg(feature = "tracing")]
+impl TransactionSpan {
+    /// Create the span for a transaction handled by a local binder object.
+    pub(crate) fn incoming(
+        descriptor: &str,
+        code: TransactionCode,
+        method: Option<&str>,
+        data_size: i32,
+        context: &TransactionContext,
+    ) -> Self {
+        let span = tracing::debug_span!(
+            "binder_transaction",
+            direction = "incoming",
+            descriptor,
+            code,
+            method,
+            oneway = context.is_oneway(),
+            data_size,
+            reply_size = tracing::field::Empty,
+            calling_uid = context.calling_uid(),
+            status = tracing::field::Empty,
+        );
+        Self { span }
+    }
+
+    /// Create the span for a transaction sent by a proxy. `class` is only
+    /// called if the span is enabled, to get the descriptor and the method
+    /// name of the transaction.
+    pub(crate) fn outgoing(
+        class: impl FnOnce() -> Option<InterfaceClass>,
+        code: TransactionCode,
+        flags: TransactionFlags,
+        data_size: i32,
+    ) -> Self {
+        let span = tracing::debug_span!(
+            "binder_transaction",
+            direction = "outgoing",
+            descriptor = tracing::field::Empty,
+            code,
+            method = tracing::field::Empty,
+            oneway = flags & crate::binder::FLAG_ONEWAY != 0,
+            data_size,
+            reply_size = tracing::field::Empty,
+            status = tracing::field::Empty,
+        );
+        if !span.is_disabled() {
+            if let Some(class) = class() {
+                span.record("descriptor", class.get_descriptor().as_str());
+                if let Some(method) = class.get_transaction_name(code) {
+                    span.record("method", method);
+                }
+            }
+        }
+        Self { span }
+    }
+
+    /// Run `f` inside the span.
+    pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.span.in_scope(f)
+    }
+
+    /// Record the size of the reply, if there is one, and the resulting
+    /// status.
+    pub(crate) fn record_result<T>(&self, reply_size: Option<i32>, result: &Result<T>) {
+        if let Some(reply_size) = reply_size {
+            self.span.record("reply_size", reply_size);
+        }
+        let status = match result {
+            Ok(_) => 0,
+            Err(e) => *e as i32,
+        };
+        self.span.record("status", status);
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl TransactionSpan {
+    pub(crate) fn incoming(
+        _descriptor: &str,
+        _code: TransactionCode,
+        _method: Option<&str>,
+        _data_size: i32,
+        _context: &TransactionContext,
+    ) -> Self {
+        Self {}
+    }
+
+    pub(crate) fn outgoing(
+        _class: impl FnOnce() -> Option<InterfaceClass>,
+        _code: TransactionCode,
+        _flags: TransactionFlags,
+        _data_size: i32,
+    ) -> Self {
+        Self {}
+    }
+
+    pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        f()
+    }
+
+    pub(crate) fn record_result<T>(&self, _reply_size: Option<i32>, _result: &Result<T>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TransactionSpan;
+    use crate::binder::{FIRST_CALL_TRANSACTION, FLAG_ONEWAY};
+    use crate::error::{Result, StatusCode};
+    use crate::state::TransactionContext;
+
+    #[test]
+    fn test_transaction_span() {
+        let context = TransactionContext::current();
+        let span =
+            TransactionSpan::incoming("test", FIRST_CALL_TRANSACTION, Some("test"), 4, &context);
+        assert_eq!(span.in_scope(|| 42), 42);
+        span.record_result::<()>(Some(8), &Ok(()));
+
+        let span = TransactionSpan::outgoing(|| None, FIRST_CALL_TRANSACTION, FLAG_ONEWAY, 4);
+        let result: Result<()> = span.in_scope(|| Err(StatusCode::DEAD_OBJECT));
+        span.record_result(None, &result);
+    }
+
+    #[cfg(feature = "tracing")]
+    mod capture {
+        use crate::binder::{
+            IBinderInternal, Interface, Remotable, FIRST_CALL_TRANSACTION, FLAG_ONEWAY,
+        };
+        use crate::error::StatusCode;
+        use crate::native::Binder;
+        use crate::test_utils::Echo;
+
+        use std::collections::HashMap;
+        use std::fmt;
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        type Fields = HashMap<&'static str, String>;
+
+        /// A subscriber which records the fields of every span.
+        struct SpanRecorder(Arc<Mutex<Vec<Fields>>>);
+
+        impl Subscriber for SpanRecorder {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut fields = Fields::new();
+                span.record(&mut FieldRecorder(&mut fields));
+                let mut spans = self.0.lock().unwrap();
+                spans.push(fields);
+                Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, span: &Id, values: &Record<'_>) {
+                let mut spans = self.0.lock().unwrap();
+                values.record(&mut FieldRecorder(&mut spans[span.into_u64() as usize - 1]));
+            }
+
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+            fn event(&self, _event: &Event<'_>) {}
+
+            fn enter(&self, _span: &Id) {}
+
+            fn exit(&self, _span: &Id) {}
+        }
+
+        struct FieldRecorder<'a>(&'a mut Fields);
+
+        impl Visit for FieldRecorder<'_> {
+            fn record_str(&mut self, field: &Field, value: &str) {
+                self.0.insert(field.name(), value.to_owned());
+            }
+
+            fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                self.0.insert(field.name(), format!("{:?}", value));
+            }
+        }
+
+        fn field<'a>(fields: &'a Fields, name: &str) -> Option<&'a str> {
+            fields.get(name).map(String::as_str)
+        }
+
+        #[test]
+        fn test_transaction_span_fields() {
+            let spans = Arc::new(Mutex::new(Vec::new()));
+            let binder = Binder::new(Echo).as_binder();
+            tracing::subscriber::with_default(SpanRecorder(spans.clone()), || {
+                binder
+                    .transact(FIRST_CALL_TRANSACTION, FLAG_ONEWAY, |mut data| data.write(&42i32))
+                    .expect("Transaction failed");
+                assert_eq!(
+                    binder.transact(FIRST_CALL_TRANSACTION, 0, |_| Ok(())).err(),
+                    Some(StatusCode::NOT_ENOUGH_DATA)
+                );
+            });
+
+            let spans = spans.lock().unwrap();
+            let uid = unsafe { libc::getuid() }.to_string();
+            let code = FIRST_CALL_TRANSACTION.to_string();
+            let [outgoing, incoming, failed_outgoing, failed_incoming] = &spans[..] else {
+                panic!("Expected 4 spans, got {:?}", spans);
+            };
+            for span in [outgoing, incoming, failed_outgoing, failed_incoming] {
+                assert_eq!(field(span, "descriptor"), Some(Echo::get_descriptor()));
+                assert_eq!(field(span, "code"), Some(code.as_str()));
+                assert_eq!(field(span, "method"), Some("echo"));
+            }
+
+            // The data starts with the interface token, so it is larger than
+            // the `i32`.
+            let data_size = field(outgoing, "data_size").expect("No data size").to_owned();
+            assert!(data_size.parse::<i32>().unwrap() > 4);
+
+            assert_eq!(field(outgoing, "direction"), Some("outgoing"));
+            assert_eq!(field(outgoing, "oneway"), Some("true"));
+            assert_eq!(field(outgoing, "status"), Some("0"));
+
+            assert_eq!(field(incoming, "direction"), Some("incoming"));
+            assert_eq!(field(incoming, "oneway"), Some("true"));
+            assert_eq!(field(incoming, "data_size"), Some(data_size.as_str()));
+            assert_eq!(field(incoming, "reply_size"), Some("4"));
+            assert_eq!(field(incoming, "calling_uid"), Some(uid.as_str()));
+            assert_eq!(field(incoming, "status"), Some("0"));
+
+            let not_enough_data = (StatusCode::NOT_ENOUGH_DATA as i32).to_string();
+            assert_eq!(field(failed_outgoing, "oneway"), Some("false"));
+            assert_eq!(field(failed_outgoing, "reply_size"), None);
+            assert_eq!(field(failed_outgoing, "status"), Some(not_enough_data.as_str()));
+            assert_eq!(field(failed_incoming, "oneway"), Some("false"));
+            assert_eq!(field(failed_incoming, "status"), Some(not_enough_data.as_str()));
+        }
+    }
+}
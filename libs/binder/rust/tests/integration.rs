@@ -17,11 +17,11 @@
 //! Rust Binder crate integration tests
 
 use binder::{declare_binder_enum, declare_binder_interface};
-use binder::{BinderFeatures, Interface, StatusCode, ThreadState};
+use binder::{BinderFeatures, Interface, SchedulerPolicy, StatusCode, ThreadState};
 // Import from internal API for testing only, do not use this module in
 // production.
 use binder::binder_impl::{
-    Binder, BorrowedParcel, IBinderInternal, TransactionCode, FIRST_CALL_TRANSACTION,
+    Binder, BorrowedParcel, IBinderInternal, Stability, TransactionCode, FIRST_CALL_TRANSACTION,
 };
 
 use std::convert::{TryFrom, TryInto};
@@ -34,6 +34,14 @@ use std::sync::Mutex;
 /// Must match the binary name in Android.bp
 const RUST_SERVICE_BINARY: &str = "rustBinderTestService";
 
+/// Environment variable which makes the service runner create its service with
+/// a realtime minimum scheduler policy and RT inheritance.
+const SCHEDULER_FEATURES_ENV: &str = "RUST_BINDER_TEST_SCHEDULER_FEATURES";
+
+/// Minimum realtime priority of services created with
+/// `SCHEDULER_FEATURES_ENV`.
+const SCHEDULER_MIN_PRIORITY: i32 = 1;
+
 /// Binary to run a test service.
 ///
 /// This needs to be in a separate process from the tests, so we spawn this
@@ -55,7 +63,22 @@ fn main() -> Result<(), &'static str> {
     let extension_name = args.next();
 
     {
-        let mut service = Binder::new(BnTest(Box::new(TestService::new(&service_name))));
+        let features = if std::env::var_os(SCHEDULER_FEATURES_ENV).is_some() {
+            BinderFeatures {
+                min_scheduler_policy: Some(
+                    SchedulerPolicy::fifo(SCHEDULER_MIN_PRIORITY).expect("Invalid priority"),
+                ),
+                inherit_rt: true,
+                ..BinderFeatures::default()
+            }
+        } else {
+            BinderFeatures::default()
+        };
+        let mut service = Binder::new_with_features(
+            BnTest(Box::new(TestService::new(&service_name))),
+            Stability::default(),
+            features,
+        );
         service.set_requesting_sid(true);
         if let Some(extension_name) = extension_name {
             let extension =
@@ -103,6 +126,7 @@ enum TestTransactionCode {
     GetDumpArgs,
     GetSelinuxContext,
     GetIsHandlingTransaction,
+    GetSchedulerPolicy,
 }
 
 impl TryFrom<u32> for TestTransactionCode {
@@ -116,6 +140,9 @@ impl TryFrom<u32> for TestTransactionCode {
                 Ok(TestTransactionCode::GetSelinuxContext)
             }
             _ if c == TestTransactionCode::GetIsHandlingTransaction as u32 => Ok(TestTransactionCode::GetIsHandlingTransaction),
+            _ if c == TestTransactionCode::GetSchedulerPolicy as u32 => {
+                Ok(TestTransactionCode::GetSchedulerPolicy)
+            }
             _ => Err(StatusCode::UNKNOWN_TRANSACTION),
         }
     }
@@ -148,6 +175,18 @@ impl ITest for TestService {
     fn get_is_handling_transaction(&self) -> Result<bool, StatusCode> {
         Ok(binder::is_handling_transaction())
     }
+
+    fn get_scheduler_policy(&self) -> Result<Vec<i32>, StatusCode> {
+        let mut param = libc::sched_param { sched_priority: 0 };
+        // Safety: `sched_getscheduler` and `sched_getparam` have no safety
+        // requirements for the current thread, and `param` outlives the call.
+        let (policy, status) =
+            unsafe { (libc::sched_getscheduler(0), libc::sched_getparam(0, &mut param)) };
+        if policy < 0 || status < 0 {
+            return Err(StatusCode::UNKNOWN_ERROR);
+        }
+        Ok(vec![policy & !libc::SCHED_RESET_ON_FORK, param.sched_priority])
+    }
 }
 
 /// Trivial testing binder interface
@@ -163,6 +202,10 @@ pub trait ITest: Interface {
 
     /// Returns the value of calling `is_handling_transaction`.
     fn get_is_handling_transaction(&self) -> Result<bool, StatusCode>;
+
+    /// Returns the scheduler policy and priority of the thread handling the
+    /// transaction.
+    fn get_scheduler_policy(&self) -> Result<Vec<i32>, StatusCode>;
 }
 
 /// Async trivial testing binder interface
@@ -187,6 +230,13 @@ declare_binder_interface! {
             x: i32 = 100
         },
         async: IATest,
+        transaction_names: [
+            "test",
+            "getDumpArgs",
+            "getSelinuxContext",
+            "getIsHandlingTransaction",
+            "getSchedulerPolicy",
+        ],
     }
 }
 
@@ -201,6 +251,7 @@ fn on_transact(
         TestTransactionCode::GetDumpArgs => reply.write(&service.get_dump_args()?),
         TestTransactionCode::GetSelinuxContext => reply.write(&service.get_selinux_context()?),
         TestTransactionCode::GetIsHandlingTransaction => reply.write(&service.get_is_handling_transaction()?),
+        TestTransactionCode::GetSchedulerPolicy => reply.write(&service.get_scheduler_policy()?),
     }
 }
 
@@ -236,6 +287,15 @@ impl ITest for BpTest {
         )?;
         reply.read()
     }
+
+    fn get_scheduler_policy(&self) -> Result<Vec<i32>, StatusCode> {
+        let reply = self.binder.transact(
+            TestTransactionCode::GetSchedulerPolicy as TransactionCode,
+            0,
+            |_| Ok(()),
+        )?;
+        reply.read()
+    }
 }
 
 impl<P: binder::BinderAsyncPool> IATest<P> for BpTest {
@@ -288,6 +348,10 @@ impl ITest for Binder<BnTest> {
     fn get_is_handling_transaction(&self) -> Result<bool, StatusCode> {
         self.0.get_is_handling_transaction()
     }
+
+    fn get_scheduler_policy(&self) -> Result<Vec<i32>, StatusCode> {
+        self.0.get_scheduler_policy()
+    }
 }
 
 impl<P: binder::BinderAsyncPool> IATest<P> for Binder<BnTest> {
@@ -312,6 +376,48 @@ impl<P: binder::BinderAsyncPool> IATest<P> for Binder<BnTest> {
     }
 }
 
+/// Binder interface defined with the `binder::interface` attribute
+#[binder::interface("android.os.IMacroTest", async = IAMacroTest)]
+pub trait IMacroTest {
+    /// Returns the given string
+    fn echo(&self, s: &str) -> Result<String, StatusCode>;
+
+    /// Returns the sum of the given values
+    fn sum(&self, values: &[i64], extra: i64) -> Result<i64, StatusCode>;
+
+    /// Stores a value
+    #[oneway]
+    fn set(&self, value: i32) -> Result<(), StatusCode>;
+
+    /// Returns the stored value
+    fn get(&self) -> Result<i32, StatusCode>;
+}
+
+/// Implementation of `IMacroTest`
+#[derive(Default)]
+pub struct MacroTestService(Mutex<i32>);
+
+impl Interface for MacroTestService {}
+
+impl IMacroTest for MacroTestService {
+    fn echo(&self, s: &str) -> Result<String, StatusCode> {
+        Ok(s.to_string())
+    }
+
+    fn sum(&self, values: &[i64], extra: i64) -> Result<i64, StatusCode> {
+        Ok(values.iter().sum::<i64>() + extra)
+    }
+
+    fn set(&self, value: i32) -> Result<(), StatusCode> {
+        *self.0.lock().unwrap() = value;
+        Ok(())
+    }
+
+    fn get(&self) -> Result<i32, StatusCode> {
+        Ok(*self.0.lock().unwrap())
+    }
+}
+
 /// Trivial testing binder interface
 pub trait ITestSameDescriptor: Interface {}
 
@@ -365,29 +471,36 @@ mod tests {
     use std::time::Duration;
 
     use binder::{
-        BinderFeatures, DeathRecipient, FromIBinder, IBinder, Interface, SpIBinder, StatusCode,
-        Strong,
+        BinderFeatures, DeathRecipient, FromIBinder, IBinder, Interface, SchedulerPolicy,
+        SpIBinder, StatusCode, Strong,
     };
     // Import from impl API for testing only, should not be necessary as long as
     // you are using AIDL.
-    use binder::binder_impl::{Binder, IBinderInternal, TransactionCode};
+    use binder::binder_impl::{Binder, IBinderInternal, Parcel, Stability, TransactionCode};
 
     use binder_tokio::Tokio;
 
-    use super::{BnTest, ITest, IATest, ITestSameDescriptor, TestService, RUST_SERVICE_BINARY};
+    use super::{
+        BnTest, ITest, IATest, ITestSameDescriptor, TestService, RUST_SERVICE_BINARY,
+        SCHEDULER_FEATURES_ENV, SCHEDULER_MIN_PRIORITY,
+    };
 
     pub struct ScopedServiceProcess(Child);
 
     impl ScopedServiceProcess {
         pub fn new(identifier: &str) -> Self {
-            Self::new_internal(identifier, None)
+            Self::new_internal(identifier, None, false)
         }
 
         pub fn new_with_extension(identifier: &str, extension: &str) -> Self {
-            Self::new_internal(identifier, Some(extension))
+            Self::new_internal(identifier, Some(extension), false)
+        }
+
+        pub fn new_with_scheduler_features(identifier: &str) -> Self {
+            Self::new_internal(identifier, None, true)
         }
 
-        fn new_internal(identifier: &str, extension: Option<&str>) -> Self {
+        fn new_internal(identifier: &str, extension: Option<&str>, scheduler: bool) -> Self {
             let mut binary_path =
                 std::env::current_exe().expect("Could not retrieve current executable path");
             binary_path.pop();
@@ -397,6 +510,9 @@ mod tests {
             if let Some(ext) = extension {
                 command.arg(ext);
             }
+            if scheduler {
+                command.env(SCHEDULER_FEATURES_ENV, "1");
+            }
             let child = command.spawn().expect("Could not start service");
             Self(child)
         }
@@ -498,6 +614,138 @@ mod tests {
         assert_eq!(expected_defaults, instances.iter().filter(|i| i.as_str() == "default").count());
     }
 
+    #[test]
+    fn service_manager_queries() {
+        use binder::service_manager::{self, DUMP_FLAG_PRIORITY_ALL};
+
+        assert!(service_manager::check_service("manager").is_some());
+        assert!(service_manager::check_service("this_service_does_not_exist").is_none());
+
+        let services =
+            service_manager::list_services(DUMP_FLAG_PRIORITY_ALL).expect("Could not list services");
+        assert!(services.iter().any(|name| name == "manager"));
+
+        let debug_info =
+            service_manager::get_service_debug_info().expect("Could not get service debug info");
+        assert!(debug_info.iter().any(|info| info.name == "manager" && info.debug_pid > 0));
+
+        assert_eq!(
+            service_manager::get_connection_info("manager").expect("Could not get connection info"),
+            None
+        );
+        assert!(!service_manager::is_updatable_via_apex("manager")
+            .expect("Could not check whether the service is updatable"));
+    }
+
+    #[test]
+    fn add_service_with_options() {
+        use binder::service_manager::{self, AddServiceOptions, DUMP_FLAG_PRIORITY_HIGH};
+
+        let service_name = "add_service_with_options_test";
+        let service = BnTest::new_binder(TestService::new(service_name), BinderFeatures::default());
+        let options = AddServiceOptions {
+            dump_priority: DUMP_FLAG_PRIORITY_HIGH,
+            ..AddServiceOptions::default()
+        };
+        service_manager::add_service_with_options(service_name, service.as_binder(), &options)
+            .expect("Could not register service");
+
+        let high = service_manager::list_services(DUMP_FLAG_PRIORITY_HIGH)
+            .expect("Could not list services");
+        assert!(high.iter().any(|name| name == service_name));
+        let critical = service_manager::list_services(service_manager::DUMP_FLAG_PRIORITY_CRITICAL)
+            .expect("Could not list services");
+        assert!(!critical.iter().any(|name| name == service_name));
+    }
+
+    #[test]
+    fn lazy_service_unregister() {
+        use binder::service_manager::{self, DUMP_FLAG_PRIORITY_ALL};
+
+        struct SetOnDrop(Arc<AtomicBool>);
+
+        impl Drop for SetOnDrop {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::Relaxed);
+            }
+        }
+
+        // Registering a callback replaces and drops the previous one.
+        let dropped = Arc::new(AtomicBool::new(false));
+        let guard = SetOnDrop(dropped.clone());
+        binder::set_active_services_callback(move |_| {
+            let _ = &guard;
+            true
+        });
+        assert!(!dropped.load(Ordering::Relaxed));
+        // Keep this process running when the service has no clients.
+        binder::set_active_services_callback(|_| true);
+        assert!(dropped.load(Ordering::Relaxed));
+
+        let service_name = "lazy_service_unregister_test";
+        let is_registered = || {
+            service_manager::list_services(DUMP_FLAG_PRIORITY_ALL)
+                .expect("Could not list services")
+                .iter()
+                .any(|name| name == service_name)
+        };
+        let service = BnTest::new_binder(TestService::new(service_name), BinderFeatures::default());
+        binder::register_lazy_service(service_name, service.as_binder())
+            .expect("Could not register lazy service");
+        assert!(is_registered());
+
+        // Nobody got the service, so it has no clients and can be unregistered.
+        assert!(binder::try_unregister());
+        assert!(!is_registered());
+
+        binder::re_register();
+        assert!(is_registered());
+    }
+
+    #[test]
+    fn service_notifications() {
+        binder::ProcessState::start_thread_pool();
+
+        let service_name = "service_notifications_test";
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let registration =
+            binder::service_manager::register_for_notifications(service_name, move |name, binder| {
+                sender.send((name.to_string(), binder)).unwrap();
+            })
+            .expect("Could not register for notifications");
+        assert!(receiver.try_recv().is_err());
+
+        let service = BnTest::new_binder(TestService::new(service_name), BinderFeatures::default());
+        binder::add_service(service_name, service.as_binder()).expect("Could not register service");
+
+        let (name, binder) =
+            receiver.recv_timeout(Duration::from_secs(5)).expect("Did not get a notification");
+        assert_eq!(name, service_name);
+        assert_eq!(binder, service.as_binder());
+
+        registration.unregister().expect("Could not unregister for notifications");
+    }
+
+    #[tokio::test]
+    async fn service_notifications_async() {
+        use tokio_stream::StreamExt;
+
+        binder::ProcessState::start_thread_pool();
+
+        // The service is already registered, so the stream yields it first.
+        let service_name = "service_notifications_async_test";
+        let service = BnTest::new_binder(TestService::new(service_name), BinderFeatures::default());
+        binder::add_service(service_name, service.as_binder()).expect("Could not register service");
+
+        let mut notifications = binder_tokio::service_notifications(service_name)
+            .await
+            .expect("Could not register for notifications");
+        let binder = tokio::time::timeout(Duration::from_secs(5), notifications.next())
+            .await
+            .expect("Did not get a notification");
+        assert_eq!(binder, Some(service.as_binder()));
+    }
+
     #[test]
     fn trivial_client() {
         let service_name = "trivial_client_test";
@@ -525,6 +773,27 @@ mod tests {
         assert_eq!(test_client.test().unwrap(), "wait_for_trivial_client_test");
     }
 
+    #[test]
+    #[cfg(not(android_vndk))]
+    fn well_known_transactions() {
+        let service_name = "well_known_transactions_test";
+        let process = ScopedServiceProcess::new(service_name);
+        let mut remote = binder::get_service(service_name).expect("Could not retrieve service");
+
+        assert_eq!(remote.get_remote_descriptor().unwrap(), "android.os.ITest");
+        assert_eq!(remote.get_debug_pid().unwrap(), process.0.id() as i32);
+        remote.sysprops_changed().expect("Could not notify the service of sysprops changes");
+
+        let mut local = BnTest::new_binder(
+            TestService::new("well_known_transactions_local"),
+            BinderFeatures::default(),
+        )
+        .as_binder();
+        assert_eq!(local.get_remote_descriptor().unwrap(), "android.os.ITest");
+        assert_eq!(local.get_debug_pid().unwrap(), std::process::id() as i32);
+        local.sysprops_changed().expect("Could not notify this process of sysprops changes");
+    }
+
     #[tokio::test]
     async fn wait_for_trivial_client_async() {
         let service_name = "wait_for_trivial_client_test";
@@ -754,6 +1023,181 @@ mod tests {
         bools.assert_dropped();
     }
 
+    /// A death link should deliver death notifications until it is dropped.
+    #[test]
+    fn test_death_link() {
+        binder::ProcessState::start_thread_pool();
+
+        let service_name = "test_death_link";
+        let service_process = ScopedServiceProcess::new(service_name);
+        let remote = binder::get_service(service_name).expect("Could not retrieve service");
+
+        let died = Arc::new(AtomicBool::new(false));
+        let link = {
+            let died = died.clone();
+            remote
+                .watch_death(move || died.store(true, Ordering::Relaxed))
+                .expect("watch_death failed")
+        };
+        let unlinked = remote.watch_death(|| panic!("Unlinked death link was called"));
+        drop(unlinked);
+
+        drop(service_process);
+
+        // Pause to ensure any death notifications get delivered
+        thread::sleep(Duration::from_secs(1));
+
+        assert!(died.load(Ordering::Relaxed), "Did not receive death notification");
+        drop(link);
+        assert_eq!(
+            remote.watch_death(|| {}).err(),
+            Some(StatusCode::DEAD_OBJECT),
+            "Linked to a dead binder"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_death_async() {
+        binder::ProcessState::start_thread_pool();
+
+        let service_name = "test_death_async";
+        let service_process = ScopedServiceProcess::new(service_name);
+        let remote = binder::get_service(service_name).expect("Could not retrieve service");
+
+        let death = binder_tokio::death(&remote);
+        drop(service_process);
+        tokio::time::timeout(Duration::from_secs(10), death)
+            .await
+            .expect("Did not receive death notification");
+
+        // The process is already dead, so this completes immediately.
+        binder_tokio::death(&remote).await;
+    }
+
+    #[test]
+    fn test_service_handle() {
+        binder::ProcessState::start_thread_pool();
+
+        let service_name = "test_service_handle";
+        let handle = binder::ServiceHandle::<dyn ITest>::new(service_name);
+        assert_eq!(handle.name(), service_name);
+
+        let service_process = ScopedServiceProcess::new(service_name);
+        assert_eq!(handle.call(|service| Ok(service.test()?)).as_deref(), Ok(service_name));
+        let first = handle.get().expect("Could not retrieve service");
+
+        drop(service_process);
+        // Pause to ensure any death notifications get delivered
+        thread::sleep(Duration::from_secs(1));
+
+        let _process = ScopedServiceProcess::new(service_name);
+        assert_eq!(
+            handle.call_idempotent(|service| Ok(service.test()?)).as_deref(),
+            Ok(service_name)
+        );
+        let second = handle.get().expect("Could not retrieve service");
+        assert_ne!(first.as_binder(), second.as_binder());
+
+        // Threads which reconnect at the same time all end up with the
+        // connection which was installed first.
+        drop(_process);
+        thread::sleep(Duration::from_secs(1));
+        let _process = ScopedServiceProcess::new(service_name);
+        let services: Vec<_> = thread::scope(|scope| {
+            let threads: Vec<_> = (0..4).map(|_| scope.spawn(|| handle.get())).collect();
+            threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+        });
+        let third = handle.get().expect("Could not retrieve service");
+        assert_ne!(second.as_binder(), third.as_binder());
+        for service in services {
+            assert_eq!(service.expect("Could not retrieve service").as_binder(), third.as_binder());
+        }
+    }
+
+    #[test]
+    fn test_with_timeout() {
+        let service_name = "test_with_timeout";
+        let _process = ScopedServiceProcess::new(service_name);
+        let test_client: Strong<dyn ITest> =
+            binder::get_interface(service_name).expect("Did not get test binder service");
+
+        let client = test_client.with_timeout(Duration::from_secs(5));
+        assert_eq!(client.timeout(), Duration::from_secs(5));
+        assert_eq!(client.call(|client| client.test()).as_deref(), Ok(service_name));
+
+        let client = test_client.with_timeout(Duration::from_millis(100));
+        let result = client.call(|client| {
+            thread::sleep(Duration::from_secs(1));
+            client.test()
+        });
+        assert_eq!(result, Err(StatusCode::TIMED_OUT));
+    }
+
+    #[test]
+    fn test_with_timeout_hung_remote() {
+        let service_name = "test_with_timeout_hung_remote";
+        let process = ScopedServiceProcess::new(service_name);
+        let test_client: Strong<dyn ITest> =
+            binder::get_interface(service_name).expect("Did not get test binder service");
+        let client = test_client.with_timeout(Duration::from_millis(100)).with_max_calls(2);
+        assert_eq!(client.max_calls(), 2);
+        assert_eq!(client.call(|client| client.test()).as_deref(), Ok(service_name));
+
+        // A stopped process doesn't handle transactions, so calls to it hang
+        // until it is continued.
+        let pid = process.0.id() as libc::pid_t;
+        assert_eq!(unsafe { libc::kill(pid, libc::SIGSTOP) }, 0);
+
+        // Every timed out call stays in progress, until the handle and its
+        // clones can't make any more.
+        for _ in 0..client.max_calls() {
+            assert_eq!(client.call(|client| client.test()), Err(StatusCode::TIMED_OUT));
+        }
+        assert_eq!(client.clone().call(|client| client.test()), Err(StatusCode::WOULD_BLOCK));
+
+        // Other handles have their own limit.
+        let local = BnTest::new_binder(
+            TestService::new("test_with_timeout_hung_remote_local"),
+            BinderFeatures::default(),
+        );
+        let local_client = local.with_timeout(Duration::from_secs(5));
+        assert_eq!(
+            local_client.call(|client| client.test()).as_deref(),
+            Ok("test_with_timeout_hung_remote_local")
+        );
+
+        assert_eq!(unsafe { libc::kill(pid, libc::SIGCONT) }, 0);
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        loop {
+            // The calls finish once the late replies arrive.
+            match client.call(|client| client.test()) {
+                Err(StatusCode::WOULD_BLOCK | StatusCode::TIMED_OUT) if std::time::Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(10))
+                }
+                result => {
+                    assert_eq!(result.as_deref(), Ok(service_name));
+                    break;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timeout_async() {
+        let service_name = "test_timeout_async";
+        let _process = ScopedServiceProcess::new(service_name);
+        let test_client: Strong<dyn IATest<Tokio>> = binder_tokio::get_interface(service_name)
+            .await
+            .expect("Did not get test binder service");
+
+        let result = binder_tokio::timeout(Duration::from_secs(5), test_client.test()).await;
+        assert_eq!(result.as_deref(), Ok(service_name));
+
+        let result: Result<(), StatusCode> =
+            binder_tokio::timeout(Duration::from_millis(100), std::future::pending()).await;
+        assert_eq!(result, Err(StatusCode::TIMED_OUT));
+    }
+
     /// Test IBinder interface methods not exercised elsewhere.
     #[test]
     fn test_misc_ibinder() {
@@ -821,9 +1265,117 @@ mod tests {
                 .expect("Extension could not be converted to the expected interface");
 
             assert_eq!(extension.test().unwrap(), extension_name);
+
+            let remote: Strong<dyn ITest> =
+                binder::get_interface(service_name).expect("Could not get service");
+            let extension = remote
+                .get_extension::<dyn ITest>()
+                .expect("Extension could not be converted to the expected interface")
+                .expect("Remote binder did not have an extension");
+            assert_eq!(extension.test().unwrap(), extension_name);
         }
     }
 
+    #[test]
+    fn test_extension_features() {
+        let extension = BnTest::new_binder(
+            TestService::new("test_extension_features_ext"),
+            BinderFeatures::default(),
+        );
+        let service = BnTest::new_binder(
+            TestService::new("test_extension_features"),
+            BinderFeatures { extension: Some(extension.as_binder()), ..BinderFeatures::default() },
+        );
+
+        let extension = service
+            .get_extension::<dyn ITest>()
+            .expect("Extension could not be converted to the expected interface")
+            .expect("Local binder did not have an extension");
+        assert_eq!(extension.test().unwrap(), "test_extension_features_ext");
+
+        let no_extension =
+            extension.get_extension::<dyn ITest>().expect("Could not check for an extension");
+        assert!(no_extension.is_none());
+    }
+
+    #[test]
+    fn test_stability_downgrade() {
+        let service = Binder::new_with_stability(
+            BnTest(Box::new(TestService::new("test_stability_downgrade"))),
+            Stability::Vintf,
+        );
+
+        let mut binder = service.as_binder();
+        binder.force_downgrade_to_local_stability();
+        let service: Strong<dyn ITest> =
+            binder.into_interface().expect("Could not convert downgraded binder");
+        assert_eq!(service.test().unwrap(), "test_stability_downgrade");
+    }
+
+    #[test]
+    fn test_compilation_unit_stability() {
+        let service = Binder::new(BnTest(Box::new(TestService::new(
+            "test_compilation_unit_stability",
+        ))));
+
+        // Binder objects already have the local stability, so marking them
+        // again is allowed.
+        let mut binder = service.as_binder();
+        binder.mark_compilation_unit_stability();
+        let service: Strong<dyn ITest> =
+            binder.into_interface().expect("Could not convert marked binder");
+        assert_eq!(service.test().unwrap(), "test_compilation_unit_stability");
+    }
+
+    /// Set the scheduler policy and realtime priority of the current thread.
+    fn set_current_scheduler(policy: i32, priority: i32) {
+        let param = libc::sched_param { sched_priority: priority };
+        // Safety: `param` outlives the call, which only changes the scheduler
+        // of the current thread.
+        let status = unsafe { libc::sched_setscheduler(0, policy, &param) };
+        assert_eq!(status, 0, "Could not set scheduler policy");
+    }
+
+    #[test]
+    fn test_scheduler_features() {
+        let service_name = "test_scheduler_features";
+        let _process = ScopedServiceProcess::new_with_scheduler_features(service_name);
+        let test_client: Strong<dyn ITest> =
+            binder::get_interface(service_name).expect("Did not get test binder service");
+
+        // A normal caller is raised to the minimum policy of the service.
+        set_current_scheduler(libc::SCHED_OTHER, 0);
+        assert_eq!(
+            test_client.get_scheduler_policy().unwrap(),
+            [libc::SCHED_FIFO, SCHEDULER_MIN_PRIORITY]
+        );
+
+        // A realtime caller with a higher priority is inherited.
+        set_current_scheduler(libc::SCHED_FIFO, SCHEDULER_MIN_PRIORITY + 1);
+        let policy = test_client.get_scheduler_policy();
+        set_current_scheduler(libc::SCHED_OTHER, 0);
+        assert_eq!(policy.unwrap(), [libc::SCHED_FIFO, SCHEDULER_MIN_PRIORITY + 1]);
+    }
+
+    #[test]
+    fn test_invalid_scheduler_policy() {
+        assert_eq!(SchedulerPolicy::normal(20), Err(StatusCode::BAD_VALUE));
+        assert_eq!(SchedulerPolicy::fifo(0), Err(StatusCode::BAD_VALUE));
+        assert_eq!(SchedulerPolicy::round_robin(100), Err(StatusCode::BAD_VALUE));
+
+        let policy = SchedulerPolicy::round_robin(99).unwrap();
+        assert_eq!((policy.policy(), policy.priority()), (libc::SCHED_RR, 99));
+
+        // Valid policies are applied without aborting.
+        BnTest::new_binder(
+            TestService::new("test_invalid_scheduler_policy"),
+            BinderFeatures {
+                min_scheduler_policy: Some(SchedulerPolicy::normal(-20).unwrap()),
+                ..BinderFeatures::default()
+            },
+        );
+    }
+
     /// Test re-associating a local binder object with a different class.
     ///
     /// This is needed because different binder service (e.g. NDK vs Rust)
@@ -992,4 +1544,85 @@ mod tests {
             assert!(!binder::is_handling_transaction());
         }).await.unwrap();
     }
+
+    #[test]
+    fn test_transaction_names() {
+        use super::BnTestSameDescriptor;
+        use binder::binder_impl::{Remotable, FIRST_CALL_TRANSACTION};
+
+        assert_eq!(BnTest::get_transaction_name(FIRST_CALL_TRANSACTION), Some("test"));
+        assert_eq!(
+            BnTest::get_transaction_name(FIRST_CALL_TRANSACTION + 3),
+            Some("getIsHandlingTransaction")
+        );
+        assert_eq!(
+            BnTest::get_transaction_name(FIRST_CALL_TRANSACTION + 4),
+            Some("getSchedulerPolicy")
+        );
+        assert_eq!(BnTest::get_transaction_name(FIRST_CALL_TRANSACTION + 5), None);
+        assert_eq!(BnTest::get_transaction_name(0), None);
+        assert_eq!(BnTestSameDescriptor::get_transaction_name(FIRST_CALL_TRANSACTION), None);
+    }
+
+    #[test]
+    fn macro_interface() {
+        use super::{BnMacroTest, BpMacroTest, IMacroTest, MacroTestService};
+        use binder::binder_impl::{Proxy, Remotable};
+
+        assert_eq!(BnMacroTest::TRANSACTION_ECHO, binder::binder_impl::FIRST_CALL_TRANSACTION);
+        assert_eq!(BnMacroTest::TRANSACTION_GET, binder::binder_impl::FIRST_CALL_TRANSACTION + 3);
+        assert_eq!(BnMacroTest::get_transaction_names(), ["echo", "sum", "set", "get"]);
+
+        let service = BnMacroTest::new_binder(MacroTestService::default(), BinderFeatures::default());
+        // Go through the proxy even though the service is local, so that the
+        // arguments are sent through a parcel and dispatched by `on_transact`.
+        let proxy = BpMacroTest::from_binder(service.as_binder()).unwrap();
+        assert_eq!(proxy.echo("hello"), Ok("hello".to_string()));
+        assert_eq!(proxy.sum(&[1, 2, 3], 4), Ok(10));
+        assert_eq!(proxy.set(42), Ok(()));
+        assert_eq!(proxy.get(), Ok(42));
+
+        let async_proxy: &dyn super::IAMacroTest<Tokio> = &proxy;
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        assert_eq!(runtime.block_on(async_proxy.echo("async")), Ok("async".to_string()));
+    }
+
+    #[derive(Debug, Default, PartialEq, binder::Parcelable, binder::Serialize, binder::Deserialize)]
+    struct DerivedParcelableV1 {
+        id: i32,
+    }
+
+    #[derive(Debug, Default, PartialEq, binder::Parcelable, binder::Serialize, binder::Deserialize)]
+    struct DerivedParcelableV2 {
+        id: i32,
+        #[parcelable(nullable)]
+        name: Option<String>,
+        #[parcelable(default = vec![1, 2])]
+        values: Vec<i64>,
+    }
+
+    #[test]
+    fn derived_parcelable() {
+        let value = DerivedParcelableV2 { id: 42, name: Some("name".into()), values: vec![3] };
+        let null_name = DerivedParcelableV2 { id: 43, name: None, values: vec![] };
+        let mut parcel = Parcel::new();
+        parcel.write(&value).unwrap();
+        parcel.write(&null_name).unwrap();
+        unsafe {
+            parcel.set_data_position(0).unwrap();
+        }
+        assert_eq!(parcel.read::<DerivedParcelableV2>(), Ok(value));
+        assert_eq!(parcel.read::<DerivedParcelableV2>(), Ok(null_name));
+
+        // Fields which an older sender doesn't know about get their defaults.
+        let mut parcel = Parcel::new();
+        parcel.write(&DerivedParcelableV1 { id: 7 }).unwrap();
+        unsafe {
+            parcel.set_data_position(0).unwrap();
+        }
+        assert_eq!(
+            parcel.read::<DerivedParcelableV2>(),
+            Ok(DerivedParcelableV2 { id: 7, name: None, values: vec![1, 2] })
+        );
+    }
 }
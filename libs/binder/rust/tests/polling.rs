@@ -0,0 +1,76 @@
+/*
+ * Copyright (C) 2022 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Integration test of polling for binder commands.
+//!
+//! This runs in its own process, which never starts the binder thread pool, so
+//! that incoming transactions can only be handled by the polling thread.
+
+use binder::binder_impl::Binder;
+use binder::{service_manager, BinderPoller, Interface};
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn handle_polled_commands() {
+    let poller = BinderPoller::new().expect("Could not set up polling");
+
+    let service_name = "binder_poller_test";
+    let (sender, receiver) = mpsc::channel();
+    let _registration =
+        service_manager::register_for_notifications(service_name, move |name, _| {
+            sender.send((name.to_owned(), thread::current().id())).unwrap();
+        })
+        .expect("Could not register for notifications");
+
+    // Registering the service makes the service manager send a oneway
+    // transaction to the notification callback, which is a local binder of
+    // this process. Calls within a process don't go through the driver, so
+    // the transaction has to come from the service manager.
+    let service = Binder::new(()).as_binder();
+    thread::spawn(move || binder::add_service(service_name, service))
+        .join()
+        .unwrap()
+        .expect("Could not register service");
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let notification = loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        assert!(!timeout.is_zero(), "Did not get a notification");
+
+        let mut fds = libc::pollfd { fd: poller.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        let ready = unsafe {
+            // Safety: `fds` is a valid array of one `pollfd` for the duration
+            // of the call.
+            libc::poll(&mut fds, 1, timeout.as_millis() as libc::c_int)
+        };
+        assert!(ready >= 0, "Could not poll the binder file descriptor");
+        if ready == 0 {
+            continue;
+        }
+        assert_ne!(fds.revents & libc::POLLIN, 0);
+        // Nothing handles the transaction until this thread does.
+        assert!(receiver.try_recv().is_err());
+
+        poller.handle_commands().expect("Could not handle binder commands");
+        if let Ok(notification) = receiver.try_recv() {
+            break notification;
+        }
+    };
+    assert_eq!(notification, (service_name.to_owned(), thread::current().id()));
+}